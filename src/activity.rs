@@ -1,8 +1,168 @@
-#[derive(Debug, PartialEq, Copy, Clone)]
+use chrono::Duration;
+
+use crate::{event::EventKind, rotation::Rotation};
+
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
 pub(crate) enum Activity {
     TakePills,
     I,
     WaterPlants,
-    TakePillsReminder,
     CleanLitterTray,
+    // Follow-ups without a button, they just send a reminder
+    Eat,
+    CheckDrainageTrays,
+}
+
+impl Activity {
+    pub(crate) const ALL: [Activity; 6] = [
+        Activity::TakePills,
+        Activity::I,
+        Activity::WaterPlants,
+        Activity::CleanLitterTray,
+        Activity::Eat,
+        Activity::CheckDrainageTrays,
+    ];
+
+    /// Identifies the activity in the database, so must never change.
+    pub(crate) fn id(self) -> &'static str {
+        match self {
+            Activity::TakePills => "take_pills",
+            Activity::I => "i",
+            Activity::WaterPlants => "water_plants",
+            Activity::CleanLitterTray => "clean_litter_tray",
+            Activity::Eat => "eat",
+            Activity::CheckDrainageTrays => "check_drainage_trays",
+        }
+    }
+
+    /// How it's shown to people, e.g. in emails.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Activity::TakePills => "Take pills",
+            Activity::I => "I",
+            Activity::WaterPlants => "Water plants",
+            Activity::CleanLitterTray => "Clean litter tray",
+            Activity::Eat => "Eat",
+            Activity::CheckDrainageTrays => "Check drainage trays",
+        }
+    }
+
+    pub(crate) fn from_id(id: &str) -> Option<Activity> {
+        Activity::ALL
+            .into_iter()
+            .find(|activity| activity.id() == id)
+    }
+}
+
+/// How an activity behaves once it has been triggered.
+#[derive(Default)]
+pub(crate) struct ActivityConfig {
+    /// Applied in order as the activity stays pending for longer, must be
+    /// sorted by `after`.
+    pub(crate) escalations: Vec<Escalation>,
+    /// How long after being triggered an occurrence counts as missed rather
+    /// than late.  `None` means it just stays pending.
+    pub(crate) deadline: Option<Duration>,
+    /// Who takes turns doing it, if it's shared.
+    pub(crate) rotation: Option<Rotation>,
+    /// What a press does when it's been triggered more than once without
+    /// being done.
+    pub(crate) acknowledge: Acknowledge,
+    /// How long before it's due a press with nothing pending counts as
+    /// doing the next one early.  `None` means it never does.
+    pub(crate) complete_early: Option<Duration>,
+    /// Other activities to trigger once this one's been done.
+    pub(crate) follow_ups: Vec<FollowUp>,
+    /// Emailed when it's triggered, for activities without a button to
+    /// show it on.
+    pub(crate) notification: Option<Notification>,
+    /// Started by a long press on the button when nothing's pending.
+    pub(crate) timer: Option<Timer>,
+    /// Something used up each time it's done, e.g. pills.
+    pub(crate) consumable: Option<Consumable>,
+    /// Commands to run when things happen to it.
+    pub(crate) hooks: Vec<Hook>,
+}
+
+/// A command run with `sh -c` when an event of kind `on` is recorded, except
+/// for presses which didn't do anything.  Its environment has
+/// `FOURBUTTONS_ACTIVITY`, `FOURBUTTONS_EVENT`, `FOURBUTTONS_PENDING_SINCE`
+/// and `FOURBUTTONS_WHO`, empty if not known.
+pub(crate) struct Hook {
+    pub(crate) on: EventKind,
+    pub(crate) command: String,
+    /// It's killed if it's still running after this long.
+    pub(crate) timeout: std::time::Duration,
+}
+
+impl Hook {
+    /// Whether it's run for an event of `kind`.  Doing something early
+    /// counts as acknowledging it, and escalation emails (sent or not) as
+    /// escalating it.
+    pub(crate) fn runs_on(&self, kind: EventKind) -> bool {
+        self.on == kind
+            || match self.on {
+                EventKind::Acknowledged => kind == EventKind::CompletedEarly,
+                EventKind::Escalated => {
+                    matches!(kind, EventKind::Notified | EventKind::NotifyFailed)
+                }
+                _ => false,
+            }
+    }
+}
+
+pub(crate) struct Consumable {
+    /// What it is, e.g. "pills".
+    pub(crate) name: String,
+    /// How much of the stock each acknowledgement uses.
+    pub(crate) per_acknowledgement: u32,
+    /// Send a refill reminder once what's left won't last this long.
+    pub(crate) refill_within: Duration,
+}
+
+pub(crate) struct Timer {
+    /// What it's for, e.g. "laundry".
+    pub(crate) name: String,
+    pub(crate) duration: Duration,
+}
+
+pub(crate) struct FollowUp {
+    pub(crate) activity: Activity,
+    /// How long after the parent's been done.
+    pub(crate) after: Duration,
+}
+
+pub(crate) struct Notification {
+    pub(crate) title: String,
+    pub(crate) message: String,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum Acknowledge {
+    /// Each press does one occurrence, e.g. one dose of pills each.
+    #[default]
+    Oldest,
+    /// One press does them all, e.g. watering the plants once is enough.
+    All,
+}
+
+pub(crate) struct Escalation {
+    /// How long the activity has been pending before this applies.
+    pub(crate) after: Duration,
+    pub(crate) action: EscalationAction,
+}
+
+pub(crate) enum EscalationAction {
+    BlinkFast,
+    /// Email the usual recipient.
+    Notify {
+        title: String,
+        message: String,
+    },
+    /// Email someone else.
+    NotifyRecipient {
+        to: String,
+        title: String,
+        message: String,
+    },
 }
@@ -1,6 +1,18 @@
+use std::time::Duration;
+
 use anyhow::Result;
 
 pub(crate) trait Actor<T> {
     fn startup(&mut self) -> Result<()>;
     fn handle_message(&mut self, msg: T) -> Result<bool>;
+
+    /// How long to wait for the next message before calling `handle_timeout`,
+    /// or `None` to wait forever.  Asked again before every wait.
+    fn timeout(&self) -> Option<Duration> {
+        None
+    }
+
+    fn handle_timeout(&mut self) -> Result<bool> {
+        Ok(false)
+    }
 }
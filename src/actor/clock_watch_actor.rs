@@ -0,0 +1,73 @@
+use std::{
+    sync::mpsc::Sender,
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use log::warn;
+
+use crate::actor::message_source::MessageSource;
+
+// NTP slews small corrections gradually, so anything bigger than this is
+// someone (or something) setting the clock.
+const CLOCK_JUMP_THRESHOLD: Duration = Duration::from_secs(2);
+
+type Notify = Box<dyn Fn() -> anyhow::Result<()> + Send>;
+
+/// Periodically compares the wall clock against the monotonic clock and sends
+/// messages whenever the two disagree, i.e. the wall clock was stepped.
+pub(crate) struct ClockWatchActor {
+    interval: Duration,
+    notify: Vec<Notify>,
+    last_instant: Instant,
+    last_system_time: SystemTime,
+}
+
+impl ClockWatchActor {
+    pub(crate) fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            notify: Vec::new(),
+            last_instant: Instant::now(),
+            last_system_time: SystemTime::now(),
+        }
+    }
+
+    /// Also send a message from `message_builder` to `tx` when the clock's
+    /// stepped.
+    pub(crate) fn notify<T>(mut self, tx: Sender<T>, message_builder: fn() -> T) -> Self
+    where
+        T: Send + Sync + 'static,
+    {
+        self.notify
+            .push(Box::new(move || Ok(tx.send(message_builder())?)));
+        self
+    }
+}
+
+impl MessageSource for ClockWatchActor {
+    fn run(&mut self) -> anyhow::Result<bool> {
+        thread::sleep(self.interval);
+
+        let instant = Instant::now();
+        let system_time = SystemTime::now();
+        let monotonic_elapsed = instant - self.last_instant;
+        // duration_since fails if the wall clock went backwards, in which
+        // case the error holds how far back it went.
+        let jump = match system_time.duration_since(self.last_system_time) {
+            Ok(wall_elapsed) => wall_elapsed.abs_diff(monotonic_elapsed),
+            Err(err) => err.duration() + monotonic_elapsed,
+        };
+        self.last_instant = instant;
+        self.last_system_time = system_time;
+
+        if jump >= CLOCK_JUMP_THRESHOLD {
+            warn!("Wall clock jumped by roughly {:?}", jump);
+            for notify in &self.notify {
+                notify()?;
+            }
+        }
+
+        Ok(false)
+    }
+}
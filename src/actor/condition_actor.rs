@@ -0,0 +1,122 @@
+use std::sync::mpsc::Sender;
+
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use log::{info, warn};
+
+use crate::{activity::Activity, condition::Condition};
+
+use super::{actor::Actor, control_actor::ControlActorMessage};
+
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) enum ConditionActorMessage {
+    // The activity's trigger at this time is due, pass it on to the control
+    // actor if the condition says it's needed
+    Check {
+        activity: Activity,
+        at: NaiveDateTime,
+        condition: Condition,
+    },
+}
+
+/// Checks trigger conditions on its own thread, so a slow check doesn't
+/// hold up the scheduler.
+pub(crate) struct ConditionActor {
+    tx_control: Sender<ControlActorMessage>,
+}
+
+impl ConditionActor {
+    pub(crate) fn new(tx_control: Sender<ControlActorMessage>) -> Self {
+        Self { tx_control }
+    }
+}
+
+impl Actor<ConditionActorMessage> for ConditionActor {
+    fn startup(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn handle_message(&mut self, msg: ConditionActorMessage) -> Result<bool> {
+        match msg {
+            ConditionActorMessage::Check {
+                activity,
+                at,
+                condition,
+            } => {
+                match condition.not_needed(at) {
+                    Ok(Some(reason)) => {
+                        info!(
+                            "Skipping {:?} trigger at {} as it's not needed, {}",
+                            activity, at, reason
+                        );
+                        return Ok(false);
+                    }
+                    Ok(None) => {}
+                    // Better a reminder that isn't needed than missing one
+                    Err(err) => warn!(
+                        "Couldn't check whether {:?} is needed, triggering anyway: {:?}",
+                        activity, err
+                    ),
+                }
+
+                info!("Activity triggered: {:?}", activity);
+                self.tx_control
+                    .send(ControlActorMessage::Activity(activity, at))?;
+            }
+        }
+
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        str::FromStr,
+        sync::mpsc::{self, TryRecvError},
+    };
+
+    use chrono::{Local, NaiveDateTime};
+
+    use crate::{
+        activity::Activity,
+        actor::{actor::Actor, control_actor::ControlActorMessage},
+        condition::Condition,
+        db::testhelper::TmpFile,
+    };
+
+    use super::{ConditionActor, ConditionActorMessage};
+
+    #[test]
+    fn only_passes_on_needed_triggers() {
+        let moisture = TmpFile::new();
+        let (tx_control, rx_control) = mpsc::channel();
+        let mut actor = ConditionActor::new(tx_control);
+        let check = |at: &str| ConditionActorMessage::Check {
+            activity: Activity::WaterPlants,
+            at: NaiveDateTime::from_str(at).unwrap(),
+            condition: Condition::FileBelow {
+                path: moisture.path.clone(),
+                below: 40.0,
+            },
+        };
+
+        // No reading, so it's needed as far as we know
+        actor.handle_message(check("2020-01-01T08:00:00")).unwrap();
+        assert_eq!(
+            rx_control.try_recv(),
+            Ok(ControlActorMessage::Activity(
+                Activity::WaterPlants,
+                NaiveDateTime::from_str("2020-01-01T08:00:00").unwrap()
+            ))
+        );
+
+        // The reading's only just been written, so has to be checked as of
+        // now rather than 2020
+        fs::write(&moisture.path, "55").unwrap();
+        let now = Local::now().naive_local().format("%Y-%m-%dT%H:%M:%S");
+        actor.handle_message(check(&now.to_string())).unwrap();
+        assert_eq!(rx_control.try_recv(), Err(TryRecvError::Empty));
+    }
+}
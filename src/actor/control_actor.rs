@@ -1,24 +1,66 @@
-use std::sync::mpsc::Sender;
+use std::{collections::HashMap, sync::mpsc::Sender, time::Instant};
 
 use anyhow::{Context, Result};
-use chrono::NaiveDateTime;
-use log::{error, info};
+use chrono::{Duration, NaiveDateTime};
+use log::{error, info, warn};
 
 use crate::{
-    activity,
+    activity::{
+        Acknowledge, ActivityConfig, Consumable, Escalation, EscalationAction, FollowUp, Hook,
+        Timer,
+    },
     appdb::AppDb,
-    application_state::ApplicationState,
+    application_state::{ActivityState, ApplicationState, Press, Status},
+    clock::SharedClock,
+    db::fmt_naivedatetime_for_sqlite,
     email::Emailer,
+    event::{Event, EventKind, EventSource},
+    inventory,
     ledstrategy::LedState,
-    rpi::{Button, Led},
-    Activity,
+    rotation::{self, Rotation, RotationAdvance},
+    rpi::{Button, Led, PressKind},
+    schedule::Schedule,
+    scheduler::{ScheduledJobSpec, Task},
+    stats, Activity,
 };
 
-use super::{actor::Actor, led_actor::LedActorMessage};
+use super::{
+    actor::Actor, hook_actor::HookActorMessage, led_actor::LedActorMessage,
+    scheduler_actor::SchedulerActorMessage,
+};
+
+// Which activity each button acknowledges, and the LED which shows that it's
+// pending.
+const BUTTONS: [(Button, Activity, Led); 4] = [
+    (Button::B1, Activity::TakePills, Led::L1),
+    (Button::B2, Activity::CleanLitterTray, Led::L2),
+    (Button::B3, Activity::I, Led::L3),
+    (Button::B4, Activity::WaterPlants, Led::L4),
+];
 
+// Presses of the same button starting this close together make up one
+// double, triple etc. press, saying who did it.
+pub(crate) const MULTI_PRESS_GAP: std::time::Duration = std::time::Duration::from_millis(600);
+// Pressing the same button again within this long (but slower than a
+// multi-press) undoes an acknowledgement.
+const UNDO_WINDOW: std::time::Duration = std::time::Duration::from_secs(5);
+// A follow-up which should have happened while we were down still does if
+// it's no later than this.
+const FOLLOW_UP_GRACE_PERIOD: Duration = Duration::hours(1);
+// A timer still goes off if the scheduler only gets to it this late, e.g.
+// after the clock jumps.  Timers which ended while we were down are dealt
+// with at startup.
+const TIMER_GRACE_PERIOD: Duration = Duration::days(1);
+
+#[derive(Debug, PartialEq, Clone)]
 pub(crate) enum ControlActorMessage {
     Activity(Activity, NaiveDateTime),
-    ButtonPress(Button),
+    // The activity's timer's one-off job fired
+    TimerEnded(Activity, NaiveDateTime),
+    // And when it was pressed, it's only sent once it's released
+    ButtonPress(Button, PressKind, Instant),
+    // Every so often, put right any LED that's wrong
+    ReconcileLeds,
 }
 
 pub(crate) struct ControlActor<TEmail>
@@ -26,9 +68,108 @@ where
     TEmail: Emailer,
 {
     tx_led: Sender<LedActorMessage>,
+    tx_scheduler: Sender<SchedulerActorMessage>,
+    // Hooks are only run if there's somewhere to run them
+    tx_hook: Option<Sender<HookActorMessage>>,
     application_state: ApplicationState,
     db: AppDb,
     email: TEmail,
+    clock: SharedClock,
+    config: ControlConfig,
+    last_acknowledgement: Option<LastAcknowledgement>,
+    // What each LED was last told to do
+    led_states: HashMap<Led, LedState>,
+}
+
+/// How the buttons behave, see config.rs.
+pub(crate) struct ControlConfig {
+    /// How long a long press snoozes a pending activity for.
+    pub(crate) snooze_duration: Duration,
+    pub(crate) activity_configs: HashMap<Activity, ActivityConfig>,
+    /// Who a single, double, triple etc. press means did it.  Empty if
+    /// nobody's keeping track.
+    pub(crate) people: Vec<String>,
+    /// Where to email people, for escalations when it's their turn.
+    pub(crate) addresses: HashMap<String, String>,
+    /// When each activity is due, for doing it early.
+    pub(crate) schedules: HashMap<Activity, Schedule>,
+}
+
+impl ControlConfig {
+    fn escalations(&self, activity: Activity) -> &[Escalation] {
+        self.activity_configs
+            .get(&activity)
+            .map_or(&[], |config| &config.escalations)
+    }
+
+    fn deadline(&self, activity: Activity) -> Option<Duration> {
+        self.activity_configs
+            .get(&activity)
+            .and_then(|config| config.deadline)
+    }
+
+    fn rotation(&self, activity: Activity) -> Option<&Rotation> {
+        self.activity_configs
+            .get(&activity)
+            .and_then(|config| config.rotation.as_ref())
+    }
+
+    fn acknowledge(&self, activity: Activity) -> Acknowledge {
+        self.activity_configs
+            .get(&activity)
+            .map_or(Acknowledge::default(), |config| config.acknowledge)
+    }
+
+    fn timer(&self, activity: Activity) -> Option<&Timer> {
+        self.activity_configs
+            .get(&activity)
+            .and_then(|config| config.timer.as_ref())
+    }
+
+    fn follow_ups(&self, activity: Activity) -> &[FollowUp] {
+        self.activity_configs
+            .get(&activity)
+            .map_or(&[], |config| &config.follow_ups)
+    }
+
+    fn hooks(&self, activity: Activity) -> &[Hook] {
+        self.activity_configs
+            .get(&activity)
+            .map_or(&[], |config| &config.hooks)
+    }
+
+    fn consumable(&self, activity: Activity) -> Option<&Consumable> {
+        self.activity_configs
+            .get(&activity)
+            .and_then(|config| config.consumable.as_ref())
+    }
+
+    /// The next trigger of `activity`, if it's soon enough that doing it
+    /// now counts as doing that one early.
+    fn early_trigger(&self, activity: Activity, now: NaiveDateTime) -> Option<NaiveDateTime> {
+        let look_ahead = self.activity_configs.get(&activity)?.complete_early?;
+        let trigger = self.schedules.get(&activity)?.calculate_next_trigger(now);
+        (trigger - now <= look_ahead).then_some(trigger)
+    }
+}
+
+// The latest acknowledgement.  Quick extra presses say who did it, and if
+// there's anyone to pick it's only recorded once they stop.  A slower press
+// undoes it.
+struct LastAcknowledgement {
+    activity: Activity,
+    // What it cleared, so an accidental press can be undone
+    previous: ActivityState,
+    press: Press,
+    // Whose turn it was, in case it's moved on
+    previous_on_duty: Option<String>,
+    acknowledged_at: NaiveDateTime,
+    presses: usize,
+    // When the latest press started
+    last_press: Instant,
+    // And when we heard about it, which is once it was released
+    last_reported: Instant,
+    recorded: bool,
 }
 
 impl<TEmail> ControlActor<TEmail>
@@ -37,105 +178,830 @@ where
 {
     pub(crate) fn new(
         tx_led: Sender<LedActorMessage>,
+        tx_scheduler: Sender<SchedulerActorMessage>,
         application_state: ApplicationState,
         db: AppDb,
         email: TEmail,
+        clock: SharedClock,
+        config: ControlConfig,
     ) -> Self {
         Self {
             tx_led,
+            tx_scheduler,
+            tx_hook: None,
             application_state,
             db,
             email,
+            clock,
+            config,
+            last_acknowledgement: None,
+            led_states: HashMap::new(),
+        }
+    }
+
+    /// Run the configured hooks on the hook actor.
+    pub(crate) fn with_hooks(self, tx_hook: Sender<HookActorMessage>) -> Self {
+        Self {
+            tx_hook: Some(tx_hook),
+            ..self
         }
     }
 
     fn handle_activity(&mut self, activity: Activity, now: NaiveDateTime) -> Result<()> {
-        match activity {
-            activity::Activity::TakePills => {
-                self.application_state.take_pills_pending = Some(now);
-                self.send_led_state_change(Led::L1, LedState::On)?;
-                self.db
-                    .update_application_state(&self.application_state)
-                    .context("Failed to update application state")?;
-            }
-            activity::Activity::WaterPlants => {
-                self.application_state.water_plants_pending = Some(now);
-                self.send_led_state_change(Led::L4, LedState::On)?;
-                self.db
-                    .update_application_state(&self.application_state)
-                    .context("Failed to update application state")?;
-            }
-            activity::Activity::I => {
-                self.application_state.i_pending = Some(now);
-                self.send_led_state_change(Led::L3, LedState::On)?;
-                self.db
-                    .update_application_state(&self.application_state)
-                    .context("Failed to update application state")?;
-            }
-            activity::Activity::TakePillsReminder => {
-                if self.application_state.take_pills_pending.is_some() {
-                    // It's still pending!  Time to complain further
-                    if let Err(err) = self.email.send(
-                        "Did you forget to take your pills",
-                        "Did you forget to take your pills you fool",
-                    ) {
-                        error!("Failed to send email {:?}", err);
-                    }
-                }
+        // Its timer's job might not have got here yet, e.g. after the clock
+        // jumps, but the timer's still over
+        self.finish_timer(activity, now)?;
+        self.db.delete_follow_ups(activity, now)?;
+        if led_for(activity).is_none() {
+            return self.notify(activity, now);
+        }
+        self.record_acknowledgement()?;
+        let state = self.application_state.activity_mut(activity);
+        state.trigger(now);
+        if state.outstanding() > 1 {
+            info!(
+                "{:?} triggered again, {} outstanding",
+                activity,
+                state.outstanding()
+            );
+        }
+        // Undoing would lose the new occurrence
+        self.last_acknowledgement
+            .take_if(|acknowledgement| acknowledgement.activity == activity);
+
+        self.update_led(activity)?;
+        // First, so the hooks are told whose turn this one is
+        self.advance_rotation(activity, RotationAdvance::OnTrigger)?;
+        self.record_event(
+            activity,
+            EventKind::Triggered,
+            now,
+            Some(now),
+            EventSource::System,
+            None,
+        )?;
+        self.save_activity_state(activity)?;
+
+        Ok(())
+    }
+
+    /// Email about an activity which doesn't have a button.
+    fn notify(&self, activity: Activity, now: NaiveDateTime) -> Result<()> {
+        let Some(notification) = self
+            .config
+            .activity_configs
+            .get(&activity)
+            .and_then(|config| config.notification.as_ref())
+        else {
+            warn!("{:?} triggered but there's nothing to do", activity);
+            return Ok(());
+        };
+        info!("Reminding about {:?}", activity);
+        if let Err(err) = self.email.send(&notification.title, &notification.message) {
+            error!("Failed to send email {:?}", err);
+            return Ok(());
+        }
+        self.record_event(
+            activity,
+            EventKind::Notified,
+            now,
+            None,
+            EventSource::System,
+            None,
+        )
+    }
+
+    fn handle_button_press(
+        &mut self,
+        button: Button,
+        press_kind: PressKind,
+        pressed_at: Instant,
+    ) -> Result<bool> {
+        info!(
+            "Saw {:?} button press {:?} at {}",
+            press_kind,
+            button,
+            self.clock.now()
+        );
+        let Some(&(_, activity, led)) = BUTTONS.iter().find(|(b, _, _)| *b == button) else {
+            // Only the stop button isn't in the table
+            return Ok(true);
+        };
+        let now = self.clock.now();
+        let instant = self.clock.instant();
+        if press_kind == PressKind::Short {
+            if let Some(acknowledgement) = self.last_acknowledgement.as_mut().filter(|ack| {
+                ack.activity == activity
+                    && !ack.recorded
+                    && pressed_at.saturating_duration_since(ack.last_press) < MULTI_PRESS_GAP
+            }) {
+                acknowledgement.presses += 1;
+                acknowledgement.last_press = pressed_at;
+                acknowledgement.last_reported = instant;
+                self.send_led_state_change(led, LedState::BlinkTemporary)?;
+                return Ok(false);
+            }
+        }
+        self.record_acknowledgement()?;
+        // With more still to do, pressing again does the next one
+        let outstanding = self.application_state.activity(activity).outstanding();
+        if press_kind == PressKind::Short && outstanding == 0 {
+            if let Some(acknowledgement) = self.last_acknowledgement.take_if(|ack| {
+                ack.activity == activity
+                    && (!ack.press.acknowledged.is_empty() || ack.press.completed_early.is_some())
+                    && pressed_at.saturating_duration_since(ack.last_press) <= UNDO_WINDOW
+            }) {
+                self.undo(acknowledgement)?;
+                return Ok(false);
             }
-            activity::Activity::CleanLitterTray => {
-                self.application_state.clean_litter_tray_pending = Some(now);
-                self.send_led_state_change(Led::L2, LedState::On)?;
-                self.db
-                    .update_application_state(&self.application_state)
-                    .context("Failed to update application state")?;
+        }
+        if press_kind == PressKind::Hold && self.show_days_since_done(activity, led, now)? {
+            return Ok(false);
+        }
+        if self.handle_timer_press(activity, led, press_kind, now)? {
+            return Ok(false);
+        }
+
+        let until = now + self.config.snooze_duration;
+        let state = self.application_state.activity_mut(activity);
+        let snoozed = match press_kind {
+            PressKind::Long | PressKind::Hold => state.snooze(until),
+            // Nothing to snooze counts as a normal press too
+            PressKind::Short => None,
+        };
+        if let Some(pending_since) = snoozed {
+            info!("Snoozing {:?} until {}", activity, until);
+            self.update_led(activity)?;
+            self.record_event(
+                activity,
+                EventKind::Snoozed,
+                now,
+                Some(pending_since),
+                EventSource::Button,
+                Some(fmt_naivedatetime_for_sqlite(&until)),
+            )?;
+            self.save_activity_state(activity)?;
+        } else {
+            // Whichever button is pressed, flash it and do whatever's
+            // pending.  Pressing after a miss just acknowledges that it was
+            // missed.
+            let previous = state.clone();
+            let press = state.press(
+                self.config.acknowledge(activity),
+                self.config.early_trigger(activity, now),
+                self.config.escalations(activity),
+                now,
+            );
+            // Important to do this first otherwise it feels laggy (the
+            // rotation and everything in record_acknowledgement are
+            // blocking database calls)
+            self.send_led_state_change(led, LedState::BlinkTemporary)?;
+            let previous_on_duty = self.on_duty(activity)?;
+            if previous.pending.is_some() || press.completed_early.is_some() {
+                self.advance_rotation(activity, RotationAdvance::OnCompletion)?;
+            }
+            if let Some(trigger) = press.completed_early {
+                info!("{:?} due at {} done early", activity, trigger);
+                self.tx_scheduler
+                    .send(SchedulerActorMessage::Skip(activity, trigger))
+                    .context("Failed to send Skip to tx_scheduler")?;
+            }
+            self.last_acknowledgement = Some(LastAcknowledgement {
+                activity,
+                previous,
+                press,
+                previous_on_duty,
+                acknowledged_at: now,
+                presses: 1,
+                last_press: pressed_at,
+                last_reported: instant,
+                recorded: false,
+            });
+
+            // Saved once it's recorded, see record_acknowledgement.  No need
+            // to wait for more presses if they don't mean anything.
+            if self.config.people.is_empty() {
+                self.record_acknowledgement()?;
             }
         }
 
-        Ok(())
+        Ok(false)
+    }
+
+    /// A hold with nothing else going on blinks out how many days it's been
+    /// since `activity` was last done, up to 9.  Returns whether it did.
+    fn show_days_since_done(
+        &mut self,
+        activity: Activity,
+        led: Led,
+        now: NaiveDateTime,
+    ) -> Result<bool> {
+        let status = self
+            .application_state
+            .activity(activity)
+            .status(self.config.escalations(activity));
+        if !matches!(status, Status::Idle | Status::CompletedEarly) {
+            return Ok(false);
+        }
+        let events = self
+            .db
+            .load_activity_events(activity, NaiveDateTime::default(), now)?;
+        // Never done is as long ago as it gets
+        let days = stats::last_done(&events)
+            .map_or(9, |done| (now.date() - done.date()).num_days().clamp(0, 9));
+        info!("{:?} last done {} days ago", activity, days);
+        self.send_led_state_change(led, LedState::Readout(u8::try_from(days).unwrap_or(9)))?;
+
+        Ok(true)
     }
 
-    fn handle_button_press(&mut self, button: Button) -> Result<bool> {
-        info!("Saw button press {:?}", button);
-        // Whichever button is pressed, flash it
-        // Sent any pending application state to not pending
-        let led = match button {
-            Button::B1 => Led::L1,
-            Button::B2 => Led::L2,
-            Button::B3 => Led::L3,
-            Button::B4 => Led::L4,
-            Button::Stop => return Ok(true),
+    /// A long press with nothing else going on starts the button's timer,
+    /// and any press stops or dismisses it.  Returns whether the press was
+    /// for the timer.
+    fn handle_timer_press(
+        &mut self,
+        activity: Activity,
+        led: Led,
+        press_kind: PressKind,
+        now: NaiveDateTime,
+    ) -> Result<bool> {
+        let Some(timer) = self.config.timer(activity) else {
+            return Ok(false);
         };
+        let ends = now + timer.duration;
+        let state = self.application_state.activity_mut(activity);
+        let running = state.timer.filter(|_| !state.timer_finished);
+        let (kind, detail) = if state.clear_timer() {
+            info!("Clearing {} timer", timer.name);
+            if let Some(ends) = running {
+                self.tx_scheduler
+                    .send(SchedulerActorMessage::Cancel(activity, ends))
+                    .context("Failed to send Cancel to tx_scheduler")?;
+            }
+            (EventKind::TimerCleared, None)
+        } else if press_kind != PressKind::Short && state.start_timer(ends) {
+            info!("Starting {} timer, ends at {}", timer.name, ends);
+            self.add_timer_job(activity, ends)?;
+            (
+                EventKind::TimerStarted,
+                Some(fmt_naivedatetime_for_sqlite(&ends)),
+            )
+        } else {
+            return Ok(false);
+        };
+        // Not something to undo
+        self.last_acknowledgement
+            .take_if(|acknowledgement| acknowledgement.activity == activity);
 
-        // Important to do this first otherwise it feels laggy
-        // (the db.insert_reading function called later is
-        // blocking).
         self.send_led_state_change(led, LedState::BlinkTemporary)?;
+        self.record_event(activity, kind, now, None, EventSource::Button, detail)?;
+        self.save_activity_state(activity)?;
+
+        Ok(true)
+    }
+
+    /// Finish `activity`'s timer if it's running and it's ended by `now`.
+    fn finish_timer(&mut self, activity: Activity, now: NaiveDateTime) -> Result<()> {
+        let Some(timer) = self.config.timer(activity) else {
+            return Ok(());
+        };
+        if self
+            .application_state
+            .activity_mut(activity)
+            .finish_timer(now)
+        {
+            info!("{} timer finished", timer.name);
+            self.update_led(activity)?;
+        }
+
+        Ok(())
+    }
+
+    /// Record the last acknowledgement, and who did it, if that hasn't
+    /// happened yet.
+    fn record_acknowledgement(&mut self) -> Result<()> {
+        let Some(acknowledgement) = self
+            .last_acknowledgement
+            .as_mut()
+            .filter(|ack| !ack.recorded)
+        else {
+            return Ok(());
+        };
+        acknowledgement.recorded = true;
+
+        let who = self.config.people.get(acknowledgement.presses - 1).cloned();
+        if who.is_none() && !self.config.people.is_empty() {
+            warn!("Nobody is assigned {} presses", acknowledgement.presses);
+        }
+        info!("{:?} done by {:?}", acknowledgement.activity, who);
+        let Press {
+            acknowledged,
+            completed_early,
+        } = &acknowledgement.press;
+        let events: Vec<(EventKind, Option<NaiveDateTime>)> = if completed_early.is_some() {
+            vec![(EventKind::CompletedEarly, *completed_early)]
+        } else if acknowledged.is_empty() {
+            vec![(EventKind::Acknowledged, None)]
+        } else {
+            acknowledged
+                .iter()
+                .map(|pending_since| (EventKind::Acknowledged, Some(*pending_since)))
+                .collect()
+        };
+        let recorded: Vec<Event> = events
+            .into_iter()
+            .map(|(kind, pending_since)| Event {
+                activity: acknowledgement.activity,
+                kind,
+                occurred_at: acknowledgement.acknowledged_at,
+                pending_since,
+                source: EventSource::Button,
+                who: who.clone(),
+                detail: None,
+            })
+            .collect();
+        for event in &recorded {
+            self.db
+                .insert_event(event)
+                .context("Failed to record event")?;
+        }
+
+        let activity = acknowledgement.activity;
+        let acknowledged_at = acknowledgement.acknowledged_at;
+        let done = acknowledgement.previous.pending.is_some() || completed_early.is_some();
+        if done {
+            for event in &recorded {
+                self.run_hooks(event);
+            }
+            self.schedule_follow_ups(activity, acknowledged_at)?;
+            self.use_stock(activity, acknowledged_at)?;
+        }
+
+        // Done one, but there are more to do
+        self.update_led(activity)?;
+        self.save_activity_state(activity)
+    }
+
+    /// Put back whatever an accidental acknowledgement cleared.
+    fn undo(&mut self, acknowledgement: LastAcknowledgement) -> Result<()> {
+        let LastAcknowledgement {
+            activity,
+            previous,
+            press,
+            previous_on_duty,
+            acknowledged_at,
+            ..
+        } = acknowledgement;
+        info!("Undoing acknowledgement of {:?}", activity);
+        if previous.pending.is_some() || press.completed_early.is_some() {
+            self.cancel_follow_ups(activity, acknowledged_at)?;
+            self.put_back_stock(activity, self.clock.now())?;
+        }
+        self.application_state.activity_mut(activity).undo(previous);
+        if let Some(on_duty) = &previous_on_duty {
+            self.db.update_on_duty(activity, on_duty)?;
+        }
+        if press.completed_early.is_some() {
+            self.tx_scheduler
+                .send(SchedulerActorMessage::Unskip(activity))
+                .context("Failed to send Unskip to tx_scheduler")?;
+        }
+
+        self.update_led(activity)?;
+        for pending_since in press.acknowledged.iter().chain(&press.completed_early) {
+            self.record_event(
+                activity,
+                EventKind::Undone,
+                self.clock.now(),
+                Some(*pending_since),
+                EventSource::Button,
+                None,
+            )?;
+        }
+        self.save_activity_state(activity)?;
+
+        Ok(())
+    }
+
+    /// Trigger `activity`'s follow-ups, counting from `done_at`.
+    fn schedule_follow_ups(&self, activity: Activity, done_at: NaiveDateTime) -> Result<()> {
+        for follow_up in self.config.follow_ups(activity) {
+            let due_at = done_at + follow_up.after;
+            info!(
+                "{:?} done, following up with {:?} at {}",
+                activity, follow_up.activity, due_at
+            );
+            self.db.insert_follow_up(follow_up.activity, due_at)?;
+            self.add_follow_up_job(follow_up.activity, due_at)?;
+        }
+
+        Ok(())
+    }
+
+    fn cancel_follow_ups(&self, activity: Activity, done_at: NaiveDateTime) -> Result<()> {
+        for follow_up in self.config.follow_ups(activity) {
+            let due_at = done_at + follow_up.after;
+            self.db.delete_follow_up(follow_up.activity, due_at)?;
+            self.tx_scheduler
+                .send(SchedulerActorMessage::Cancel(follow_up.activity, due_at))
+                .context("Failed to send Cancel to tx_scheduler")?;
+        }
+
+        Ok(())
+    }
+
+    /// Use up some of what `activity` consumes, asking for a refill once
+    /// there's not enough left to last.
+    fn use_stock(&self, activity: Activity, now: NaiveDateTime) -> Result<()> {
+        let Some(consumable) = self.config.consumable(activity) else {
+            return Ok(());
+        };
+        let Some(stock) = self
+            .db
+            .consume_stock(activity, i64::from(consumable.per_acknowledgement))?
+        else {
+            // Not counted until it's first restocked
+            return Ok(());
+        };
+        info!("{} {} left", stock.remaining, consumable.name);
+        if stock.refill_notified {
+            return Ok(());
+        }
+        let Some(schedule) = self.config.schedules.get(&activity) else {
+            warn!("{:?} has no schedule to project its stock from", activity);
+            return Ok(());
+        };
+        if !inventory::runs_out_within(
+            stock.remaining,
+            consumable.per_acknowledgement,
+            schedule,
+            now,
+            consumable.refill_within,
+        ) {
+            return Ok(());
+        }
+
+        info!("Running low on {}", consumable.name);
+        if let Err(err) = self.email.send(
+            &format!("Running low on {}", consumable.name),
+            &format!(
+                "Only {} {} left, that won't last {} days.  Time to restock!",
+                stock.remaining,
+                consumable.name,
+                consumable.refill_within.num_days()
+            ),
+        ) {
+            error!("Failed to send email {:?}", err);
+            return Ok(());
+        }
+        self.db.set_refill_notified(activity, true)
+    }
+
+    /// Undo `use_stock`.  If there's enough to last after all, ask for a
+    /// refill again next time it runs low.
+    fn put_back_stock(&self, activity: Activity, now: NaiveDateTime) -> Result<()> {
+        let Some(consumable) = self.config.consumable(activity) else {
+            return Ok(());
+        };
+        let Some(stock) = self
+            .db
+            .consume_stock(activity, -i64::from(consumable.per_acknowledgement))?
+        else {
+            return Ok(());
+        };
+        if stock.refill_notified
+            && self
+                .config
+                .schedules
+                .get(&activity)
+                .is_some_and(|schedule| {
+                    !inventory::runs_out_within(
+                        stock.remaining,
+                        consumable.per_acknowledgement,
+                        schedule,
+                        now,
+                        consumable.refill_within,
+                    )
+                })
+        {
+            self.db.set_refill_notified(activity, false)?;
+        }
+
+        Ok(())
+    }
+
+    fn add_follow_up_job(&self, activity: Activity, due_at: NaiveDateTime) -> Result<()> {
+        self.tx_scheduler
+            .send(SchedulerActorMessage::AddJob(ScheduledJobSpec::new(
+                Schedule::Once(due_at),
+                activity,
+                FOLLOW_UP_GRACE_PERIOD,
+            )))
+            .context("Failed to send AddJob to tx_scheduler")?;
+
+        Ok(())
+    }
+
+    fn add_timer_job(&self, activity: Activity, ends: NaiveDateTime) -> Result<()> {
+        self.tx_scheduler
+            .send(SchedulerActorMessage::AddJob(ScheduledJobSpec::for_task(
+                Schedule::Once(ends),
+                Task::EndTimer(activity),
+                TIMER_GRACE_PERIOD,
+            )))
+            .context("Failed to send AddJob to tx_scheduler")?;
+
+        Ok(())
+    }
+
+    /// Whether `activity` has been pressed but that's not been recorded yet.
+    fn awaiting_record(&self, activity: Activity) -> bool {
+        self.last_acknowledgement
+            .as_ref()
+            .is_some_and(|ack| ack.activity == activity && !ack.recorded)
+    }
+
+    fn on_duty(&self, activity: Activity) -> Result<Option<String>> {
+        match self.config.rotation(activity) {
+            Some(rotation) => rotation::on_duty(&self.db, activity, rotation),
+            None => Ok(None),
+        }
+    }
+
+    /// Move the rotation on to the next person, if it moves on `when`.
+    fn advance_rotation(&self, activity: Activity, when: RotationAdvance) -> Result<()> {
+        let Some(rotation) = self
+            .config
+            .rotation(activity)
+            .filter(|rotation| rotation.advance == when)
+        else {
+            return Ok(());
+        };
+        let away = self.db.load_away()?;
+        let stored = self.db.load_on_duty(activity)?;
+        let next = match (when, stored) {
+            // The very first trigger is the first person's
+            (RotationAdvance::OnTrigger, None) => rotation.on_duty(None, &away),
+            (_, stored) => {
+                rotation.next(rotation.on_duty(stored.as_deref(), &away).as_deref(), &away)
+            }
+        };
+        if let Some(next) = next {
+            info!("{:?} is now {}'s turn", activity, next);
+            self.db.update_on_duty(activity, &next)?;
+        }
+
+        Ok(())
+    }
+
+    fn led_state(&self, activity: Activity) -> LedState {
+        self.application_state
+            .activity(activity)
+            .led_state(self.config.escalations(activity))
+    }
 
-        match button {
-            Button::B1 => {
-                self.application_state.take_pills_pending = None;
+    /// When the next thing happens to any activity.
+    fn next_wakeup(&self) -> Option<NaiveDateTime> {
+        BUTTONS
+            .iter()
+            .filter_map(|(_, activity, _)| {
+                self.application_state.activity(*activity).next_wakeup(
+                    self.config.deadline(*activity),
+                    self.config.escalations(*activity),
+                )
+            })
+            .min()
+    }
+
+    /// Move every activity on to now, missing deadlines, ending snoozes and
+    /// applying escalations.
+    fn catch_up(&mut self) -> Result<()> {
+        let now = self.clock.now();
+        for (_, activity, _) in BUTTONS {
+            let timeout = self.application_state.activity_mut(activity).timeout(
+                now,
+                self.config.deadline(activity),
+                self.config.escalations(activity),
+            );
+            for pending_since in &timeout.missed {
+                warn!(
+                    "{:?} pending since {} missed its deadline",
+                    activity, pending_since
+                );
+                self.record_event(
+                    activity,
+                    EventKind::Missed,
+                    now,
+                    Some(*pending_since),
+                    EventSource::System,
+                    None,
+                )?;
             }
-            Button::B2 => {
-                self.application_state.clean_litter_tray_pending = None;
+            for (level, pending_since) in &timeout.escalations {
+                self.escalate(activity, *level, *pending_since, now)?;
             }
-            Button::B3 => {
-                self.application_state.i_pending = None;
+            self.update_led(activity)?;
+            // Otherwise the snapshot would include a press that isn't in the
+            // events yet
+            if timeout.changed && !self.awaiting_record(activity) {
+                self.save_activity_state(activity)?;
             }
-            Button::B4 => {
-                self.application_state.water_plants_pending = None;
+        }
+
+        Ok(())
+    }
+
+    /// Apply `activity`'s escalation at `level`.
+    fn escalate(
+        &self,
+        activity: Activity,
+        level: usize,
+        pending_since: NaiveDateTime,
+        now: NaiveDateTime,
+    ) -> Result<()> {
+        info!("Escalating {:?}, pending since {}", activity, pending_since);
+        let on_duty = self.on_duty(activity)?;
+        let addressed = |message: &String| match &on_duty {
+            Some(person) => format!("It's {person}'s turn. {message}"),
+            None => message.clone(),
+        };
+        let on_duty_address = on_duty
+            .as_ref()
+            .and_then(|person| self.config.addresses.get(person));
+        let (kind, result, detail) = match &self.config.escalations(activity)[level].action {
+            // The LED follows from the state
+            EscalationAction::BlinkFast => (EventKind::Escalated, Ok(()), None),
+            // Straight to whoever's turn it is, if we know where
+            EscalationAction::Notify { title, message } => match on_duty_address {
+                Some(to) => (
+                    EventKind::Notified,
+                    self.email.send_to(to, title, &addressed(message)),
+                    Some(to.clone()),
+                ),
+                None => (
+                    EventKind::Notified,
+                    self.email.send(title, &addressed(message)),
+                    None,
+                ),
+            },
+            EscalationAction::NotifyRecipient { to, title, message } => (
+                EventKind::Notified,
+                self.email.send_to(to, title, &addressed(message)),
+                Some(to.clone()),
+            ),
+        };
+        // The escalation's been applied either way, otherwise it'd be
+        // retried straight away, so keep track of the failure instead
+        let (kind, detail) = match result {
+            Ok(()) => (kind, detail),
+            Err(err) => {
+                error!("Failed to send email {:?}", err);
+                (EventKind::NotifyFailed, Some(format!("{err:#}")))
             }
-            Button::Stop => {}
         };
+        self.record_event(
+            activity,
+            kind,
+            now,
+            Some(pending_since),
+            EventSource::System,
+            detail,
+        )
+    }
 
+    fn save_activity_state(&self, activity: Activity) -> Result<()> {
         self.db
-            .update_application_state(&self.application_state)
-            .context("Failed to update application state")?;
+            .update_activity_state(activity, &self.application_state.activity(activity))
+            .context("Failed to update activity state")?;
 
-        Ok(false)
+        Ok(())
+    }
+
+    fn record_event(
+        &self,
+        activity: Activity,
+        kind: EventKind,
+        occurred_at: NaiveDateTime,
+        pending_since: Option<NaiveDateTime>,
+        source: EventSource,
+        detail: Option<String>,
+    ) -> Result<()> {
+        let event = Event {
+            activity,
+            kind,
+            occurred_at,
+            pending_since,
+            source,
+            who: None,
+            detail,
+        };
+        self.db
+            .insert_event(&event)
+            .context("Failed to record event")?;
+        self.run_hooks(&event);
+
+        Ok(())
+    }
+
+    /// Hand `event` to any hooks waiting for it.  They're optional, so
+    /// anything going wrong is only logged.
+    fn run_hooks(&self, event: &Event) {
+        let Some(tx_hook) = &self.tx_hook else {
+            return;
+        };
+        let hooks: Vec<_> = self
+            .config
+            .hooks(event.activity)
+            .iter()
+            .filter(|hook| hook.runs_on(event.kind))
+            .collect();
+        if hooks.is_empty() {
+            return;
+        }
+        let who = match &event.who {
+            Some(who) => Some(who.clone()),
+            // Nobody's done it yet, but it's someone's turn
+            None if event.kind == EventKind::Triggered => {
+                self.on_duty(event.activity).unwrap_or_else(|err| {
+                    error!("Failed to load who's on duty {:?}", err);
+                    None
+                })
+            }
+            None => None,
+        };
+        for hook in hooks {
+            let sent = tx_hook.send(HookActorMessage::Run {
+                command: hook.command.clone(),
+                timeout: hook.timeout,
+                env: vec![
+                    ("FOURBUTTONS_ACTIVITY", event.activity.id().to_owned()),
+                    ("FOURBUTTONS_EVENT", event.kind.id().to_owned()),
+                    (
+                        "FOURBUTTONS_PENDING_SINCE",
+                        event
+                            .pending_since
+                            .map(|pending_since| pending_since.to_string())
+                            .unwrap_or_default(),
+                    ),
+                    ("FOURBUTTONS_WHO", who.clone().unwrap_or_default()),
+                ],
+            });
+            if let Err(err) = sent {
+                error!("Failed to send Run to tx_hook {:?}", err);
+            }
+        }
+    }
+
+    /// What `led` is showing now, a temporary blink or a readout ends with
+    /// it off.
+    fn shown_led_state(&self, led: Led) -> Option<LedState> {
+        match self.led_states.get(&led) {
+            Some(LedState::BlinkTemporary | LedState::Readout(_)) => Some(LedState::Off),
+            shown => shown.copied(),
+        }
     }
 
-    fn send_led_state_change(&self, led: Led, state: LedState) -> Result<()> {
+    /// Show `activity`'s state on its LED, if it isn't already.
+    fn update_led(&mut self, activity: Activity) -> Result<()> {
+        let Some(led) = led_for(activity) else {
+            return Ok(());
+        };
+        let desired = self.led_state(activity);
+        // The LEDs start off, until `reconcile_leds` says otherwise
+        if self.shown_led_state(led).unwrap_or(LedState::Off) != desired {
+            self.send_led_state_change(led, desired)?;
+        }
+
+        Ok(())
+    }
+
+    /// Make every LED show what the state says it should.  Used at startup,
+    /// and periodically in case anything's drifted.  Every LED is sent its
+    /// state each time, since `led_states` is only what was sent, not what
+    /// the `LedActor` got.  That ignores those it's already showing, and
+    /// holds them back until a press blink or readout ends.
+    fn reconcile_leds(&mut self) -> Result<()> {
+        for (_, activity, led) in BUTTONS {
+            // Still blinking to say it's been pressed
+            if self.awaiting_record(activity) {
+                continue;
+            }
+            let desired = self.led_state(activity);
+            match self.shown_led_state(led) {
+                Some(shown) if shown != desired => warn!(
+                    "{:?} LED was {:?} but {:?} is {:?}, correcting",
+                    led, shown, activity, desired
+                ),
+                _ => {}
+            }
+            self.send_led_state_change(led, desired)?;
+        }
+
+        Ok(())
+    }
+
+    fn send_led_state_change(&mut self, led: Led, state: LedState) -> Result<()> {
+        self.led_states.insert(led, state);
         self.tx_led
             .send(LedActorMessage::StateChange { led, state })
             .context("Failed to send LedStateChange to tx_led")?;
@@ -144,21 +1010,65 @@ where
     }
 }
 
+/// `None` for the follow-ups which are just reminders.
+pub(crate) fn led_for(activity: Activity) -> Option<Led> {
+    BUTTONS
+        .iter()
+        .find(|(_, a, _)| *a == activity)
+        .map(|(_, _, led)| *led)
+}
+
 impl<TEmail> Actor<ControlActorMessage> for ControlActor<TEmail>
 where
     TEmail: Emailer,
 {
     fn startup(&mut self) -> anyhow::Result<()> {
-        if self.application_state.take_pills_pending.is_some() {
-            self.send_led_state_change(Led::L1, LedState::On)?;
+        // We don't know which escalations were applied before the restart,
+        // so assume all the ones which were due already have been rather
+        // than sending duplicate notifications.
+        let now = self.clock.now();
+        for (_, activity, _) in BUTTONS {
+            self.application_state
+                .activity_mut(activity)
+                .catch_up_escalations(self.config.escalations(activity), now);
+        }
+
+        // The scheduler doesn't remember what's been done early
+        for (_, activity, _) in BUTTONS {
+            if let Some(trigger) = self
+                .application_state
+                .activity(activity)
+                .completed_early
+                .filter(|trigger| *trigger > now)
+            {
+                self.tx_scheduler
+                    .send(SchedulerActorMessage::Skip(activity, trigger))
+                    .context("Failed to send Skip to tx_scheduler")?;
+            }
         }
-        if self.application_state.water_plants_pending.is_some() {
-            self.send_led_state_change(Led::L4, LedState::On)?;
+
+        // Nor the timers it was waiting to end
+        for (_, activity, _) in BUTTONS {
+            let state = self.application_state.activity_mut(activity);
+            if let Some(ends) = state.timer {
+                if !state.finish_timer(now) {
+                    self.add_timer_job(activity, ends)?;
+                }
+            }
         }
-        if self.application_state.i_pending.is_some() {
-            self.send_led_state_change(Led::L3, LedState::On)?;
+
+        // Nor the follow-ups it was waiting to trigger
+        for (activity, due_at) in self.db.load_follow_ups()? {
+            if due_at + FOLLOW_UP_GRACE_PERIOD < now {
+                warn!("Dropping follow-up {:?} due at {}", activity, due_at);
+                self.db.delete_follow_up(activity, due_at)?;
+            } else {
+                self.add_follow_up_job(activity, due_at)?;
+            }
         }
 
+        self.reconcile_leds()?;
+
         Ok(())
     }
 
@@ -168,50 +1078,220 @@ where
                 self.handle_activity(activity, now)?;
                 Ok(false)
             }
-            ControlActorMessage::ButtonPress(button) => self.handle_button_press(button),
+            ControlActorMessage::TimerEnded(activity, now) => {
+                self.finish_timer(activity, now)?;
+                Ok(false)
+            }
+            ControlActorMessage::ButtonPress(button, press_kind, pressed_at) => {
+                self.handle_button_press(button, press_kind, pressed_at)
+            }
+            ControlActorMessage::ReconcileLeds => {
+                self.reconcile_leds()?;
+                Ok(false)
+            }
+        }
+    }
+
+    fn timeout(&self) -> Option<std::time::Duration> {
+        let wakeup = self.next_wakeup().map(|wakeup| {
+            (wakeup - self.clock.now())
+                .to_std()
+                .unwrap_or(std::time::Duration::ZERO)
+        });
+        let press_pattern_end = self
+            .last_acknowledgement
+            .as_ref()
+            .filter(|ack| !ack.recorded)
+            // Another press could have started by then but not been
+            // released yet, this leaves about as long for that as the last
+            // one took
+            .map(|ack| {
+                (ack.last_reported + MULTI_PRESS_GAP)
+                    .saturating_duration_since(self.clock.instant())
+            });
+        wakeup.into_iter().chain(press_pattern_end).min()
+    }
+
+    fn handle_timeout(&mut self) -> anyhow::Result<bool> {
+        let instant = self.clock.instant();
+        if self
+            .last_acknowledgement
+            .as_ref()
+            .is_some_and(|ack| instant - ack.last_reported >= MULTI_PRESS_GAP)
+        {
+            self.record_acknowledgement()?;
         }
+        self.catch_up()?;
+
+        Ok(false)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use std::{
+        collections::HashMap,
         str::FromStr,
         sync::mpsc::{self, Receiver, TryRecvError},
         time::Duration,
     };
 
-    use chrono::NaiveDateTime;
+    use chrono::{NaiveDateTime, NaiveTime};
 
     use crate::{
-        actor::{actor::Actor, control_actor::ControlActorMessage, led_actor::LedActorMessage},
+        activity::{
+            Acknowledge, Activity, ActivityConfig, Consumable, Escalation, EscalationAction,
+            FollowUp, Hook, Notification, Timer,
+        },
+        actor::{
+            actor::Actor, control_actor::ControlActorMessage, hook_actor::HookActorMessage,
+            led_actor::LedActorMessage, scheduler_actor::SchedulerActorMessage,
+        },
         appdb::AppDb,
-        application_state::ApplicationState,
-        email::Emailer,
+        application_state::{ActivityState, ApplicationState, Status},
+        clock::{testhelper::ManualClock, Clock},
+        email::testhelper::FakeEmail,
+        event::{EventKind, EventSource},
         ledstrategy::LedState,
-        rpi::{Button, Led},
+        rotation::{Rotation, RotationAdvance},
+        rpi::{Button, Led, PressKind},
+        schedule::{every_day, DailySchedule, Schedule},
+        scheduler::Task,
     };
 
-    use super::ControlActor;
+    use super::{ControlActor, ControlConfig};
 
-    struct FakeEmail {}
+    fn take_pills_config() -> ActivityConfig {
+        ActivityConfig {
+            escalations: vec![
+                Escalation {
+                    after: chrono::Duration::hours(2),
+                    action: EscalationAction::BlinkFast,
+                },
+                Escalation {
+                    after: chrono::Duration::hours(5),
+                    action: EscalationAction::Notify {
+                        title: "Pills".to_owned(),
+                        message: "Take your pills".to_owned(),
+                    },
+                },
+                Escalation {
+                    after: chrono::Duration::hours(6),
+                    action: EscalationAction::NotifyRecipient {
+                        to: "someone@else".to_owned(),
+                        title: "Pills".to_owned(),
+                        message: "Remind them to take their pills".to_owned(),
+                    },
+                },
+            ],
+            deadline: Some(chrono::Duration::hours(10)),
+            rotation: None,
+            acknowledge: Acknowledge::Oldest,
+            complete_early: None,
+            follow_ups: vec![FollowUp {
+                activity: Activity::Eat,
+                after: chrono::Duration::minutes(30),
+            }],
+            notification: None,
+            timer: None,
+            consumable: None,
+            hooks: [
+                EventKind::Triggered,
+                EventKind::Acknowledged,
+                EventKind::Undone,
+            ]
+            .into_iter()
+            .map(|on| Hook {
+                on,
+                command: format!("./hooks/{}", on.id()),
+                timeout: Duration::from_secs(30),
+            })
+            .collect(),
+        }
+    }
 
-    impl Emailer for FakeEmail {
-        fn send(&self, _: &str, _: &str) -> anyhow::Result<()> {
-            Ok(())
+    fn control_config() -> ControlConfig {
+        ControlConfig {
+            snooze_duration: chrono::Duration::hours(1),
+            activity_configs: HashMap::from([
+                (Activity::TakePills, take_pills_config()),
+                (
+                    Activity::Eat,
+                    ActivityConfig {
+                        notification: Some(Notification {
+                            title: "Eat".to_owned(),
+                            message: "Eat something".to_owned(),
+                        }),
+                        ..ActivityConfig::default()
+                    },
+                ),
+                (
+                    Activity::WaterPlants,
+                    ActivityConfig {
+                        escalations: vec![Escalation {
+                            after: chrono::Duration::hours(1),
+                            action: EscalationAction::Notify {
+                                title: "Plants".to_owned(),
+                                message: "Water the plants".to_owned(),
+                            },
+                        }],
+                        rotation: Some(Rotation {
+                            people: vec!["Alex".to_owned(), "Sam".to_owned()],
+                            advance: RotationAdvance::OnCompletion,
+                        }),
+                        acknowledge: Acknowledge::All,
+                        timer: Some(Timer {
+                            name: "tea".to_owned(),
+                            duration: chrono::Duration::minutes(4),
+                        }),
+                        ..ActivityConfig::default()
+                    },
+                ),
+                (
+                    Activity::CleanLitterTray,
+                    ActivityConfig {
+                        consumable: Some(Consumable {
+                            name: "litter".to_owned(),
+                            per_acknowledgement: 5,
+                            refill_within: chrono::Duration::days(3),
+                        }),
+                        ..ActivityConfig::default()
+                    },
+                ),
+            ]),
+            people: vec![],
+            addresses: HashMap::new(),
+            schedules: HashMap::new(),
         }
     }
 
-    fn control_actor() -> (ControlActor<FakeEmail>, mpsc::Receiver<LedActorMessage>) {
+    fn control_actor() -> (
+        ControlActor<FakeEmail>,
+        mpsc::Receiver<LedActorMessage>,
+        mpsc::Receiver<SchedulerActorMessage>,
+        ManualClock,
+    ) {
         let (tx_led, rx_led) = mpsc::channel::<LedActorMessage>();
+        let (tx_scheduler, rx_scheduler) = mpsc::channel::<SchedulerActorMessage>();
         let application_state = ApplicationState::blank();
         let db = AppDb::new_tmp();
         db.run_migrations().unwrap();
-        let email = FakeEmail {};
+        let email = FakeEmail::new();
+        let clock = ManualClock::new(NaiveDateTime::from_str("2020-01-01T09:00:00").unwrap());
 
         (
-            ControlActor::new(tx_led, application_state, db, email),
+            ControlActor::new(
+                tx_led,
+                tx_scheduler,
+                application_state,
+                db,
+                email,
+                clock.shared(),
+                control_config(),
+            ),
             rx_led,
+            rx_scheduler,
+            clock,
         )
     }
 
@@ -230,14 +1310,11 @@ mod tests {
 
     #[test]
     fn test_take_pills_activity() {
-        let (mut actor, rx_led) = control_actor();
+        let (mut actor, rx_led, _rx_scheduler, _) = control_actor();
 
         let now = NaiveDateTime::from_str("2020-01-01T09:00:00").unwrap();
         actor
-            .handle_message(ControlActorMessage::Activity(
-                crate::activity::Activity::TakePills,
-                now,
-            ))
+            .handle_message(ControlActorMessage::Activity(Activity::TakePills, now))
             .unwrap();
 
         assert_eq!(
@@ -249,29 +1326,28 @@ mod tests {
         );
 
         assert_eq!(
-            actor.db.load_application_state().unwrap(),
-            Some(ApplicationState {
-                take_pills_pending: Some(now),
-                water_plants_pending: None,
-                i_pending: None,
-                clean_litter_tray_pending: None,
-            })
+            actor.db.load_activity_state(Activity::TakePills).unwrap(),
+            ActivityState {
+                pending: Some(now),
+                ..ActivityState::default()
+            }
         );
     }
 
     #[test]
     fn test_take_pills_resolution() {
-        let (mut actor, rx_led) = control_actor();
+        let (mut actor, rx_led, _rx_scheduler, clock) = control_actor();
 
         let now = NaiveDateTime::from_str("2020-01-01T09:00:00").unwrap();
         actor
-            .handle_message(ControlActorMessage::Activity(
-                crate::activity::Activity::TakePills,
-                now,
-            ))
+            .handle_message(ControlActorMessage::Activity(Activity::TakePills, now))
             .unwrap();
         actor
-            .handle_message(ControlActorMessage::ButtonPress(Button::B1))
+            .handle_message(ControlActorMessage::ButtonPress(
+                Button::B1,
+                PressKind::Short,
+                clock.instant(),
+            ))
             .unwrap();
 
         assert_eq!(
@@ -289,8 +1365,1718 @@ mod tests {
         );
 
         assert_eq!(
-            actor.db.load_application_state().unwrap(),
-            Some(ApplicationState::blank())
+            actor.db.load_activity_state(Activity::TakePills).unwrap(),
+            ActivityState::default()
+        );
+    }
+
+    #[test]
+    fn test_snooze() {
+        // Using I as it doesn't have any escalations to get in the way
+        let (mut actor, rx_led, _rx_scheduler, clock) = control_actor();
+
+        let now = NaiveDateTime::from_str("2020-01-01T09:00:00").unwrap();
+        actor
+            .handle_message(ControlActorMessage::Activity(Activity::I, now))
+            .unwrap();
+        assert_eq!(actor.timeout(), None);
+
+        actor
+            .handle_message(ControlActorMessage::ButtonPress(
+                Button::B3,
+                PressKind::Long,
+                clock.instant(),
+            ))
+            .unwrap();
+        assert_eq!(
+            actor.db.load_activity_state(Activity::I).unwrap(),
+            ActivityState {
+                pending: Some(now),
+                queued: Vec::new(),
+                snoozed_until: Some(NaiveDateTime::from_str("2020-01-01T10:00:00").unwrap()),
+                missed: None,
+                completed_early: None,
+                escalation_level: 0,
+                timer: None,
+                timer_finished: false,
+            }
+        );
+        assert_eq!(
+            actor.timeout(),
+            Some(chrono::Duration::hours(1).to_std().unwrap())
+        );
+
+        clock.advance(chrono::Duration::hours(1));
+        actor.handle_timeout().unwrap();
+        assert_eq!(actor.timeout(), None);
+
+        assert_eq!(
+            expect_messages(&rx_led, 3),
+            vec![
+                LedActorMessage::StateChange {
+                    led: Led::L3,
+                    state: LedState::On
+                },
+                LedActorMessage::StateChange {
+                    led: Led::L3,
+                    state: LedState::Off
+                },
+                LedActorMessage::StateChange {
+                    led: Led::L3,
+                    state: LedState::On
+                },
+            ]
+        );
+        assert_eq!(
+            actor.db.load_activity_state(Activity::I).unwrap(),
+            ActivityState {
+                pending: Some(now),
+                ..ActivityState::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_long_press_with_nothing_pending() {
+        let (mut actor, rx_led, _rx_scheduler, clock) = control_actor();
+
+        actor
+            .handle_message(ControlActorMessage::ButtonPress(
+                Button::B2,
+                PressKind::Long,
+                clock.instant(),
+            ))
+            .unwrap();
+
+        assert_eq!(
+            expect_messages(&rx_led, 1),
+            vec![LedActorMessage::StateChange {
+                led: Led::L2,
+                state: LedState::BlinkTemporary
+            }]
+        );
+        assert_eq!(actor.timeout(), None);
+    }
+
+    #[test]
+    fn test_hold_shows_days_since_done() {
+        let (mut actor, rx_led, _rx_scheduler, clock) = control_actor();
+        let hold = |actor: &mut ControlActor<FakeEmail>| {
+            actor
+                .handle_message(ControlActorMessage::ButtonPress(
+                    Button::B1,
+                    PressKind::Hold,
+                    clock.instant(),
+                ))
+                .unwrap();
+        };
+
+        // Never done
+        hold(&mut actor);
+        assert_eq!(
+            expect_messages(&rx_led, 1),
+            vec![LedActorMessage::StateChange {
+                led: Led::L1,
+                state: LedState::Readout(9)
+            }]
+        );
+
+        actor
+            .handle_message(ControlActorMessage::Activity(
+                Activity::TakePills,
+                clock.now(),
+            ))
+            .unwrap();
+        actor
+            .handle_message(ControlActorMessage::ButtonPress(
+                Button::B1,
+                PressKind::Short,
+                clock.instant(),
+            ))
+            .unwrap();
+        clock.advance(chrono::Duration::days(3));
+        actor.handle_timeout().unwrap();
+        expect_messages(&rx_led, 2);
+        hold(&mut actor);
+        assert_eq!(
+            expect_messages(&rx_led, 1),
+            vec![LedActorMessage::StateChange {
+                led: Led::L1,
+                state: LedState::Readout(3)
+            }]
+        );
+
+        // With something pending it's just a long press, and snoozes
+        actor
+            .handle_message(ControlActorMessage::Activity(
+                Activity::TakePills,
+                clock.now(),
+            ))
+            .unwrap();
+        hold(&mut actor);
+        assert_eq!(
+            expect_messages(&rx_led, 2),
+            vec![
+                LedActorMessage::StateChange {
+                    led: Led::L1,
+                    state: LedState::On
+                },
+                LedActorMessage::StateChange {
+                    led: Led::L1,
+                    state: LedState::Off
+                },
+            ]
+        );
+        assert!(actor
+            .application_state
+            .activity(Activity::TakePills)
+            .snoozed_until
+            .is_some());
+    }
+
+    #[test]
+    fn test_escalation() {
+        let (mut actor, rx_led, _rx_scheduler, clock) = control_actor();
+
+        let now = NaiveDateTime::from_str("2020-01-01T09:00:00").unwrap();
+        actor
+            .handle_message(ControlActorMessage::Activity(Activity::TakePills, now))
+            .unwrap();
+        assert_eq!(
+            actor.timeout(),
+            Some(chrono::Duration::hours(2).to_std().unwrap())
+        );
+
+        clock.advance(chrono::Duration::hours(2));
+        actor.handle_timeout().unwrap();
+        assert_eq!(
+            actor.timeout(),
+            Some(chrono::Duration::hours(3).to_std().unwrap())
+        );
+        assert!(actor.email.sent.borrow().is_empty());
+
+        // Both notifications are due by now
+        clock.advance(chrono::Duration::hours(5));
+        actor.handle_timeout().unwrap();
+        // Only the deadline is left
+        assert_eq!(
+            actor.timeout(),
+            Some(chrono::Duration::hours(3).to_std().unwrap())
+        );
+        assert_eq!(
+            *actor.email.sent.borrow(),
+            vec![
+                (
+                    "default".to_owned(),
+                    "Pills".to_owned(),
+                    "Take your pills".to_owned()
+                ),
+                (
+                    "someone@else".to_owned(),
+                    "Pills".to_owned(),
+                    "Remind them to take their pills".to_owned()
+                )
+            ]
+        );
+
+        assert_eq!(
+            expect_messages(&rx_led, 2),
+            vec![
+                LedActorMessage::StateChange {
+                    led: Led::L1,
+                    state: LedState::On
+                },
+                LedActorMessage::StateChange {
+                    led: Led::L1,
+                    state: LedState::BlinkFast
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_escalation_email_fails() {
+        let (mut actor, _rx_led, _rx_scheduler, clock) = control_actor();
+
+        let triggered_at = NaiveDateTime::from_str("2020-01-01T09:00:00").unwrap();
+        actor
+            .handle_message(ControlActorMessage::Activity(
+                Activity::TakePills,
+                triggered_at,
+            ))
+            .unwrap();
+        actor.email.failing.set(true);
+        clock.advance(chrono::Duration::hours(5));
+        actor.handle_timeout().unwrap();
+        // Not retried, it's on to the next escalation
+        assert_eq!(
+            actor.timeout(),
+            Some(chrono::Duration::hours(1).to_std().unwrap())
+        );
+
+        let events: Vec<_> = actor
+            .db
+            .load_activity_events(
+                Activity::TakePills,
+                triggered_at,
+                triggered_at + chrono::Duration::days(1),
+            )
+            .unwrap()
+            .into_iter()
+            .map(|event| (event.kind, event.detail))
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                (EventKind::Triggered, None),
+                (EventKind::Escalated, None),
+                (
+                    EventKind::NotifyFailed,
+                    Some("Failed to send to default".to_owned())
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_history() {
+        let (mut actor, _rx_led, _rx_scheduler, clock) = control_actor();
+
+        let triggered_at = NaiveDateTime::from_str("2020-01-01T09:00:00").unwrap();
+        actor
+            .handle_message(ControlActorMessage::Activity(
+                Activity::TakePills,
+                triggered_at,
+            ))
+            .unwrap();
+        clock.advance(chrono::Duration::minutes(30));
+        actor
+            .handle_message(ControlActorMessage::ButtonPress(
+                Button::B1,
+                PressKind::Long,
+                clock.instant(),
+            ))
+            .unwrap();
+        clock.advance(chrono::Duration::hours(5));
+        actor.handle_timeout().unwrap();
+        actor
+            .handle_message(ControlActorMessage::ButtonPress(
+                Button::B1,
+                PressKind::Short,
+                clock.instant(),
+            ))
+            .unwrap();
+
+        let at = |s: &str| Some(NaiveDateTime::from_str(s).unwrap());
+        let events: Vec<_> = actor
+            .db
+            .load_activity_events(
+                Activity::TakePills,
+                triggered_at,
+                triggered_at + chrono::Duration::days(1),
+            )
+            .unwrap()
+            .into_iter()
+            .map(|event| {
+                assert_eq!(event.pending_since, Some(triggered_at));
+                (
+                    event.kind,
+                    Some(event.occurred_at),
+                    event.source,
+                    event.detail,
+                )
+            })
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                (
+                    EventKind::Triggered,
+                    at("2020-01-01T09:00:00"),
+                    EventSource::System,
+                    None
+                ),
+                (
+                    EventKind::Snoozed,
+                    at("2020-01-01T09:30:00"),
+                    EventSource::Button,
+                    Some("2020-01-01T10:30:00.000000000Z".to_owned())
+                ),
+                (
+                    EventKind::Escalated,
+                    at("2020-01-01T14:30:00"),
+                    EventSource::System,
+                    None
+                ),
+                (
+                    EventKind::Notified,
+                    at("2020-01-01T14:30:00"),
+                    EventSource::System,
+                    None
+                ),
+                (
+                    EventKind::Acknowledged,
+                    at("2020-01-01T14:30:00"),
+                    EventSource::Button,
+                    None
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_undo() {
+        let (mut actor, rx_led, _rx_scheduler, clock) = control_actor();
+
+        let now = NaiveDateTime::from_str("2020-01-01T09:00:00").unwrap();
+        actor
+            .handle_message(ControlActorMessage::Activity(Activity::TakePills, now))
+            .unwrap();
+        clock.advance(chrono::Duration::hours(2));
+        actor.handle_timeout().unwrap();
+
+        // The cat sits on the button
+        let press =
+            || ControlActorMessage::ButtonPress(Button::B1, PressKind::Short, clock.instant());
+        actor.handle_message(press()).unwrap();
+        clock.advance(chrono::Duration::seconds(2));
+        actor.handle_message(press()).unwrap();
+
+        // Back to how it was, including the escalation
+        assert_eq!(
+            actor.db.load_activity_state(Activity::TakePills).unwrap(),
+            ActivityState {
+                pending: Some(now),
+                ..ActivityState::default()
+            }
+        );
+        assert_eq!(
+            actor.timeout(),
+            Some(
+                (chrono::Duration::hours(3) - chrono::Duration::seconds(2))
+                    .to_std()
+                    .unwrap()
+            )
+        );
+        let messages = expect_messages(&rx_led, 4);
+        assert_eq!(
+            messages[2..],
+            vec![
+                LedActorMessage::StateChange {
+                    led: Led::L1,
+                    state: LedState::BlinkTemporary
+                },
+                LedActorMessage::StateChange {
+                    led: Led::L1,
+                    state: LedState::BlinkFast
+                },
+            ]
+        );
+
+        // Too late to undo the real acknowledgement
+        actor.handle_message(press()).unwrap();
+        clock.advance(chrono::Duration::seconds(6));
+        actor.handle_message(press()).unwrap();
+        assert_eq!(
+            actor.db.load_activity_state(Activity::TakePills).unwrap(),
+            ActivityState::default()
+        );
+
+        let kinds: Vec<EventKind> = actor
+            .db
+            .load_activity_events(Activity::TakePills, now, now + chrono::Duration::days(1))
+            .unwrap()
+            .into_iter()
+            .map(|event| event.kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                EventKind::Triggered,
+                EventKind::Escalated,
+                EventKind::Acknowledged,
+                EventKind::Undone,
+                EventKind::Acknowledged,
+                EventKind::Acknowledged,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_who_did_it() {
+        let (mut actor, rx_led, _rx_scheduler, clock) = control_actor();
+        actor.config.people = vec!["Alex".to_owned(), "Sam".to_owned()];
+
+        let now = NaiveDateTime::from_str("2020-01-01T09:00:00").unwrap();
+        let who_did_it = |actor: &mut ControlActor<FakeEmail>, presses: usize| {
+            actor
+                .handle_message(ControlActorMessage::Activity(
+                    Activity::CleanLitterTray,
+                    now,
+                ))
+                .unwrap();
+            // Pressed 350ms apart, each only sent once it's released
+            for _ in 0..presses {
+                let pressed_at = clock.instant();
+                clock.advance(chrono::Duration::milliseconds(150));
+                actor
+                    .handle_message(ControlActorMessage::ButtonPress(
+                        Button::B2,
+                        PressKind::Short,
+                        pressed_at,
+                    ))
+                    .unwrap();
+                clock.advance(chrono::Duration::milliseconds(200));
+            }
+            // Only recorded once the presses stop
+            assert_eq!(actor.timeout(), Some(Duration::from_millis(400)));
+            clock.advance(chrono::Duration::milliseconds(400));
+            actor.handle_timeout().unwrap();
+            assert_eq!(actor.timeout(), None);
+
+            let events = actor
+                .db
+                .load_activity_events(
+                    Activity::CleanLitterTray,
+                    now,
+                    now + chrono::Duration::days(1),
+                )
+                .unwrap();
+            let acknowledgement = events.last().unwrap();
+            assert_eq!(acknowledgement.kind, EventKind::Acknowledged);
+            acknowledgement.who.clone()
+        };
+
+        assert_eq!(who_did_it(&mut actor, 1), Some("Alex".to_owned()));
+        assert_eq!(who_did_it(&mut actor, 2), Some("Sam".to_owned()));
+        assert_eq!(who_did_it(&mut actor, 3), None);
+
+        // Every press flashes the LED
+        let blinks = expect_messages(&rx_led, 9)
+            .into_iter()
+            .filter(|message| {
+                *message
+                    == LedActorMessage::StateChange {
+                        led: Led::L2,
+                        state: LedState::BlinkTemporary,
+                    }
+            })
+            .count();
+        assert_eq!(blinks, 6);
+        assert_eq!(
+            actor
+                .db
+                .load_activity_state(Activity::CleanLitterTray)
+                .unwrap(),
+            ActivityState::default()
+        );
+    }
+
+    #[test]
+    fn test_rotation() {
+        let (mut actor, _rx_led, _rx_scheduler, clock) = control_actor();
+
+        let now = NaiveDateTime::from_str("2020-01-01T09:00:00").unwrap();
+        actor
+            .handle_message(ControlActorMessage::Activity(Activity::WaterPlants, now))
+            .unwrap();
+        clock.advance(chrono::Duration::hours(1));
+        actor.handle_timeout().unwrap();
+        assert_eq!(
+            *actor.email.sent.borrow(),
+            vec![(
+                "default".to_owned(),
+                "Plants".to_owned(),
+                "It's Alex's turn. Water the plants".to_owned()
+            )]
+        );
+
+        // Whoever actually did it, it's the next person's turn
+        let press =
+            || ControlActorMessage::ButtonPress(Button::B4, PressKind::Short, clock.instant());
+        actor.handle_message(press()).unwrap();
+        assert_eq!(
+            actor.on_duty(Activity::WaterPlants).unwrap(),
+            Some("Sam".to_owned())
+        );
+
+        // Undoing puts it back
+        clock.advance(chrono::Duration::seconds(2));
+        actor.handle_message(press()).unwrap();
+        assert_eq!(
+            actor.on_duty(Activity::WaterPlants).unwrap(),
+            Some("Alex".to_owned())
+        );
+
+        // Done for real, then pressing with nothing pending doesn't move it
+        // on again
+        actor.handle_message(press()).unwrap();
+        clock.advance(chrono::Duration::seconds(6));
+        actor.handle_message(press()).unwrap();
+        assert_eq!(
+            actor.on_duty(Activity::WaterPlants).unwrap(),
+            Some("Sam".to_owned())
+        );
+
+        // Sam's away so it's still Alex's turn
+        actor.db.set_away("Sam", true).unwrap();
+        assert_eq!(
+            actor.on_duty(Activity::WaterPlants).unwrap(),
+            Some("Alex".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_rotation_on_trigger() {
+        let (mut actor, _rx_led, _rx_scheduler, clock) = control_actor();
+        actor
+            .config
+            .activity_configs
+            .get_mut(&Activity::WaterPlants)
+            .and_then(|config| config.rotation.as_mut())
+            .unwrap()
+            .advance = RotationAdvance::OnTrigger;
+        actor.config.addresses = HashMap::from([("Alex".to_owned(), "alex@home".to_owned())]);
+
+        // The first trigger's the first person's, and their reminder goes
+        // to them
+        let now = NaiveDateTime::from_str("2020-01-01T09:00:00").unwrap();
+        actor
+            .handle_message(ControlActorMessage::Activity(Activity::WaterPlants, now))
+            .unwrap();
+        assert_eq!(
+            actor.on_duty(Activity::WaterPlants).unwrap(),
+            Some("Alex".to_owned())
+        );
+        clock.advance(chrono::Duration::hours(1));
+        actor.handle_timeout().unwrap();
+        assert_eq!(
+            *actor.email.sent.borrow(),
+            vec![(
+                "alex@home".to_owned(),
+                "Plants".to_owned(),
+                "It's Alex's turn. Water the plants".to_owned()
+            )]
+        );
+
+        // Doing it doesn't move it on, the next trigger does
+        actor
+            .handle_message(ControlActorMessage::ButtonPress(
+                Button::B4,
+                PressKind::Short,
+                clock.instant(),
+            ))
+            .unwrap();
+        assert_eq!(
+            actor.on_duty(Activity::WaterPlants).unwrap(),
+            Some("Alex".to_owned())
+        );
+        actor
+            .handle_message(ControlActorMessage::Activity(
+                Activity::WaterPlants,
+                clock.now(),
+            ))
+            .unwrap();
+        assert_eq!(
+            actor.on_duty(Activity::WaterPlants).unwrap(),
+            Some("Sam".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_escalation_resets_on_press() {
+        let (mut actor, rx_led, _rx_scheduler, clock) = control_actor();
+
+        let now = NaiveDateTime::from_str("2020-01-01T09:00:00").unwrap();
+        actor
+            .handle_message(ControlActorMessage::Activity(Activity::TakePills, now))
+            .unwrap();
+        clock.advance(chrono::Duration::hours(2));
+        actor.handle_timeout().unwrap();
+        actor
+            .handle_message(ControlActorMessage::ButtonPress(
+                Button::B1,
+                PressKind::Short,
+                clock.instant(),
+            ))
+            .unwrap();
+        assert_eq!(actor.timeout(), None);
+
+        // The next trigger starts again from the bottom of the ladder
+        actor
+            .handle_message(ControlActorMessage::Activity(
+                Activity::TakePills,
+                clock.now(),
+            ))
+            .unwrap();
+        assert_eq!(
+            actor.timeout(),
+            Some(chrono::Duration::hours(2).to_std().unwrap())
+        );
+        expect_messages(&rx_led, 4);
+    }
+
+    #[test]
+    fn test_missed_deadline() {
+        let (mut actor, rx_led, _rx_scheduler, clock) = control_actor();
+
+        let now = NaiveDateTime::from_str("2020-01-01T09:00:00").unwrap();
+        actor
+            .handle_message(ControlActorMessage::Activity(Activity::TakePills, now))
+            .unwrap();
+        // Snoozing doesn't put off the deadline
+        clock.advance(chrono::Duration::hours(9) + chrono::Duration::minutes(30));
+        actor
+            .handle_message(ControlActorMessage::ButtonPress(
+                Button::B1,
+                PressKind::Long,
+                clock.instant(),
+            ))
+            .unwrap();
+        assert_eq!(
+            actor.timeout(),
+            Some(chrono::Duration::minutes(30).to_std().unwrap())
+        );
+
+        clock.advance(chrono::Duration::minutes(30));
+        actor.handle_timeout().unwrap();
+        assert_eq!(actor.timeout(), None);
+        assert_eq!(
+            actor.db.load_activity_state(Activity::TakePills).unwrap(),
+            ActivityState {
+                missed: Some(now),
+                ..ActivityState::default()
+            }
+        );
+
+        // Pressing the button acknowledges the miss
+        actor
+            .handle_message(ControlActorMessage::ButtonPress(
+                Button::B1,
+                PressKind::Short,
+                clock.instant(),
+            ))
+            .unwrap();
+        clock.advance(chrono::Duration::milliseconds(600));
+        actor.handle_timeout().unwrap();
+        assert_eq!(
+            actor.db.load_activity_state(Activity::TakePills).unwrap(),
+            ActivityState::default()
+        );
+
+        let messages = expect_messages(&rx_led, 4);
+        assert_eq!(
+            messages[2..],
+            vec![
+                LedActorMessage::StateChange {
+                    led: Led::L1,
+                    state: LedState::BlinkSlow
+                },
+                LedActorMessage::StateChange {
+                    led: Led::L1,
+                    state: LedState::BlinkTemporary
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_queued_occurrences() {
+        let (mut actor, rx_led, _rx_scheduler, clock) = control_actor();
+        let first = clock.now();
+        for _ in 0..3 {
+            actor
+                .handle_message(ControlActorMessage::Activity(Activity::I, clock.now()))
+                .unwrap();
+            clock.advance(chrono::Duration::days(1));
+        }
+        assert_eq!(
+            expect_messages(&rx_led, 3),
+            vec![
+                LedActorMessage::StateChange {
+                    led: Led::L3,
+                    state: LedState::On
+                },
+                LedActorMessage::StateChange {
+                    led: Led::L3,
+                    state: LedState::BlinkCount(2)
+                },
+                LedActorMessage::StateChange {
+                    led: Led::L3,
+                    state: LedState::BlinkCount(3)
+                },
+            ]
+        );
+        let state = actor.db.load_activity_state(Activity::I).unwrap();
+        assert_eq!(state.pending, Some(first));
+        assert_eq!(state.outstanding(), 3);
+
+        // Each press does the oldest one
+        actor
+            .handle_message(ControlActorMessage::ButtonPress(
+                Button::B3,
+                PressKind::Short,
+                clock.instant(),
+            ))
+            .unwrap();
+        clock.advance(chrono::Duration::seconds(1));
+        actor.handle_timeout().unwrap();
+        assert_eq!(
+            expect_messages(&rx_led, 2),
+            vec![
+                LedActorMessage::StateChange {
+                    led: Led::L3,
+                    state: LedState::BlinkTemporary
+                },
+                LedActorMessage::StateChange {
+                    led: Led::L3,
+                    state: LedState::BlinkCount(2)
+                },
+            ]
+        );
+        let state = actor.db.load_activity_state(Activity::I).unwrap();
+        assert_eq!(state.pending, Some(first + chrono::Duration::days(1)));
+        assert_eq!(state.queued, vec![first + chrono::Duration::days(2)]);
+        let acknowledged: Vec<_> = actor
+            .db
+            .load_activity_events(Activity::I, first, clock.now())
+            .unwrap()
+            .into_iter()
+            .filter(|event| event.kind == EventKind::Acknowledged)
+            .map(|event| event.pending_since)
+            .collect();
+        assert_eq!(acknowledged, vec![Some(first)]);
+    }
+
+    #[test]
+    fn test_press_again_for_next_dose() {
+        let (mut actor, _rx_led, _rx_scheduler, clock) = control_actor();
+        let first = clock.now();
+        for _ in 0..2 {
+            actor
+                .handle_message(ControlActorMessage::Activity(
+                    Activity::TakePills,
+                    clock.now(),
+                ))
+                .unwrap();
+            clock.advance(chrono::Duration::hours(1));
+        }
+
+        // Well within the undo window, but there was another to do
+        for _ in 0..2 {
+            actor
+                .handle_message(ControlActorMessage::ButtonPress(
+                    Button::B1,
+                    PressKind::Short,
+                    clock.instant(),
+                ))
+                .unwrap();
+            clock.advance(chrono::Duration::seconds(1));
+        }
+        assert_eq!(
+            actor
+                .application_state
+                .activity(Activity::TakePills)
+                .outstanding(),
+            0
+        );
+        let kinds: Vec<_> = actor
+            .db
+            .load_activity_events(Activity::TakePills, first, clock.now())
+            .unwrap()
+            .into_iter()
+            .map(|event| (event.kind, event.pending_since))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (EventKind::Triggered, Some(first)),
+                (
+                    EventKind::Triggered,
+                    Some(first + chrono::Duration::hours(1))
+                ),
+                (EventKind::Acknowledged, Some(first)),
+                (
+                    EventKind::Acknowledged,
+                    Some(first + chrono::Duration::hours(1))
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_acknowledge_all_queued() {
+        let (mut actor, rx_led, _rx_scheduler, clock) = control_actor();
+        actor.config.people = vec!["Alex".to_owned(), "Sam".to_owned()];
+        let first = clock.now();
+        for _ in 0..2 {
+            actor
+                .handle_message(ControlActorMessage::Activity(
+                    Activity::WaterPlants,
+                    clock.now(),
+                ))
+                .unwrap();
+            clock.advance(chrono::Duration::minutes(30));
+        }
+        actor
+            .handle_message(ControlActorMessage::ButtonPress(
+                Button::B4,
+                PressKind::Short,
+                clock.instant(),
+            ))
+            .unwrap();
+        clock.advance(chrono::Duration::seconds(1));
+        actor.handle_timeout().unwrap();
+        expect_messages(&rx_led, 3);
+
+        assert_eq!(
+            actor.db.load_activity_state(Activity::WaterPlants).unwrap(),
+            ActivityState::default()
+        );
+        let acknowledged: Vec<_> = actor
+            .db
+            .load_activity_events(Activity::WaterPlants, first, clock.now())
+            .unwrap()
+            .into_iter()
+            .filter(|event| event.kind == EventKind::Acknowledged)
+            .map(|event| (event.pending_since, event.who))
+            .collect();
+        assert_eq!(
+            acknowledged,
+            vec![
+                (Some(first), Some("Alex".to_owned())),
+                (
+                    Some(first + chrono::Duration::minutes(30)),
+                    Some("Alex".to_owned())
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_missed_deadline_with_queue() {
+        let (mut actor, rx_led, _rx_scheduler, clock) = control_actor();
+        let first = clock.now();
+        for _ in 0..2 {
+            actor
+                .handle_message(ControlActorMessage::Activity(
+                    Activity::TakePills,
+                    clock.now(),
+                ))
+                .unwrap();
+            clock.advance(chrono::Duration::hours(1));
+        }
+        expect_messages(&rx_led, 2);
+
+        // Only the first one's deadline has passed, the second one's
+        // escalations that are already due aren't sent again
+        clock.set_now(first + chrono::Duration::hours(10));
+        actor.handle_timeout().unwrap();
+        let state = actor.db.load_activity_state(Activity::TakePills).unwrap();
+        assert_eq!(state.missed, Some(first));
+        assert_eq!(state.pending, Some(first + chrono::Duration::hours(1)));
+        assert!(state.queued.is_empty());
+        assert_eq!(
+            expect_messages(&rx_led, 1),
+            vec![LedActorMessage::StateChange {
+                led: Led::L1,
+                state: LedState::BlinkFast
+            }]
+        );
+        assert!(actor.email.sent.borrow().is_empty());
+    }
+
+    // The litter tray is due at 18:00 each day.
+    fn litter_tray_schedule() -> HashMap<Activity, Schedule> {
+        HashMap::from([(
+            Activity::CleanLitterTray,
+            Schedule::Daily(DailySchedule::new(
+                NaiveTime::from_str("18:00:00").unwrap(),
+                every_day(),
+            )),
+        )])
+    }
+
+    // And can be done up to 12 hours before.
+    fn complete_litter_tray_early(actor: &mut ControlActor<FakeEmail>) {
+        actor
+            .config
+            .activity_configs
+            .get_mut(&Activity::CleanLitterTray)
+            .unwrap()
+            .complete_early = Some(chrono::Duration::hours(12));
+        actor.config.schedules = litter_tray_schedule();
+    }
+
+    #[test]
+    fn test_completed_early() {
+        let (mut actor, rx_led, rx_scheduler, clock) = control_actor();
+        complete_litter_tray_early(&mut actor);
+        let started = clock.now();
+        let trigger = NaiveDateTime::from_str("2020-01-01T18:00:00").unwrap();
+        let press = |actor: &mut ControlActor<FakeEmail>| {
+            actor
+                .handle_message(ControlActorMessage::ButtonPress(
+                    Button::B2,
+                    PressKind::Short,
+                    clock.instant(),
+                ))
+                .unwrap();
+        };
+
+        // 9 hours before it's due, within the 12 hour look-ahead
+        press(&mut actor);
+        assert!(matches!(
+            rx_scheduler.try_recv(),
+            Ok(SchedulerActorMessage::Skip(Activity::CleanLitterTray, t)) if t == trigger
+        ));
+        assert_eq!(
+            actor
+                .db
+                .load_activity_state(Activity::CleanLitterTray)
+                .unwrap()
+                .completed_early,
+            Some(trigger)
+        );
+
+        // Oops, pressed it by mistake
+        press(&mut actor);
+        assert!(matches!(
+            rx_scheduler.try_recv(),
+            Ok(SchedulerActorMessage::Unskip(Activity::CleanLitterTray))
+        ));
+        assert_eq!(
+            actor
+                .db
+                .load_activity_state(Activity::CleanLitterTray)
+                .unwrap(),
+            ActivityState::default()
+        );
+
+        // For real this time, then pressing again doesn't do tomorrow's too
+        clock.advance(chrono::Duration::seconds(10));
+        press(&mut actor);
+        clock.advance(chrono::Duration::seconds(10));
+        press(&mut actor);
+        assert!(matches!(
+            rx_scheduler.try_recv(),
+            Ok(SchedulerActorMessage::Skip(Activity::CleanLitterTray, t)) if t == trigger
+        ));
+        assert!(rx_scheduler.try_recv().is_err());
+
+        // Forgotten about once it would have fired
+        assert_eq!(
+            actor.timeout(),
+            Some((trigger - clock.now()).to_std().unwrap())
+        );
+        clock.set_now(trigger);
+        actor.handle_timeout().unwrap();
+        assert_eq!(
+            actor
+                .db
+                .load_activity_state(Activity::CleanLitterTray)
+                .unwrap(),
+            ActivityState::default()
+        );
+        assert_eq!(actor.timeout(), None);
+        // Undoing leaves it off, so just the three presses
+        for message in expect_messages(&rx_led, 3) {
+            assert_eq!(
+                message,
+                LedActorMessage::StateChange {
+                    led: Led::L2,
+                    state: LedState::BlinkTemporary
+                }
+            );
+        }
+
+        let kinds: Vec<_> = actor
+            .db
+            .load_activity_events(Activity::CleanLitterTray, started, trigger)
+            .unwrap()
+            .into_iter()
+            .map(|event| (event.kind, event.pending_since))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (EventKind::CompletedEarly, Some(trigger)),
+                (EventKind::Undone, Some(trigger)),
+                (EventKind::CompletedEarly, Some(trigger)),
+                (EventKind::Acknowledged, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_state_replays_from_events() {
+        let (mut actor, _rx_led, _rx_scheduler, clock) = control_actor();
+        actor.config.people = vec!["Alex".to_owned(), "Sam".to_owned()];
+        let press = |actor: &mut ControlActor<FakeEmail>, press_kind| {
+            actor
+                .handle_message(ControlActorMessage::ButtonPress(
+                    Button::B1,
+                    press_kind,
+                    clock.instant(),
+                ))
+                .unwrap();
+        };
+        // The saved state can be thrown away and rebuilt from the events
+        let assert_replays = |actor: &ControlActor<FakeEmail>| {
+            for activity in Activity::ALL {
+                assert_eq!(
+                    actor.db.rebuild_activity_state(activity).unwrap(),
+                    ActivityState {
+                        escalation_level: 0,
+                        ..actor.application_state.activity(activity)
+                    }
+                );
+            }
+        };
+
+        actor
+            .handle_message(ControlActorMessage::Activity(
+                Activity::TakePills,
+                clock.now(),
+            ))
+            .unwrap();
+        clock.advance(chrono::Duration::hours(1));
+        actor
+            .handle_message(ControlActorMessage::Activity(
+                Activity::TakePills,
+                clock.now(),
+            ))
+            .unwrap();
+        press(&mut actor, PressKind::Long);
+        assert_replays(&actor);
+
+        // The first one's missed
+        clock.set_now(NaiveDateTime::from_str("2020-01-01T19:00:00").unwrap());
+        actor.handle_timeout().unwrap();
+        assert_replays(&actor);
+
+        // The second's pressed by mistake
+        press(&mut actor, PressKind::Short);
+        clock.advance(chrono::Duration::seconds(2));
+        press(&mut actor, PressKind::Short);
+        assert_replays(&actor);
+
+        // Then done by Sam
+        clock.advance(chrono::Duration::seconds(10));
+        press(&mut actor, PressKind::Short);
+        press(&mut actor, PressKind::Short);
+        clock.advance(chrono::Duration::seconds(1));
+        actor.handle_timeout().unwrap();
+        assert_replays(&actor);
+        assert_eq!(
+            actor.application_state.activity(Activity::TakePills),
+            ActivityState::default()
+        );
+    }
+
+    #[test]
+    fn test_follow_ups() {
+        let (mut actor, _rx_led, rx_scheduler, clock) = control_actor();
+        let press = |actor: &mut ControlActor<FakeEmail>| {
+            actor
+                .handle_message(ControlActorMessage::ButtonPress(
+                    Button::B1,
+                    PressKind::Short,
+                    clock.instant(),
+                ))
+                .unwrap();
+        };
+        let due_at = NaiveDateTime::from_str("2020-01-01T09:30:01").unwrap();
+        let is_eat_at = |message, at| {
+            matches!(
+                message,
+                Ok(SchedulerActorMessage::AddJob(spec))
+                    if spec.task() == Task::Trigger(Activity::Eat)
+                        && matches!(spec.schedule(), Schedule::Once(once) if *once == at)
+            )
+        };
+
+        // Nothing pending, nothing to follow up
+        press(&mut actor);
+        clock.advance(chrono::Duration::seconds(1));
+        actor.handle_timeout().unwrap();
+        assert!(rx_scheduler.try_recv().is_err());
+
+        actor
+            .handle_message(ControlActorMessage::Activity(
+                Activity::TakePills,
+                clock.now(),
+            ))
+            .unwrap();
+        press(&mut actor);
+        clock.advance(chrono::Duration::seconds(1));
+        actor.handle_timeout().unwrap();
+        assert!(is_eat_at(rx_scheduler.try_recv(), due_at));
+
+        // Undoing it cancels the follow-up
+        press(&mut actor);
+        assert!(matches!(
+            rx_scheduler.try_recv(),
+            Ok(SchedulerActorMessage::Cancel(Activity::Eat, at)) if at == due_at
+        ));
+        assert_eq!(actor.db.load_follow_ups().unwrap(), vec![]);
+
+        // Done for real, which survives a restart
+        clock.advance(chrono::Duration::seconds(10));
+        press(&mut actor);
+        clock.advance(chrono::Duration::seconds(1));
+        actor.handle_timeout().unwrap();
+        let due_at = NaiveDateTime::from_str("2020-01-01T09:30:12").unwrap();
+        assert!(is_eat_at(rx_scheduler.try_recv(), due_at));
+        actor.startup().unwrap();
+        assert!(is_eat_at(rx_scheduler.try_recv(), due_at));
+
+        // There's no button, so it's just an email
+        actor
+            .handle_message(ControlActorMessage::Activity(Activity::Eat, due_at))
+            .unwrap();
+        assert_eq!(
+            *actor.email.sent.borrow(),
+            vec![(
+                "default".to_owned(),
+                "Eat".to_owned(),
+                "Eat something".to_owned()
+            )]
+        );
+        assert_eq!(actor.db.load_follow_ups().unwrap(), vec![]);
+        assert_eq!(
+            actor.application_state.activity(Activity::Eat),
+            ActivityState::default()
+        );
+    }
+
+    #[test]
+    fn test_stock() {
+        let (mut actor, _rx_led, _rx_scheduler, clock) = control_actor();
+        // Projected from the schedule
+        actor.config.schedules = litter_tray_schedule();
+        actor
+            .config
+            .activity_configs
+            .get_mut(&Activity::CleanLitterTray)
+            .unwrap()
+            .acknowledge = Acknowledge::All;
+        let clean = |actor: &mut ControlActor<FakeEmail>| {
+            actor
+                .handle_message(ControlActorMessage::Activity(
+                    Activity::CleanLitterTray,
+                    clock.now(),
+                ))
+                .unwrap();
+            actor
+                .handle_message(ControlActorMessage::ButtonPress(
+                    Button::B2,
+                    PressKind::Short,
+                    clock.instant(),
+                ))
+                .unwrap();
+        };
+        let remaining = |actor: &ControlActor<FakeEmail>| {
+            actor
+                .db
+                .load_stock(Activity::CleanLitterTray)
+                .unwrap()
+                .unwrap()
+                .remaining
+        };
+
+        actor.db.restock(Activity::CleanLitterTray, 20).unwrap();
+        // Enough for the next three days' cleans
+        clean(&mut actor);
+        assert_eq!(remaining(&actor), 15);
+        assert!(actor.email.sent.borrow().is_empty());
+
+        // Undoing puts it back
+        actor
+            .handle_message(ControlActorMessage::ButtonPress(
+                Button::B2,
+                PressKind::Short,
+                clock.instant(),
+            ))
+            .unwrap();
+        assert_eq!(remaining(&actor), 20);
+
+        clock.advance(chrono::Duration::seconds(10));
+        actor.handle_timeout().unwrap();
+        clean(&mut actor);
+        clock.advance(chrono::Duration::seconds(10));
+        clean(&mut actor);
+        assert_eq!(remaining(&actor), 10);
+        assert_eq!(
+            *actor.email.sent.borrow(),
+            vec![(
+                "default".to_owned(),
+                "Running low on litter".to_owned(),
+                "Only 10 litter left, that won't last 3 days.  Time to restock!".to_owned()
+            )]
+        );
+
+        // Undoing puts it back above the threshold, so it'll ask again
+        actor
+            .handle_message(ControlActorMessage::ButtonPress(
+                Button::B2,
+                PressKind::Short,
+                clock.instant(),
+            ))
+            .unwrap();
+        assert_eq!(remaining(&actor), 15);
+        assert!(
+            !actor
+                .db
+                .load_stock(Activity::CleanLitterTray)
+                .unwrap()
+                .unwrap()
+                .refill_notified
+        );
+        clock.advance(chrono::Duration::seconds(10));
+        actor.handle_timeout().unwrap();
+        clean(&mut actor);
+        assert_eq!(remaining(&actor), 10);
+        assert_eq!(actor.email.sent.borrow().len(), 2);
+
+        // Only asked once until it's restocked
+        clock.advance(chrono::Duration::seconds(10));
+        clean(&mut actor);
+        assert_eq!(remaining(&actor), 5);
+        assert_eq!(actor.email.sent.borrow().len(), 2);
+        assert!(
+            !actor
+                .db
+                .restock(Activity::CleanLitterTray, 20)
+                .unwrap()
+                .refill_notified
+        );
+    }
+
+    #[test]
+    fn test_hooks() {
+        let (actor, _rx_led, _rx_scheduler, clock) = control_actor();
+        let (tx_hook, rx_hook) = mpsc::channel();
+        let mut actor = actor.with_hooks(tx_hook);
+        actor.config.people = vec!["Alex".to_owned(), "Sam".to_owned()];
+        actor
+            .config
+            .activity_configs
+            .get_mut(&Activity::TakePills)
+            .unwrap()
+            .rotation = Some(Rotation {
+            people: actor.config.people.clone(),
+            advance: RotationAdvance::OnTrigger,
+        });
+        let run = |event: &str, pending_since: &str, who: &str| HookActorMessage::Run {
+            command: format!("./hooks/{event}"),
+            timeout: Duration::from_secs(30),
+            env: vec![
+                ("FOURBUTTONS_ACTIVITY", "take_pills".to_owned()),
+                ("FOURBUTTONS_EVENT", event.to_owned()),
+                ("FOURBUTTONS_PENDING_SINCE", pending_since.to_owned()),
+                ("FOURBUTTONS_WHO", who.to_owned()),
+            ],
+        };
+
+        actor
+            .handle_message(ControlActorMessage::Activity(
+                Activity::TakePills,
+                clock.now(),
+            ))
+            .unwrap();
+        assert_eq!(
+            rx_hook.try_recv(),
+            Ok(run("triggered", "2020-01-01 09:00:00", "Alex"))
+        );
+
+        // Once we know who did it
+        for _ in 0..2 {
+            actor
+                .handle_message(ControlActorMessage::ButtonPress(
+                    Button::B1,
+                    PressKind::Short,
+                    clock.instant(),
+                ))
+                .unwrap();
+        }
+        assert!(rx_hook.try_recv().is_err());
+        clock.advance(chrono::Duration::seconds(1));
+        actor.handle_timeout().unwrap();
+        assert_eq!(
+            rx_hook.try_recv(),
+            Ok(run("acknowledged", "2020-01-01 09:00:00", "Sam"))
+        );
+
+        // So it can take back whatever the acknowledged hook did
+        actor
+            .handle_message(ControlActorMessage::ButtonPress(
+                Button::B1,
+                PressKind::Short,
+                clock.instant(),
+            ))
+            .unwrap();
+        assert_eq!(
+            rx_hook.try_recv(),
+            Ok(run("undone", "2020-01-01 09:00:00", ""))
+        );
+
+        // Nothing hooked on to other kinds or activities, or to presses
+        // with nothing to do
+        actor
+            .handle_message(ControlActorMessage::ButtonPress(
+                Button::B1,
+                PressKind::Long,
+                clock.instant(),
+            ))
+            .unwrap();
+        actor
+            .handle_message(ControlActorMessage::Activity(Activity::I, clock.now()))
+            .unwrap();
+        assert!(rx_hook.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_hooks_escalated() {
+        let (actor, _rx_led, _rx_scheduler, clock) = control_actor();
+        let (tx_hook, rx_hook) = mpsc::channel();
+        let mut actor = actor.with_hooks(tx_hook);
+        actor
+            .config
+            .activity_configs
+            .get_mut(&Activity::TakePills)
+            .unwrap()
+            .hooks = vec![Hook {
+            on: EventKind::Escalated,
+            command: "./hooks/escalated".to_owned(),
+            timeout: Duration::from_secs(30),
+        }];
+        let escalate = |actor: &mut ControlActor<FakeEmail>, hours| {
+            clock.advance(chrono::Duration::hours(hours));
+            actor.handle_timeout().unwrap();
+            match rx_hook.try_recv() {
+                Ok(HookActorMessage::Run { command, env, .. }) => {
+                    assert_eq!(command, "./hooks/escalated");
+                    env.into_iter()
+                        .find(|(key, _)| *key == "FOURBUTTONS_EVENT")
+                        .unwrap()
+                        .1
+                }
+                other => panic!("Expected the escalated hook, got {other:?}"),
+            }
+        };
+
+        actor
+            .handle_message(ControlActorMessage::Activity(
+                Activity::TakePills,
+                clock.now(),
+            ))
+            .unwrap();
+        assert_eq!(escalate(&mut actor, 2), "escalated");
+        // The emails are escalations too, whether they're sent or not
+        assert_eq!(escalate(&mut actor, 3), "notified");
+        actor.email.failing.set(true);
+        assert_eq!(escalate(&mut actor, 1), "notify_failed");
+    }
+
+    #[test]
+    fn test_hooks_completed_early() {
+        let (actor, rx_led, _rx_scheduler, clock) = control_actor();
+        let (tx_hook, rx_hook) = mpsc::channel();
+        let mut actor = actor.with_hooks(tx_hook);
+        complete_litter_tray_early(&mut actor);
+        actor
+            .config
+            .activity_configs
+            .get_mut(&Activity::CleanLitterTray)
+            .unwrap()
+            .hooks = take_pills_config().hooks;
+        let press = |actor: &mut ControlActor<FakeEmail>| {
+            actor
+                .handle_message(ControlActorMessage::ButtonPress(
+                    Button::B2,
+                    PressKind::Short,
+                    clock.instant(),
+                ))
+                .unwrap();
+        };
+
+        // Doing it early counts as acknowledging it
+        press(&mut actor);
+        assert!(matches!(
+            rx_hook.try_recv(),
+            Ok(HookActorMessage::Run { command, env, .. })
+                if command == "./hooks/acknowledged"
+                    && env.contains(&("FOURBUTTONS_EVENT", "completed_early".to_owned()))
+        ));
+
+        // Hooks are optional, so it carries on without them
+        drop(rx_hook);
+        clock.advance(chrono::Duration::seconds(10));
+        actor
+            .handle_message(ControlActorMessage::Activity(
+                Activity::CleanLitterTray,
+                clock.now(),
+            ))
+            .unwrap();
+        assert_eq!(
+            expect_messages(&rx_led, 2),
+            vec![
+                LedActorMessage::StateChange {
+                    led: Led::L2,
+                    state: LedState::BlinkTemporary
+                },
+                LedActorMessage::StateChange {
+                    led: Led::L2,
+                    state: LedState::On
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_timer() {
+        let (mut actor, rx_led, rx_scheduler, clock) = control_actor();
+        let press = |actor: &mut ControlActor<FakeEmail>, press_kind| {
+            actor
+                .handle_message(ControlActorMessage::ButtonPress(
+                    Button::B4,
+                    press_kind,
+                    clock.instant(),
+                ))
+                .unwrap();
+        };
+        let is_timer_job = |message, ends| {
+            matches!(
+                message,
+                Ok(SchedulerActorMessage::AddJob(spec))
+                    if spec.task() == Task::EndTimer(Activity::WaterPlants)
+                        && matches!(spec.schedule(), Schedule::Once(once) if *once == ends)
+            )
+        };
+        let ends = NaiveDateTime::from_str("2020-01-01T09:04:00").unwrap();
+
+        press(&mut actor, PressKind::Long);
+        assert!(is_timer_job(rx_scheduler.try_recv(), ends));
+        assert_eq!(actor.timeout(), None);
+        // Survives a restart
+        assert_eq!(
+            actor.db.load_activity_state(Activity::WaterPlants).unwrap(),
+            ActivityState {
+                timer: Some(ends),
+                ..ActivityState::default()
+            }
+        );
+        actor.startup().unwrap();
+        assert!(is_timer_job(rx_scheduler.try_recv(), ends));
+        expect_messages(&rx_led, 5);
+
+        // The job firing ends the timer, rather than the plants needing
+        // watering
+        clock.set_now(ends);
+        actor
+            .handle_message(ControlActorMessage::TimerEnded(Activity::WaterPlants, ends))
+            .unwrap();
+        assert_eq!(
+            actor
+                .application_state
+                .activity(Activity::WaterPlants)
+                .status(&[]),
+            Status::TimerFinished
+        );
+
+        // Pressing again dismisses it, it doesn't count as watering them
+        press(&mut actor, PressKind::Short);
+        clock.advance(chrono::Duration::seconds(1));
+        actor.handle_timeout().unwrap();
+        assert_eq!(
+            actor.db.load_activity_state(Activity::WaterPlants).unwrap(),
+            ActivityState::default()
+        );
+        assert_eq!(
+            expect_messages(&rx_led, 2),
+            vec![
+                LedActorMessage::StateChange {
+                    led: Led::L4,
+                    state: LedState::BlinkFast
+                },
+                LedActorMessage::StateChange {
+                    led: Led::L4,
+                    state: LedState::BlinkTemporary
+                },
+            ]
+        );
+        let kinds: Vec<_> = actor
+            .db
+            .load_activity_events(
+                Activity::WaterPlants,
+                ends - chrono::Duration::hours(1),
+                clock.now(),
+            )
+            .unwrap()
+            .into_iter()
+            .map(|event| event.kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![EventKind::TimerStarted, EventKind::TimerCleared]
+        );
+        assert!(rx_scheduler.try_recv().is_err());
+
+        // Stopping one that's still running cancels its job
+        press(&mut actor, PressKind::Long);
+        let ends = clock.now() + chrono::Duration::minutes(4);
+        assert!(is_timer_job(rx_scheduler.try_recv(), ends));
+        press(&mut actor, PressKind::Short);
+        assert!(matches!(
+            rx_scheduler.try_recv(),
+            Ok(SchedulerActorMessage::Cancel(Activity::WaterPlants, at)) if at == ends
+        ));
+    }
+
+    #[test]
+    fn test_trigger_before_timer_ended() {
+        let (mut actor, _rx_led, rx_scheduler, clock) = control_actor();
+        actor
+            .handle_message(ControlActorMessage::ButtonPress(
+                Button::B4,
+                PressKind::Long,
+                clock.instant(),
+            ))
+            .unwrap();
+        let ends = NaiveDateTime::from_str("2020-01-01T09:04:00").unwrap();
+        assert!(matches!(
+            rx_scheduler.try_recv(),
+            Ok(SchedulerActorMessage::AddJob(_))
+        ));
+
+        // The clock jumps past the end of the timer, and the plants' usual
+        // trigger gets here before the timer's job does
+        let now = ends + chrono::Duration::hours(1);
+        clock.set_now(now);
+        actor
+            .handle_message(ControlActorMessage::Activity(Activity::WaterPlants, now))
+            .unwrap();
+        actor
+            .handle_message(ControlActorMessage::TimerEnded(Activity::WaterPlants, now))
+            .unwrap();
+
+        // Both the trigger and the end of the timer count
+        let state = actor.application_state.activity(Activity::WaterPlants);
+        assert_eq!(state.pending, Some(now));
+        assert!(state.timer_finished);
+        let kinds: Vec<_> = actor
+            .db
+            .load_activity_events(
+                Activity::WaterPlants,
+                ends,
+                clock.now() + chrono::Duration::seconds(1),
+            )
+            .unwrap()
+            .into_iter()
+            .map(|event| event.kind)
+            .collect();
+        assert_eq!(kinds, vec![EventKind::Triggered]);
+    }
+
+    #[test]
+    fn test_timer_ended_while_down() {
+        let (mut actor, rx_led, rx_scheduler, clock) = control_actor();
+        actor
+            .application_state
+            .activity_mut(Activity::WaterPlants)
+            .timer = Some(clock.now() - chrono::Duration::minutes(1));
+
+        actor.startup().unwrap();
+        assert!(rx_scheduler.try_recv().is_err());
+        assert!(
+            expect_messages(&rx_led, 4).contains(&LedActorMessage::StateChange {
+                led: Led::L4,
+                state: LedState::BlinkFast
+            })
+        );
+    }
+
+    #[test]
+    fn test_reconcile_leds() {
+        let (mut actor, rx_led, _rx_scheduler, clock) = control_actor();
+        let now = clock.now();
+        *actor
+            .application_state
+            .activity_mut(Activity::CleanLitterTray) = ActivityState {
+            pending: Some(now),
+            ..ActivityState::default()
+        };
+        *actor.application_state.activity_mut(Activity::TakePills) = ActivityState {
+            pending: Some(now),
+            snoozed_until: Some(now + chrono::Duration::minutes(30)),
+            ..ActivityState::default()
+        };
+
+        // Every LED is restored after a restart
+        actor.startup().unwrap();
+        assert_eq!(
+            expect_messages(&rx_led, 4),
+            vec![
+                LedActorMessage::StateChange {
+                    led: Led::L1,
+                    state: LedState::Off
+                },
+                LedActorMessage::StateChange {
+                    led: Led::L2,
+                    state: LedState::On
+                },
+                LedActorMessage::StateChange {
+                    led: Led::L3,
+                    state: LedState::Off
+                },
+                LedActorMessage::StateChange {
+                    led: Led::L4,
+                    state: LedState::Off
+                },
+            ]
+        );
+
+        // They're all sent again even though nothing's changed here, in case
+        // the LedActor missed a message or restarted with everything off
+        actor
+            .handle_message(ControlActorMessage::ReconcileLeds)
+            .unwrap();
+        assert_eq!(
+            expect_messages(&rx_led, 4),
+            vec![
+                LedActorMessage::StateChange {
+                    led: Led::L1,
+                    state: LedState::Off
+                },
+                LedActorMessage::StateChange {
+                    led: Led::L2,
+                    state: LedState::On
+                },
+                LedActorMessage::StateChange {
+                    led: Led::L3,
+                    state: LedState::Off
+                },
+                LedActorMessage::StateChange {
+                    led: Led::L4,
+                    state: LedState::Off
+                },
+            ]
+        );
+
+        // And anything that's drifted from the state is put right
+        actor
+            .application_state
+            .activity_mut(Activity::CleanLitterTray)
+            .pending = None;
+        actor
+            .handle_message(ControlActorMessage::ReconcileLeds)
+            .unwrap();
+        assert!(
+            expect_messages(&rx_led, 4).contains(&LedActorMessage::StateChange {
+                led: Led::L2,
+                state: LedState::Off
+            })
         );
     }
 }
@@ -0,0 +1,263 @@
+use std::{collections::HashMap, fmt::Write};
+
+use anyhow::Result;
+use chrono::{Duration, NaiveDateTime};
+use log::error;
+
+use crate::{
+    activity::Activity,
+    appdb::AppDb,
+    email::Emailer,
+    rotation::{self, Rotation},
+    stats::summarise,
+};
+
+use super::actor::Actor;
+
+/// A summary of the past week, emailed when the scheduler says so.
+pub(crate) struct DigestConfig {
+    /// Empty means the usual recipient.
+    pub(crate) recipients: Vec<String>,
+    pub(crate) activities: Vec<Activity>,
+    /// Anything done within this long of being triggered counts as on time.
+    pub(crate) on_time: Duration,
+    pub(crate) rotations: HashMap<Activity, Rotation>,
+}
+
+pub(crate) enum DigestActorMessage {
+    // Send the digest for the week up to this time
+    Send(NaiveDateTime),
+}
+
+pub(crate) struct DigestActor<TEmail>
+where
+    TEmail: Emailer,
+{
+    config: DigestConfig,
+    db: AppDb,
+    email: TEmail,
+}
+
+impl<TEmail> DigestActor<TEmail>
+where
+    TEmail: Emailer,
+{
+    pub(crate) fn new(config: DigestConfig, db: AppDb, email: TEmail) -> Self {
+        Self { config, db, email }
+    }
+
+    fn digest(&self, now: NaiveDateTime) -> Result<String> {
+        let state = self.db.load_application_state()?;
+        let mut message = String::new();
+        for activity in &self.config.activities {
+            let events = self
+                .db
+                .load_activity_events(*activity, now - Duration::weeks(1), now)?;
+            let summary = summarise(&events, self.config.on_time);
+            writeln!(
+                message,
+                "{}: triggered {} times, {} on time, {} late, {} missed.",
+                activity.name(),
+                summary.triggered,
+                summary.on_time,
+                summary.late,
+                summary.missed
+            )?;
+
+            let activity_state = state.activity(*activity);
+            if let Some(pending_since) = activity_state.pending {
+                write!(message, "  Pending since {pending_since}")?;
+                if !activity_state.queued.is_empty() {
+                    write!(message, " and {} more since", activity_state.queued.len())?;
+                }
+                if let Some(snoozed_until) = activity_state.snoozed_until {
+                    write!(message, ", snoozed until {snoozed_until}")?;
+                }
+                writeln!(message, ".")?;
+            } else if let Some(missed) = activity_state.missed {
+                writeln!(message, "  Missed the one from {missed}.")?;
+            } else if let Some(completed_early) = activity_state.completed_early {
+                writeln!(message, "  Already done the one due {completed_early}.")?;
+            } else {
+                writeln!(message, "  Nothing pending.")?;
+            }
+            if let Some(rotation) = self.config.rotations.get(activity) {
+                match rotation::on_duty(&self.db, *activity, rotation)? {
+                    Some(person) => writeln!(message, "  {person}'s turn next.")?,
+                    None => writeln!(message, "  Everyone's away.")?,
+                }
+            }
+        }
+        Ok(message)
+    }
+
+    fn send_digest(&self, now: NaiveDateTime) -> Result<()> {
+        let title = "Four Buttons weekly digest";
+        let message = self.digest(now)?;
+        let results = if self.config.recipients.is_empty() {
+            vec![self.email.send(title, &message)]
+        } else {
+            self.config
+                .recipients
+                .iter()
+                .map(|to| self.email.send_to(to, title, &message))
+                .collect()
+        };
+        for result in results {
+            if let Err(err) = result {
+                error!("Failed to send email {:?}", err);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<TEmail> Actor<DigestActorMessage> for DigestActor<TEmail>
+where
+    TEmail: Emailer,
+{
+    fn startup(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn handle_message(&mut self, msg: DigestActorMessage) -> Result<bool> {
+        match msg {
+            DigestActorMessage::Send(now) => self.send_digest(now)?,
+        }
+
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, str::FromStr};
+
+    use chrono::{Duration, NaiveDateTime};
+
+    use crate::{
+        activity::Activity,
+        actor::actor::Actor,
+        appdb::AppDb,
+        application_state::ActivityState,
+        email::testhelper::FakeEmail,
+        event::{Event, EventKind, EventSource},
+        rotation::{Rotation, RotationAdvance},
+    };
+
+    use super::{DigestActor, DigestActorMessage, DigestConfig};
+
+    fn digest_actor(recipients: Vec<String>) -> DigestActor<FakeEmail> {
+        let db = AppDb::new_tmp();
+        db.run_migrations().unwrap();
+        let config = DigestConfig {
+            recipients,
+            activities: vec![Activity::TakePills, Activity::WaterPlants],
+            on_time: Duration::hours(2),
+            rotations: HashMap::from([(
+                Activity::WaterPlants,
+                Rotation {
+                    people: vec!["Alex".to_owned(), "Sam".to_owned()],
+                    advance: RotationAdvance::OnCompletion,
+                },
+            )]),
+        };
+        DigestActor::new(config, db, FakeEmail::new())
+    }
+
+    fn event(kind: EventKind, occurred_at: &str, pending_since: &str) -> Event {
+        Event {
+            activity: Activity::TakePills,
+            kind,
+            occurred_at: NaiveDateTime::from_str(occurred_at).unwrap(),
+            pending_since: Some(NaiveDateTime::from_str(pending_since).unwrap()),
+            source: EventSource::System,
+            who: None,
+            detail: None,
+        }
+    }
+
+    #[test]
+    fn sends_weekly_digest() {
+        let mut actor = digest_actor(vec![]);
+        for event in [
+            // Last week's doesn't count
+            event(
+                EventKind::Triggered,
+                "2019-12-29T06:00:00",
+                "2019-12-29T06:00:00",
+            ),
+            event(
+                EventKind::Triggered,
+                "2020-01-04T06:00:00",
+                "2020-01-04T06:00:00",
+            ),
+            event(
+                EventKind::Acknowledged,
+                "2020-01-04T07:00:00",
+                "2020-01-04T06:00:00",
+            ),
+            event(
+                EventKind::Triggered,
+                "2020-01-05T06:00:00",
+                "2020-01-05T06:00:00",
+            ),
+        ] {
+            actor.db.insert_event(&event).unwrap();
+        }
+        actor
+            .db
+            .update_activity_state(
+                Activity::TakePills,
+                &ActivityState {
+                    pending: Some(NaiveDateTime::from_str("2020-01-05T06:00:00").unwrap()),
+                    ..ActivityState::default()
+                },
+            )
+            .unwrap();
+        actor
+            .db
+            .update_on_duty(Activity::WaterPlants, "Sam")
+            .unwrap();
+
+        actor
+            .handle_message(DigestActorMessage::Send(
+                NaiveDateTime::from_str("2020-01-05T18:00:00").unwrap(),
+            ))
+            .unwrap();
+
+        assert_eq!(
+            *actor.email.sent.borrow(),
+            vec![(
+                "default".to_owned(),
+                "Four Buttons weekly digest".to_owned(),
+                "Take pills: triggered 2 times, 1 on time, 0 late, 0 missed.
+  Pending since 2020-01-05 06:00:00.
+Water plants: triggered 0 times, 0 on time, 0 late, 0 missed.
+  Nothing pending.
+  Sam's turn next.
+"
+                .to_owned()
+            )]
+        );
+    }
+
+    #[test]
+    fn sends_to_each_recipient() {
+        let mut actor = digest_actor(vec!["a@example.com".to_owned(), "b@example.com".to_owned()]);
+        actor
+            .handle_message(DigestActorMessage::Send(
+                NaiveDateTime::from_str("2020-01-05T18:00:00").unwrap(),
+            ))
+            .unwrap();
+        let recipients: Vec<String> = actor
+            .email
+            .sent
+            .borrow()
+            .iter()
+            .map(|(to, _, _)| to.clone())
+            .collect();
+        assert_eq!(recipients, vec!["a@example.com", "b@example.com"]);
+    }
+}
@@ -0,0 +1,51 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use log::{error, info, warn};
+
+use crate::command;
+
+use super::actor::Actor;
+
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) enum HookActorMessage {
+    // Run a hook's command, with these extra environment variables
+    Run {
+        command: String,
+        timeout: Duration,
+        env: Vec<(&'static str, String)>,
+    },
+}
+
+/// Runs hooks on its own thread, so a slow one doesn't hold up the
+/// buttons.
+pub(crate) struct HookActor {}
+
+impl HookActor {
+    pub(crate) fn new() -> Self {
+        Self {}
+    }
+}
+
+impl Actor<HookActorMessage> for HookActor {
+    fn startup(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn handle_message(&mut self, msg: HookActorMessage) -> Result<bool> {
+        match msg {
+            HookActorMessage::Run {
+                command,
+                timeout,
+                env,
+            } => match command::run_with_timeout(&command, &env, timeout) {
+                Ok(status) if status.success() => info!("Hook {} {}", command, status),
+                Ok(status) => warn!("Hook {} {}", command, status),
+                // Not worth stopping for
+                Err(err) => error!("Hook failed {:?}", err),
+            },
+        }
+
+        Ok(false)
+    }
+}
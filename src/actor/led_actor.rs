@@ -1,5 +1,6 @@
 use crate::{
     actor::actor::Actor,
+    clock::SharedClock,
     ledstrategy::{self, LedState, LedStrategies},
     rpi::{Led, RpiOutput},
 };
@@ -15,12 +16,17 @@ pub(crate) enum LedActorMessage {
 pub(crate) struct LedActor {
     rpi: Box<dyn RpiOutput + Send>,
     strategies: LedStrategies,
+    clock: SharedClock,
 }
 
 impl LedActor {
-    pub(crate) fn new(mut rpi: Box<dyn RpiOutput + Send>) -> LedActor {
+    pub(crate) fn new(mut rpi: Box<dyn RpiOutput + Send>, clock: SharedClock) -> LedActor {
         let strategies = ledstrategy::LedStrategies::all_off(&mut *rpi);
-        Self { rpi, strategies }
+        Self {
+            rpi,
+            strategies,
+            clock,
+        }
     }
 }
 
@@ -31,9 +37,10 @@ impl Actor<LedActorMessage> for LedActor {
                 self.strategies.tick(instant, &mut *self.rpi);
             }
             LedActorMessage::StateChange { led, state } => {
-                self.strategies.update(&mut *self.rpi, led, state);
+                self.strategies
+                    .update(&mut *self.rpi, led, state, self.clock.instant());
             }
-        };
+        }
 
         Ok(false)
     }
@@ -1,6 +1,10 @@
 #[allow(clippy::module_inception)]
 pub(crate) mod actor;
+pub(crate) mod clock_watch_actor;
+pub(crate) mod condition_actor;
 pub(crate) mod control_actor;
+pub(crate) mod digest_actor;
+pub(crate) mod hook_actor;
 pub(crate) mod led_actor;
 pub(crate) mod message_source;
 pub(crate) mod rpi_input_actor;
@@ -20,14 +20,16 @@ impl RpiInputActor {
 
 impl MessageSource for RpiInputActor {
     fn run(&mut self) -> Result<bool> {
-        let button = self
+        let (button, press_kind, pressed_at) = self
             .rpi
             .wait_for_button_press()
             .context("RPI Input Actor failed to wait for button press")?;
-        debug!("Sending: {:?}", button);
+        debug!("Sending: {:?} {:?}", button, press_kind);
 
         self.tx
-            .send(ControlActorMessage::ButtonPress(button))
+            .send(ControlActorMessage::ButtonPress(
+                button, press_kind, pressed_at,
+            ))
             .context("RPI Input Actor failed to send to tx")?;
 
         Ok(false)
@@ -1,43 +1,427 @@
-use std::sync::mpsc::Sender;
+use std::{sync::mpsc::Sender, time::Instant};
 
-use chrono::Local;
-use log::info;
+use chrono::NaiveDateTime;
+use log::{info, warn};
 
-use crate::scheduler::Scheduler;
+use crate::{
+    activity::Activity,
+    clock::SharedClock,
+    scheduler::{ScheduledJobSpec, Scheduler, Task},
+};
 
-use super::{actor::Actor, control_actor::ControlActorMessage};
+use super::{
+    actor::Actor, condition_actor::ConditionActorMessage, control_actor::ControlActorMessage,
+    digest_actor::DigestActorMessage,
+};
+
+// How often to check whether the clock has been synchronised yet.
+const SYNC_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+// If NTP is broken we still want reminders, even if they might be at the
+// wrong time, so only hold off for this long after startup.
+const MAX_SYNC_WAIT: std::time::Duration = std::time::Duration::from_mins(15);
 
 pub(crate) enum SchedulerActorMessage {
-    Tick,
+    // The wall clock moved independently of the monotonic clock, so the
+    // current timeout is no longer accurate.
+    ClockChanged,
+    AddJob(ScheduledJobSpec),
+    // A one-off trigger of the activity at this time isn't wanted after all
+    Cancel(Activity, NaiveDateTime),
+    // The activity's trigger at this time has already been done
+    Skip(Activity, NaiveDateTime),
+    // Changed our minds about the skip
+    Unskip(Activity),
 }
 
 pub(crate) struct SchedulerActor {
     scheduler: Scheduler,
     tx_control: Sender<ControlActorMessage>,
+    // Triggers with a condition go here to be checked first
+    tx_condition: Sender<ConditionActorMessage>,
+    // Digests are only sent if there's somewhere to send them
+    tx_digest: Option<Sender<DigestActorMessage>>,
+    clock: SharedClock,
+    // Nothing triggers until the clock is synchronised (or we give up
+    // waiting), set once and never unset.
+    is_synchronised: bool,
+    started_at: Instant,
 }
 
 impl SchedulerActor {
-    pub(crate) fn new(scheduler: Scheduler, tx_control: Sender<ControlActorMessage>) -> Self {
+    pub(crate) fn new(
+        scheduler: Scheduler,
+        tx_control: Sender<ControlActorMessage>,
+        tx_condition: Sender<ConditionActorMessage>,
+        clock: SharedClock,
+    ) -> Self {
+        let started_at = clock.instant();
         Self {
             scheduler,
             tx_control,
+            tx_condition,
+            tx_digest: None,
+            clock,
+            is_synchronised: false,
+            started_at,
+        }
+    }
+
+    /// Send digests on the digest actor.
+    pub(crate) fn with_digest(self, tx_digest: Sender<DigestActorMessage>) -> Self {
+        Self {
+            tx_digest: Some(tx_digest),
+            ..self
+        }
+    }
+
+    fn tick(&mut self) -> anyhow::Result<()> {
+        if !self.check_synchronised() {
+            return Ok(());
         }
+
+        let now = self.clock.now();
+        for trigger in self.scheduler.tick(now) {
+            match (trigger.task, trigger.condition) {
+                (Task::Trigger(activity), Some(condition)) => {
+                    info!("Checking whether {:?} is needed", activity);
+                    self.tx_condition.send(ConditionActorMessage::Check {
+                        activity,
+                        at: now,
+                        condition,
+                    })?;
+                }
+                (Task::Trigger(activity), None) => {
+                    info!("Activity triggered: {:?}", activity);
+                    self.tx_control
+                        .send(ControlActorMessage::Activity(activity, now))?;
+                }
+                (Task::EndTimer(activity), _) => {
+                    info!("Timer ended: {:?}", activity);
+                    self.tx_control
+                        .send(ControlActorMessage::TimerEnded(activity, now))?;
+                }
+                (Task::SendDigest, _) => {
+                    if let Some(tx_digest) = &self.tx_digest {
+                        tx_digest.send(DigestActorMessage::Send(now))?;
+                    } else {
+                        warn!("Digest due but there's no digest actor");
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_synchronised(&mut self) -> bool {
+        if self.is_synchronised {
+            return true;
+        }
+
+        if self.clock.is_synchronised() {
+            info!("Clock is synchronised, it's now {}", self.clock.now());
+        } else if self.clock.instant() - self.started_at >= MAX_SYNC_WAIT {
+            warn!(
+                "Clock still not synchronised after {:?}, carrying on regardless",
+                MAX_SYNC_WAIT
+            );
+        } else {
+            return false;
+        }
+
+        // The schedule was calculated from whatever the clock said at boot,
+        // so treat this just like any other clock change.
+        self.is_synchronised = true;
+        self.scheduler.clock_changed(self.clock.now());
+        true
     }
 }
 
 impl Actor<SchedulerActorMessage> for SchedulerActor {
     fn startup(&mut self) -> anyhow::Result<()> {
+        if !self.check_synchronised() {
+            info!("Clock is not synchronised, holding off scheduling");
+        }
         Ok(())
     }
 
-    fn handle_message(&mut self, _: SchedulerActorMessage) -> anyhow::Result<bool> {
-        let now = Local::now().naive_local();
-        for activity in self.scheduler.tick(now) {
-            info!("Activity triggered: {:?}", activity);
-            self.tx_control
-                .send(ControlActorMessage::Activity(activity, now))?;
+    fn handle_message(&mut self, msg: SchedulerActorMessage) -> anyhow::Result<bool> {
+        match msg {
+            SchedulerActorMessage::ClockChanged => {
+                info!("Clock changed, re-checking schedule");
+                if self.is_synchronised {
+                    self.scheduler.clock_changed(self.clock.now());
+                }
+            }
+            SchedulerActorMessage::AddJob(job_spec) => {
+                self.scheduler.add_job(self.clock.now(), &job_spec);
+            }
+            SchedulerActorMessage::Skip(activity, trigger) => {
+                self.scheduler.skip(activity, trigger);
+            }
+            SchedulerActorMessage::Unskip(activity) => {
+                self.scheduler.unskip(activity);
+            }
+            SchedulerActorMessage::Cancel(activity, at) => {
+                self.scheduler.cancel(activity, at);
+            }
         }
+        // Anything which became due in the meantime should fire now rather
+        // than after the next timeout.
+        self.tick()?;
 
         Ok(false)
     }
+
+    fn timeout(&self) -> Option<std::time::Duration> {
+        if !self.is_synchronised {
+            return Some(SYNC_POLL_INTERVAL);
+        }
+
+        let next_trigger = self.scheduler.next_trigger()?;
+        // A trigger in the past (to_std fails on negative durations) is due
+        // immediately.
+        Some(
+            (next_trigger - self.clock.now())
+                .to_std()
+                .unwrap_or(std::time::Duration::ZERO),
+        )
+    }
+
+    fn handle_timeout(&mut self) -> anyhow::Result<bool> {
+        self.tick()?;
+
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        str::FromStr,
+        sync::mpsc::{self, TryRecvError},
+    };
+
+    use chrono::{Duration, NaiveDateTime, NaiveTime};
+
+    use crate::{
+        activity::Activity,
+        actor::{
+            actor::Actor, condition_actor::ConditionActorMessage,
+            control_actor::ControlActorMessage, digest_actor::DigestActorMessage,
+        },
+        clock::{testhelper::ManualClock, Clock},
+        condition::Condition,
+        config,
+        schedule::{every_day, DailySchedule, Schedule},
+        scheduler::{ScheduledJobSpec, Scheduler},
+    };
+
+    use super::{SchedulerActor, SchedulerActorMessage};
+
+    fn job_spec(time: &str, activity: Activity) -> ScheduledJobSpec {
+        ScheduledJobSpec::new(
+            Schedule::Daily(DailySchedule::new(
+                NaiveTime::from_str(time).unwrap(),
+                every_day(),
+            )),
+            activity,
+            Duration::hours(1),
+        )
+    }
+
+    #[test]
+    fn sleeps_until_next_trigger() {
+        let clock = ManualClock::new(NaiveDateTime::from_str("2020-01-01T05:59:00").unwrap());
+        let scheduler = Scheduler::new(clock.now(), &[job_spec("06:00:00", Activity::TakePills)]);
+        let (tx_control, rx_control) = mpsc::channel();
+        let (tx_condition, _rx_condition) = mpsc::channel();
+        let mut actor = SchedulerActor::new(scheduler, tx_control, tx_condition, clock.shared());
+        actor.startup().unwrap();
+
+        assert_eq!(
+            actor.timeout(),
+            Some(Duration::minutes(1).to_std().unwrap())
+        );
+
+        clock.advance(Duration::seconds(60));
+        assert_eq!(actor.timeout(), Some(std::time::Duration::ZERO));
+        actor.handle_timeout().unwrap();
+
+        assert_eq!(
+            rx_control.try_recv(),
+            Ok(ControlActorMessage::Activity(
+                Activity::TakePills,
+                NaiveDateTime::from_str("2020-01-01T06:00:00").unwrap()
+            ))
+        );
+        assert_eq!(rx_control.try_recv(), Err(TryRecvError::Empty));
+        // Tomorrow's trigger is next
+        assert_eq!(actor.timeout(), Some(Duration::days(1).to_std().unwrap()));
+    }
+
+    #[test]
+    fn added_job_shortens_timeout() {
+        let clock = ManualClock::new(NaiveDateTime::from_str("2020-01-01T05:00:00").unwrap());
+        let scheduler = Scheduler::new(clock.now(), &[job_spec("06:00:00", Activity::TakePills)]);
+        let (tx_control, rx_control) = mpsc::channel();
+        let (tx_condition, _rx_condition) = mpsc::channel();
+        let mut actor = SchedulerActor::new(scheduler, tx_control, tx_condition, clock.shared());
+        actor.startup().unwrap();
+
+        actor
+            .handle_message(SchedulerActorMessage::AddJob(job_spec(
+                "05:30:00",
+                Activity::WaterPlants,
+            )))
+            .unwrap();
+
+        assert_eq!(
+            actor.timeout(),
+            Some(Duration::minutes(30).to_std().unwrap())
+        );
+        assert_eq!(rx_control.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[test]
+    fn holds_off_until_synchronised() {
+        // Booted with a stale clock
+        let clock = ManualClock::new(NaiveDateTime::from_str("2020-01-01T05:00:00").unwrap());
+        clock.set_synchronised(false);
+        let scheduler = Scheduler::new(clock.now(), &[job_spec("06:00:00", Activity::TakePills)]);
+        let (tx_control, rx_control) = mpsc::channel();
+        let (tx_condition, _rx_condition) = mpsc::channel();
+        let mut actor = SchedulerActor::new(scheduler, tx_control, tx_condition, clock.shared());
+        actor.startup().unwrap();
+
+        // Real time is past the trigger but we don't know it yet
+        clock.advance(Duration::minutes(10));
+        assert_eq!(actor.timeout(), Some(super::SYNC_POLL_INTERVAL));
+        actor.handle_timeout().unwrap();
+        assert_eq!(rx_control.try_recv(), Err(TryRecvError::Empty));
+
+        // NTP steps the clock forwards a day, inside the grace period of
+        // today's trigger
+        clock.set_now(NaiveDateTime::from_str("2020-01-02T06:10:00").unwrap());
+        clock.set_synchronised(true);
+        actor.handle_timeout().unwrap();
+        // Yesterday's trigger is way outside the grace period so is skipped,
+        // but today's is within it.
+        assert_eq!(
+            rx_control.try_recv(),
+            Ok(ControlActorMessage::Activity(
+                Activity::TakePills,
+                NaiveDateTime::from_str("2020-01-02T06:10:00").unwrap()
+            ))
+        );
+        assert_eq!(rx_control.try_recv(), Err(TryRecvError::Empty));
+        assert_eq!(
+            actor.timeout(),
+            Some(
+                (Duration::hours(23) + Duration::minutes(50))
+                    .to_std()
+                    .unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn stops_holding_off_eventually() {
+        let clock = ManualClock::new(NaiveDateTime::from_str("2020-01-01T05:00:00").unwrap());
+        clock.set_synchronised(false);
+        let scheduler = Scheduler::new(clock.now(), &[job_spec("06:00:00", Activity::TakePills)]);
+        let (tx_control, rx_control) = mpsc::channel();
+        let (tx_condition, _rx_condition) = mpsc::channel();
+        let mut actor = SchedulerActor::new(scheduler, tx_control, tx_condition, clock.shared());
+        actor.startup().unwrap();
+
+        clock.advance(Duration::hours(1));
+        actor.handle_timeout().unwrap();
+
+        assert_eq!(
+            rx_control.try_recv(),
+            Ok(ControlActorMessage::Activity(
+                Activity::TakePills,
+                NaiveDateTime::from_str("2020-01-01T06:00:00").unwrap()
+            ))
+        );
+    }
+
+    #[test]
+    fn clock_jumps_backwards() {
+        let clock = ManualClock::new(NaiveDateTime::from_str("2020-01-01T07:00:00").unwrap());
+        let scheduler = Scheduler::new(clock.now(), &[job_spec("06:00:00", Activity::TakePills)]);
+        let (tx_control, rx_control) = mpsc::channel();
+        let (tx_condition, _rx_condition) = mpsc::channel();
+        let mut actor = SchedulerActor::new(scheduler, tx_control, tx_condition, clock.shared());
+        actor.startup().unwrap();
+
+        clock.set_now(NaiveDateTime::from_str("2020-01-01T05:00:00").unwrap());
+        actor
+            .handle_message(SchedulerActorMessage::ClockChanged)
+            .unwrap();
+
+        assert_eq!(actor.timeout(), Some(Duration::hours(1).to_std().unwrap()));
+        assert_eq!(rx_control.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[test]
+    fn conditional_trigger_checked_elsewhere() {
+        let clock = ManualClock::new(NaiveDateTime::from_str("2020-01-01T05:59:00").unwrap());
+        let condition = Condition::Command {
+            command: "sleep 30".to_owned(),
+        };
+        let scheduler = Scheduler::new(
+            clock.now(),
+            &[job_spec("06:00:00", Activity::WaterPlants).with_condition(condition.clone())],
+        );
+        let (tx_control, rx_control) = mpsc::channel();
+        let (tx_condition, rx_condition) = mpsc::channel();
+        let mut actor = SchedulerActor::new(scheduler, tx_control, tx_condition, clock.shared());
+        actor.startup().unwrap();
+
+        // Handed over without waiting for the (slow) check
+        clock.advance(Duration::minutes(1));
+        actor.handle_timeout().unwrap();
+        assert_eq!(
+            rx_condition.try_recv(),
+            Ok(ConditionActorMessage::Check {
+                activity: Activity::WaterPlants,
+                at: NaiveDateTime::from_str("2020-01-01T06:00:00").unwrap(),
+                condition,
+            })
+        );
+        assert_eq!(rx_control.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[test]
+    fn digest_waits_for_synchronised_clock() {
+        // Booted on Sunday evening as far as the stale clock knows
+        let clock = ManualClock::new(NaiveDateTime::from_str("2020-01-05T17:55:00").unwrap());
+        clock.set_synchronised(false);
+        let scheduler = Scheduler::new(clock.now(), &[config::digest_job_spec().unwrap()]);
+        let (tx_control, _rx_control) = mpsc::channel();
+        let (tx_condition, _rx_condition) = mpsc::channel();
+        let (tx_digest, rx_digest) = mpsc::channel();
+        let mut actor = SchedulerActor::new(scheduler, tx_control, tx_condition, clock.shared())
+            .with_digest(tx_digest);
+        actor.startup().unwrap();
+
+        // Not sent at what's really the wrong time
+        clock.advance(Duration::minutes(10));
+        actor.handle_timeout().unwrap();
+        assert!(rx_digest.try_recv().is_err());
+
+        // It's really Wednesday, so there's no digest until Sunday
+        clock.set_now(NaiveDateTime::from_str("2020-01-08T10:00:00").unwrap());
+        clock.set_synchronised(true);
+        actor.handle_timeout().unwrap();
+        assert!(rx_digest.try_recv().is_err());
+        clock.set_now(NaiveDateTime::from_str("2020-01-12T18:00:00").unwrap());
+        actor.handle_timeout().unwrap();
+        assert!(matches!(
+            rx_digest.try_recv(),
+            Ok(DigestActorMessage::Send(now)) if now == clock.now()
+        ));
+    }
 }
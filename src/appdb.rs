@@ -1,9 +1,17 @@
-use anyhow::{Context, Result};
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{ensure, Context, Result};
+use chrono::{Duration, NaiveDateTime};
+use log::warn;
 use rusqlite::OptionalExtension;
 
 use crate::{
-    application_state::ApplicationState,
+    activity::Activity,
+    application_state::{ActivityState, ApplicationState, Replay},
     db::{fmt_naivedatetime_for_sqlite, parse_naivedatetime_from_sqlite, Db, Migration},
+    event::{Event, EventKind, EventSource},
+    inventory::Stock,
+    scheduler::ScheduleAdjustment,
 };
 
 pub(crate) const MIGRATIONS: &[Migration] = &[
@@ -24,102 +32,692 @@ pub(crate) const MIGRATIONS: &[Migration] = &[
         id: "003",
         sql: "ALTER TABLE application_state ADD COLUMN clean_litter_tray_pending TIMESTAMP",
     },
+    // Leaves application_state alone, nothing uses it any more but it's
+    // the only history from before there were events.
+    Migration {
+        id: "004",
+        sql: "CREATE TABLE activity_state (
+                  activity_id    TEXT PRIMARY KEY
+                , pending_since  TIMESTAMP
+                , snoozed_until  TIMESTAMP
+                , missed         TIMESTAMP
+                , updated_on     DEFAULT CURRENT_TIMESTAMP
+              );
+              INSERT INTO activity_state (activity_id, pending_since)
+                SELECT 'take_pills', take_pills_pending
+                FROM application_state ORDER BY id DESC LIMIT 1;
+              INSERT INTO activity_state (activity_id, pending_since)
+                SELECT 'water_plants', water_plants_pending
+                FROM application_state ORDER BY id DESC LIMIT 1;
+              INSERT INTO activity_state (activity_id, pending_since)
+                SELECT 'i', i_pending
+                FROM application_state ORDER BY id DESC LIMIT 1;
+              INSERT INTO activity_state (activity_id, pending_since)
+                SELECT 'clean_litter_tray', clean_litter_tray_pending
+                FROM application_state ORDER BY id DESC LIMIT 1;",
+    },
+    Migration {
+        id: "005",
+        sql: "CREATE TABLE events (
+                  id             INTEGER PRIMARY KEY
+                , activity_id    TEXT NOT NULL
+                , kind           TEXT NOT NULL
+                , occurred_at    TIMESTAMP NOT NULL
+                , pending_since  TIMESTAMP
+                , source         TEXT NOT NULL
+                , detail         TEXT
+                , created_on     DEFAULT CURRENT_TIMESTAMP
+              );
+              CREATE INDEX events_activity_id_occurred_at ON events (activity_id, occurred_at);
+              CREATE INDEX events_occurred_at ON events (occurred_at);",
+    },
+    Migration {
+        id: "006",
+        sql: "ALTER TABLE events ADD COLUMN who TEXT",
+    },
+    Migration {
+        id: "007",
+        sql: "CREATE TABLE rotation (
+                  activity_id  TEXT PRIMARY KEY
+                , on_duty      TEXT NOT NULL
+                , updated_on   DEFAULT CURRENT_TIMESTAMP
+              );
+              CREATE TABLE away (
+                  person      TEXT PRIMARY KEY
+                , created_on  DEFAULT CURRENT_TIMESTAMP
+              );",
+    },
+    Migration {
+        id: "008",
+        sql: "CREATE TABLE queued_occurrences (
+                  activity_id    TEXT NOT NULL
+                , pending_since  TIMESTAMP NOT NULL
+                , created_on     DEFAULT CURRENT_TIMESTAMP
+              );
+              CREATE INDEX queued_occurrences_activity_id ON queued_occurrences (activity_id);",
+    },
+    Migration {
+        id: "009",
+        sql: "ALTER TABLE activity_state ADD COLUMN completed_early TIMESTAMP",
+    },
+    Migration {
+        id: "010",
+        sql: "ALTER TABLE activity_state ADD COLUMN last_event_id INTEGER NOT NULL DEFAULT 0;
+              UPDATE activity_state SET last_event_id = (
+                  SELECT COALESCE(MAX(id), 0) FROM events
+                  WHERE events.activity_id = activity_state.activity_id
+              );",
+    },
+    Migration {
+        id: "011",
+        sql: "CREATE TABLE follow_ups (
+                  activity_id  TEXT NOT NULL
+                , due_at       TIMESTAMP NOT NULL
+                , created_on   DEFAULT CURRENT_TIMESTAMP
+              )",
+    },
+    Migration {
+        id: "012",
+        sql: "ALTER TABLE activity_state ADD COLUMN timer TIMESTAMP",
+    },
+    Migration {
+        id: "013",
+        sql: "CREATE TABLE stock (
+                  activity_id      TEXT PRIMARY KEY
+                , remaining        INTEGER NOT NULL
+                , refill_notified  INTEGER NOT NULL DEFAULT 0
+                , updated_on       DEFAULT CURRENT_TIMESTAMP
+              )",
+    },
+    Migration {
+        id: "014",
+        sql: "CREATE TABLE schedule_adjustments (
+                  activity_id    TEXT PRIMARY KEY
+                , shift_minutes  INTEGER NOT NULL
+                , applied_at     TIMESTAMP NOT NULL
+              )",
+    },
 ];
 
 pub(crate) struct AppDb {
     db: Db,
 }
 
+type ActivityStateColumns = (
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+);
+
+fn parse_activity_state(
+    (pending, snoozed_until, missed, completed_early, timer): ActivityStateColumns,
+) -> Result<ActivityState> {
+    let parse = |dt: Option<String>| {
+        dt.map(|dt| parse_naivedatetime_from_sqlite(&dt))
+            .transpose()
+    };
+    Ok(ActivityState {
+        pending: parse(pending)?,
+        queued: Vec::new(),
+        snoozed_until: parse(snoozed_until)?,
+        missed: parse(missed)?,
+        completed_early: parse(completed_early)?,
+        escalation_level: 0,
+        timer: parse(timer)?,
+        timer_finished: false,
+    })
+}
+
+type EventColumns = (
+    String,
+    String,
+    String,
+    Option<String>,
+    String,
+    Option<String>,
+    Option<String>,
+);
+
+fn event_columns(row: &rusqlite::Row) -> rusqlite::Result<EventColumns> {
+    Ok((
+        row.get::<usize, String>(0)?,
+        row.get::<usize, String>(1)?,
+        row.get::<usize, String>(2)?,
+        row.get::<usize, Option<String>>(3)?,
+        row.get::<usize, String>(4)?,
+        row.get::<usize, Option<String>>(5)?,
+        row.get::<usize, Option<String>>(6)?,
+    ))
+}
+
+/// `None` for anything this version doesn't know about.
+fn parse_event(
+    (activity_id, kind, occurred_at, pending_since, source, who, detail): EventColumns,
+) -> Result<Option<Event>> {
+    let (Some(activity), Some(kind), Some(source)) = (
+        Activity::from_id(&activity_id),
+        EventKind::from_id(&kind),
+        EventSource::from_id(&source),
+    ) else {
+        warn!(
+            "Ignoring unknown event {} {} from {}",
+            activity_id, kind, source
+        );
+        return Ok(None);
+    };
+    Ok(Some(Event {
+        activity,
+        kind,
+        occurred_at: parse_naivedatetime_from_sqlite(&occurred_at)?,
+        pending_since: pending_since
+            .map(|dt| parse_naivedatetime_from_sqlite(&dt))
+            .transpose()?,
+        source,
+        who,
+        detail,
+    }))
+}
+
 impl AppDb {
-    pub(crate) fn update_application_state(
+    /// Snapshot `activity`'s state as of the latest event recorded for it,
+    /// so that loading only has to replay anything after that.  The events
+    /// are what counts, this is just a cache.
+    pub(crate) fn update_activity_state(
         &self,
-        application_state: &ApplicationState,
+        activity: Activity,
+        activity_state: &ActivityState,
     ) -> Result<()> {
-        let conn = self.db.new_conn()?;
-        let take_pills_pending: Option<String> = application_state
-            .take_pills_pending
-            .map(|dt| fmt_naivedatetime_for_sqlite(&dt));
-        let water_plants_pending = application_state
-            .water_plants_pending
-            .map(|dt| fmt_naivedatetime_for_sqlite(&dt));
-        let i_pending = application_state
-            .i_pending
-            .map(|dt| fmt_naivedatetime_for_sqlite(&dt));
-        let clean_litter_tray_pending = application_state
-            .clean_litter_tray_pending
-            .map(|dt| fmt_naivedatetime_for_sqlite(&dt));
-        conn.execute(
+        let mut conn = self.db.new_conn()?;
+        let tx = conn.transaction()?;
+        let fmt = |dt: Option<NaiveDateTime>| dt.map(|dt| fmt_naivedatetime_for_sqlite(&dt));
+        tx.execute(
             "
-                INSERT INTO application_state (
-                    take_pills_pending
-                  , water_plants_pending
-                  , i_pending
-                  , clean_litter_tray_pending
+                INSERT INTO activity_state (
+                    activity_id
+                  , pending_since
+                  , snoozed_until
+                  , missed
+                  , completed_early
+                  , timer
+                  , last_event_id
+                )
+                VALUES (
+                    ?1, ?2, ?3, ?4, ?5, ?6
+                  , (SELECT COALESCE(MAX(id), 0) FROM events WHERE activity_id = ?1)
                 )
-                VALUES (?1, ?2, ?3, ?4)
+                ON CONFLICT (activity_id) DO UPDATE SET
+                    pending_since = excluded.pending_since
+                  , snoozed_until = excluded.snoozed_until
+                  , missed = excluded.missed
+                  , completed_early = excluded.completed_early
+                  , timer = excluded.timer
+                  , last_event_id = excluded.last_event_id
+                  , updated_on = CURRENT_TIMESTAMP
             ",
-            [
-                &take_pills_pending,
-                &water_plants_pending,
-                &i_pending,
-                &clean_litter_tray_pending,
-            ],
+            (
+                activity.id(),
+                fmt(activity_state.pending),
+                fmt(activity_state.snoozed_until),
+                fmt(activity_state.missed),
+                fmt(activity_state.completed_early),
+                fmt(activity_state.timer),
+            ),
+        )
+        .context("Failed to update activity state")?;
+        tx.execute(
+            "DELETE FROM queued_occurrences WHERE activity_id = ?1",
+            [activity.id()],
         )
-        .context("Failed to update application state")?;
+        .context("Failed to clear queued occurrences")?;
+        for pending_since in &activity_state.queued {
+            tx.execute(
+                "INSERT INTO queued_occurrences (activity_id, pending_since) VALUES (?1, ?2)",
+                (activity.id(), fmt_naivedatetime_for_sqlite(pending_since)),
+            )
+            .context("Failed to queue occurrence")?;
+        }
+        tx.commit()?;
         Ok(())
     }
 
-    pub(crate) fn load_application_state(&self) -> Result<Option<ApplicationState>> {
+    /// The latest snapshot of `activity`'s state, with any events since
+    /// replayed on top.
+    pub(crate) fn load_activity_state(&self, activity: Activity) -> Result<ActivityState> {
+        let (snapshot, last_event_id) = self.load_snapshot(activity)?;
+        let mut replay = Replay::new(snapshot);
+        for event in self.load_events_after(activity, last_event_id)? {
+            replay.apply(&event);
+        }
+        Ok(replay.finish())
+    }
+
+    pub(crate) fn load_application_state(&self) -> Result<ApplicationState> {
+        let mut activities = HashMap::new();
+        for activity in Activity::ALL {
+            activities.insert(activity, self.load_activity_state(activity)?);
+        }
+
+        Ok(ApplicationState::new(activities))
+    }
+
+    /// Throw away `activity`'s snapshot and replay every event from the
+    /// start, e.g. after fixing a bug in how they're applied.  Anything from
+    /// before there was an event log is lost.
+    pub(crate) fn rebuild_activity_state(&self, activity: Activity) -> Result<ActivityState> {
+        let mut replay = Replay::new(ActivityState::default());
+        for event in self.load_events_after(activity, 0)? {
+            replay.apply(&event);
+        }
+        let state = replay.finish();
+        self.update_activity_state(activity, &state)?;
+        Ok(state)
+    }
+
+    /// The snapshot, and the id of the last event it includes.
+    fn load_snapshot(&self, activity: Activity) -> Result<(ActivityState, i64)> {
         let conn = self.db.new_conn()?;
         let result = conn
             .query_row(
                 "
                 SELECT 
-                      take_pills_pending
-                    , water_plants_pending 
-                    , i_pending
-                    , clean_litter_tray_pending
-                FROM application_state
-                ORDER BY id DESC
-                LIMIT 1
+                      pending_since
+                    , snoozed_until
+                    , missed
+                    , completed_early
+                    , timer
+                    , last_event_id
+                FROM activity_state
+                WHERE activity_id = ?1
             ",
-                (),
+                [activity.id()],
                 |row| {
                     Ok((
-                        row.get::<usize, Option<String>>(0)?,
-                        row.get::<usize, Option<String>>(1)?,
-                        row.get::<usize, Option<String>>(2)?,
-                        row.get::<usize, Option<String>>(3)?,
+                        (
+                            row.get::<usize, Option<String>>(0)?,
+                            row.get::<usize, Option<String>>(1)?,
+                            row.get::<usize, Option<String>>(2)?,
+                            row.get::<usize, Option<String>>(3)?,
+                            row.get::<usize, Option<String>>(4)?,
+                        ),
+                        row.get::<usize, i64>(5)?,
                     ))
                 },
             )
             .optional()
-            .context("Failed to load application state")?;
-
-        match result {
-            Some((take_pills, water_plants, i, clean_litter_tray)) => {
-                let take_pills_pending = take_pills
-                    .map(|dt: String| parse_naivedatetime_from_sqlite(&dt))
-                    .transpose()?;
-                let water_plants_pending = water_plants
-                    .map(|dt: String| parse_naivedatetime_from_sqlite(&dt))
-                    .transpose()?;
-                let i_pending = i
-                    .map(|dt: String| parse_naivedatetime_from_sqlite(&dt))
-                    .transpose()?;
-                let clean_litter_tray_pending = clean_litter_tray
-                    .map(|dt: String| parse_naivedatetime_from_sqlite(&dt))
-                    .transpose()?;
-
-                Ok(Some(ApplicationState {
-                    take_pills_pending,
-                    water_plants_pending,
-                    i_pending,
-                    clean_litter_tray_pending,
-                }))
+            .context("Failed to load activity state")?;
+
+        let Some((columns, last_event_id)) = result else {
+            return Ok((ActivityState::default(), 0));
+        };
+        let mut activity_state = parse_activity_state(columns)?;
+        activity_state.queued = self.load_queued(activity)?;
+        Ok((activity_state, last_event_id))
+    }
+
+    /// Queued occurrences of `activity`, oldest first.
+    fn load_queued(&self, activity: Activity) -> Result<Vec<NaiveDateTime>> {
+        let conn = self.db.new_conn()?;
+        let mut stmt = conn.prepare(
+            "
+                SELECT pending_since
+                FROM queued_occurrences
+                WHERE activity_id = ?1
+                ORDER BY pending_since
+            ",
+        )?;
+        let rows = stmt
+            .query_map([activity.id()], |row| row.get::<usize, String>(0))
+            .context("Failed to load queued occurrences")?;
+
+        let mut queued = Vec::new();
+        for row in rows {
+            queued.push(parse_naivedatetime_from_sqlite(&row?)?);
+        }
+        Ok(queued)
+    }
+
+    /// Events for `activity` recorded after the one with id `id`, in the
+    /// order they were recorded.
+    fn load_events_after(&self, activity: Activity, id: i64) -> Result<Vec<Event>> {
+        let conn = self.db.new_conn()?;
+        let mut stmt = conn.prepare(
+            "
+                SELECT 
+                      activity_id
+                    , kind
+                    , occurred_at
+                    , pending_since
+                    , source
+                    , who
+                    , detail
+                FROM events
+                WHERE activity_id = ?1
+                  AND id > ?2
+                ORDER BY id
+            ",
+        )?;
+        let rows = stmt
+            .query_map((activity.id(), id), event_columns)
+            .context("Failed to load events")?;
+
+        let mut events = Vec::new();
+        for row in rows {
+            events.extend(parse_event(row?)?);
+        }
+        Ok(events)
+    }
+
+    pub(crate) fn insert_event(&self, event: &Event) -> Result<()> {
+        let conn = self.db.new_conn()?;
+        conn.execute(
+            "
+                INSERT INTO events (
+                    activity_id
+                  , kind
+                  , occurred_at
+                  , pending_since
+                  , source
+                  , who
+                  , detail
+                )
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+            ",
+            (
+                event.activity.id(),
+                event.kind.id(),
+                fmt_naivedatetime_for_sqlite(&event.occurred_at),
+                event
+                    .pending_since
+                    .map(|dt| fmt_naivedatetime_for_sqlite(&dt)),
+                event.source.id(),
+                &event.who,
+                &event.detail,
+            ),
+        )
+        .context("Failed to insert event")?;
+        Ok(())
+    }
+
+    /// Events for `activity` which occurred in `[from, to)`, oldest first.
+    pub(crate) fn load_activity_events(
+        &self,
+        activity: Activity,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+    ) -> Result<Vec<Event>> {
+        let conn = self.db.new_conn()?;
+        let mut stmt = conn.prepare(
+            "
+                SELECT 
+                      activity_id
+                    , kind
+                    , occurred_at
+                    , pending_since
+                    , source
+                    , who
+                    , detail
+                FROM events
+                WHERE activity_id = ?1
+                  AND occurred_at >= ?2
+                  AND occurred_at < ?3
+                ORDER BY occurred_at, id
+            ",
+        )?;
+        let rows = stmt
+            .query_map(
+                (
+                    activity.id(),
+                    fmt_naivedatetime_for_sqlite(&from),
+                    fmt_naivedatetime_for_sqlite(&to),
+                ),
+                event_columns,
+            )
+            .context("Failed to load events")?;
+
+        let mut events = Vec::new();
+        for row in rows {
+            events.extend(parse_event(row?)?);
+        }
+
+        Ok(events)
+    }
+
+    /// Remember that `activity` is due to be triggered at `due_at`, so it
+    /// still happens after a restart.
+    pub(crate) fn insert_follow_up(&self, activity: Activity, due_at: NaiveDateTime) -> Result<()> {
+        let conn = self.db.new_conn()?;
+        conn.execute(
+            "INSERT INTO follow_ups (activity_id, due_at) VALUES (?1, ?2)",
+            (activity.id(), fmt_naivedatetime_for_sqlite(&due_at)),
+        )
+        .context("Failed to insert follow-up")?;
+        Ok(())
+    }
+
+    /// Forget `activity`'s follow-ups due at or before `up_to`.
+    pub(crate) fn delete_follow_ups(&self, activity: Activity, up_to: NaiveDateTime) -> Result<()> {
+        let conn = self.db.new_conn()?;
+        conn.execute(
+            "DELETE FROM follow_ups WHERE activity_id = ?1 AND due_at <= ?2",
+            (activity.id(), fmt_naivedatetime_for_sqlite(&up_to)),
+        )
+        .context("Failed to delete follow-ups")?;
+        Ok(())
+    }
+
+    /// Forget the one follow-up of `activity` due at `due_at`.
+    pub(crate) fn delete_follow_up(&self, activity: Activity, due_at: NaiveDateTime) -> Result<()> {
+        let conn = self.db.new_conn()?;
+        conn.execute(
+            "DELETE FROM follow_ups WHERE activity_id = ?1 AND due_at = ?2",
+            (activity.id(), fmt_naivedatetime_for_sqlite(&due_at)),
+        )
+        .context("Failed to delete follow-up")?;
+        Ok(())
+    }
+
+    /// Every follow-up still to happen, soonest first.
+    pub(crate) fn load_follow_ups(&self) -> Result<Vec<(Activity, NaiveDateTime)>> {
+        let conn = self.db.new_conn()?;
+        let mut stmt =
+            conn.prepare("SELECT activity_id, due_at FROM follow_ups ORDER BY due_at")?;
+        let rows = stmt
+            .query_map((), |row| {
+                Ok((row.get::<usize, String>(0)?, row.get::<usize, String>(1)?))
+            })
+            .context("Failed to load follow-ups")?;
+
+        let mut follow_ups = Vec::new();
+        for row in rows {
+            let (activity_id, due_at) = row?;
+            if let Some(activity) = Activity::from_id(&activity_id) {
+                follow_ups.push((activity, parse_naivedatetime_from_sqlite(&due_at)?));
+            } else {
+                warn!("Ignoring follow-up of unknown activity {}", activity_id);
             }
-            None => Ok(None),
         }
+        Ok(follow_ups)
+    }
+
+    /// How much of what `activity` uses is left, `None` if it's not being
+    /// counted yet.
+    pub(crate) fn load_stock(&self, activity: Activity) -> Result<Option<Stock>> {
+        let conn = self.db.new_conn()?;
+        conn.query_row(
+            "SELECT remaining, refill_notified FROM stock WHERE activity_id = ?1",
+            [activity.id()],
+            |row| {
+                Ok(Stock {
+                    remaining: row.get(0)?,
+                    refill_notified: row.get(1)?,
+                })
+            },
+        )
+        .optional()
+        .context("Failed to load stock")
+    }
+
+    /// Use up (or, negative, put back) `amount` of what `activity` uses, if
+    /// it's being counted.  Returns what's left.
+    pub(crate) fn consume_stock(&self, activity: Activity, amount: i64) -> Result<Option<Stock>> {
+        let conn = self.db.new_conn()?;
+        conn.execute(
+            "UPDATE stock SET remaining = remaining - ?2, updated_on = CURRENT_TIMESTAMP
+             WHERE activity_id = ?1",
+            (activity.id(), amount),
+        )
+        .context("Failed to update stock")?;
+        self.load_stock(activity)
+    }
+
+    /// Add `amount` to what `activity` uses, starting to count it if it
+    /// wasn't already.  Returns what's left.
+    pub(crate) fn restock(&self, activity: Activity, amount: i64) -> Result<Stock> {
+        ensure!(
+            amount > 0,
+            "Can only restock a positive amount, not {amount}"
+        );
+        let conn = self.db.new_conn()?;
+        conn.execute(
+            "
+                INSERT INTO stock (activity_id, remaining)
+                VALUES (?1, ?2)
+                ON CONFLICT (activity_id) DO UPDATE SET
+                    remaining = remaining + excluded.remaining
+                  , refill_notified = 0
+                  , updated_on = CURRENT_TIMESTAMP
+            ",
+            (activity.id(), amount),
+        )
+        .context("Failed to restock")?;
+        self.load_stock(activity)?
+            .context("Stock missing after restocking")
+    }
+
+    /// Remember whether we've asked for `activity`'s stock to be refilled,
+    /// so it's only asked once until it's restocked.
+    pub(crate) fn set_refill_notified(&self, activity: Activity, notified: bool) -> Result<()> {
+        let conn = self.db.new_conn()?;
+        conn.execute(
+            "UPDATE stock SET refill_notified = ?2 WHERE activity_id = ?1",
+            (activity.id(), notified),
+        )
+        .context("Failed to update stock")?;
+        Ok(())
+    }
+
+    pub(crate) fn load_schedule_adjustments(
+        &self,
+    ) -> Result<HashMap<Activity, ScheduleAdjustment>> {
+        let conn = self.db.new_conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT activity_id, shift_minutes, applied_at
+             FROM schedule_adjustments",
+        )?;
+        let rows = stmt
+            .query_map((), |row| {
+                Ok((
+                    row.get::<usize, String>(0)?,
+                    row.get::<usize, i64>(1)?,
+                    row.get::<usize, String>(2)?,
+                ))
+            })
+            .context("Failed to load schedule adjustments")?;
+
+        let mut adjustments = HashMap::new();
+        for row in rows {
+            let (activity_id, shift, applied_at) = row?;
+            let Some(activity) = Activity::from_id(&activity_id) else {
+                warn!(
+                    "Ignoring schedule adjustment of unknown activity {}",
+                    activity_id
+                );
+                continue;
+            };
+            adjustments.insert(
+                activity,
+                ScheduleAdjustment {
+                    shift: Duration::minutes(shift),
+                    applied_at: parse_naivedatetime_from_sqlite(&applied_at)?,
+                },
+            );
+        }
+        Ok(adjustments)
+    }
+
+    pub(crate) fn update_schedule_adjustment(
+        &self,
+        activity: Activity,
+        adjustment: &ScheduleAdjustment,
+    ) -> Result<()> {
+        let conn = self.db.new_conn()?;
+        conn.execute(
+            "
+                INSERT INTO schedule_adjustments
+                    (activity_id, shift_minutes, applied_at)
+                VALUES (?1, ?2, ?3)
+                ON CONFLICT (activity_id) DO UPDATE SET
+                    shift_minutes = excluded.shift_minutes
+                  , applied_at = excluded.applied_at
+            ",
+            (
+                activity.id(),
+                adjustment.shift.num_minutes(),
+                fmt_naivedatetime_for_sqlite(&adjustment.applied_at),
+            ),
+        )
+        .context("Failed to update schedule adjustment")?;
+        Ok(())
+    }
+
+    pub(crate) fn load_on_duty(&self, activity: Activity) -> Result<Option<String>> {
+        let conn = self.db.new_conn()?;
+        conn.query_row(
+            "SELECT on_duty FROM rotation WHERE activity_id = ?1",
+            [activity.id()],
+            |row| row.get::<usize, String>(0),
+        )
+        .optional()
+        .context("Failed to load rotation")
+    }
+
+    pub(crate) fn update_on_duty(&self, activity: Activity, person: &str) -> Result<()> {
+        let conn = self.db.new_conn()?;
+        conn.execute(
+            "
+                INSERT INTO rotation (activity_id, on_duty)
+                VALUES (?1, ?2)
+                ON CONFLICT (activity_id) DO UPDATE SET
+                    on_duty = excluded.on_duty
+                  , updated_on = CURRENT_TIMESTAMP
+            ",
+            (activity.id(), person),
+        )
+        .context("Failed to update rotation")?;
+        Ok(())
+    }
+
+    pub(crate) fn load_away(&self) -> Result<HashSet<String>> {
+        let conn = self.db.new_conn()?;
+        let mut stmt = conn.prepare("SELECT person FROM away")?;
+        let away = stmt
+            .query_map((), |row| row.get::<usize, String>(0))
+            .context("Failed to load away")?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(away)
+    }
+
+    pub(crate) fn set_away(&self, person: &str, away: bool) -> Result<()> {
+        let conn = self.db.new_conn()?;
+        if away {
+            conn.execute("INSERT OR IGNORE INTO away (person) VALUES (?1)", [person])
+        } else {
+            conn.execute("DELETE FROM away WHERE person = ?1", [person])
+        }
+        .context("Failed to update away")?;
+        Ok(())
     }
 
     pub(crate) fn new(path: String) -> Self {
@@ -133,13 +731,23 @@ impl AppDb {
 
 #[cfg(test)]
 mod tests {
-    use std::str::FromStr;
+    use std::{
+        collections::{HashMap, HashSet},
+        str::FromStr,
+    };
 
-    use chrono::NaiveDateTime;
+    use chrono::{Duration, NaiveDateTime};
 
-    use crate::{db::Db, ApplicationState};
+    use crate::{
+        activity::Activity,
+        application_state::ActivityState,
+        db::Db,
+        event::{Event, EventKind, EventSource},
+        inventory::Stock,
+        scheduler::ScheduleAdjustment,
+    };
 
-    use super::AppDb;
+    use super::{AppDb, MIGRATIONS};
 
     impl AppDb {
         pub(crate) fn new_tmp() -> Self {
@@ -148,42 +756,424 @@ mod tests {
     }
 
     #[test]
-    fn save_and_load_populated_app_state() {
+    fn save_and_load_populated_activity_state() {
+        let appdb = AppDb::new_tmp();
+        appdb.run_migrations().unwrap();
+
+        let mut state = ActivityState {
+            pending: Some(NaiveDateTime::from_str("2020-01-01T08:00:00").unwrap()),
+            queued: vec![
+                NaiveDateTime::from_str("2020-01-02T08:00:00").unwrap(),
+                NaiveDateTime::from_str("2020-01-03T08:00:00").unwrap(),
+            ],
+            snoozed_until: Some(NaiveDateTime::from_str("2020-01-02T09:00:00").unwrap()),
+            missed: Some(NaiveDateTime::from_str("2019-12-28T08:00:01").unwrap()),
+            completed_early: Some(NaiveDateTime::from_str("2020-01-04T08:00:00").unwrap()),
+            escalation_level: 0,
+            timer: Some(NaiveDateTime::from_str("2020-01-01T08:45:00").unwrap()),
+            timer_finished: false,
+        };
+        appdb
+            .update_activity_state(Activity::TakePills, &state)
+            .unwrap();
+
+        assert_eq!(
+            appdb.load_activity_state(Activity::TakePills).unwrap(),
+            state
+        );
+        assert_eq!(
+            appdb.load_activity_state(Activity::I).unwrap(),
+            ActivityState::default()
+        );
+        let application_state = appdb.load_application_state().unwrap();
+        assert_eq!(application_state.activity(Activity::TakePills), state);
+        assert_eq!(
+            application_state.activity(Activity::I),
+            ActivityState::default()
+        );
+
+        // The queue is replaced, not added to
+        state.pop_pending();
+        appdb
+            .update_activity_state(Activity::TakePills, &state)
+            .unwrap();
+        assert_eq!(
+            appdb.load_activity_state(Activity::TakePills).unwrap(),
+            state
+        );
+    }
+
+    #[test]
+    fn save_and_load_empty_activity_state() {
+        let appdb = AppDb::new_tmp();
+        appdb.run_migrations().unwrap();
+
+        let state = ActivityState {
+            pending: Some(NaiveDateTime::from_str("2020-01-01T08:00:00").unwrap()),
+            ..ActivityState::default()
+        };
+        appdb.update_activity_state(Activity::I, &state).unwrap();
+        appdb
+            .update_activity_state(Activity::I, &ActivityState::default())
+            .unwrap();
+
+        assert_eq!(
+            appdb.load_activity_state(Activity::I).unwrap(),
+            ActivityState::default()
+        );
+    }
+
+    #[test]
+    fn load_activity_state_after_no_saves() {
+        let appdb = AppDb::new_tmp();
+        appdb.run_migrations().unwrap();
+
+        assert_eq!(
+            appdb.load_activity_state(Activity::WaterPlants).unwrap(),
+            ActivityState::default()
+        );
+    }
+
+    #[test]
+    fn migrate_application_state_to_activity_state() {
+        let appdb = AppDb::new_tmp();
+        let migration_idx = MIGRATIONS.iter().position(|m| m.id == "004").unwrap();
+        appdb.db.upgrade(&MIGRATIONS[..migration_idx]).unwrap();
+
+        // Two snapshots, only the latest one matters
+        let conn = appdb.db.new_conn().unwrap();
+        conn.execute(
+            "INSERT INTO application_state (take_pills_pending) VALUES ('2020-01-01T06:00:00.000000000Z')",
+            (),
+        )
+        .unwrap();
+        conn.execute(
+            "
+                INSERT INTO application_state (
+                    water_plants_pending
+                  , clean_litter_tray_pending
+                )
+                VALUES (
+                    '2020-01-02T06:00:00.000000000Z'
+                  , '2019-12-28T06:00:00.000000000Z'
+                )
+            ",
+            (),
+        )
+        .unwrap();
+
+        appdb.run_migrations().unwrap();
+
+        assert_eq!(
+            appdb.load_activity_state(Activity::TakePills).unwrap(),
+            ActivityState::default()
+        );
+        assert_eq!(
+            appdb.load_activity_state(Activity::WaterPlants).unwrap(),
+            ActivityState {
+                pending: Some(NaiveDateTime::from_str("2020-01-02T06:00:00").unwrap()),
+                queued: Vec::new(),
+                snoozed_until: None,
+                missed: None,
+                completed_early: None,
+                escalation_level: 0,
+                timer: None,
+                timer_finished: false,
+            }
+        );
+        assert_eq!(
+            appdb
+                .load_activity_state(Activity::CleanLitterTray)
+                .unwrap(),
+            ActivityState {
+                pending: Some(NaiveDateTime::from_str("2019-12-28T06:00:00").unwrap()),
+                ..ActivityState::default()
+            }
+        );
+
+        // The old snapshots are kept
+        let snapshots: i64 = conn
+            .query_row("SELECT COUNT(*) FROM application_state", (), |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(snapshots, 2);
+    }
+
+    #[test]
+    fn insert_and_query_events() {
+        let appdb = AppDb::new_tmp();
+        appdb.run_migrations().unwrap();
+
+        let at = |s: &str| NaiveDateTime::from_str(s).unwrap();
+        let triggered = Event {
+            activity: Activity::TakePills,
+            kind: EventKind::Triggered,
+            occurred_at: at("2020-01-01T06:00:00"),
+            pending_since: Some(at("2020-01-01T06:00:00")),
+            source: EventSource::System,
+            who: None,
+            detail: None,
+        };
+        let watered = Event {
+            activity: Activity::WaterPlants,
+            kind: EventKind::Acknowledged,
+            occurred_at: at("2020-01-01T07:00:00"),
+            pending_since: None,
+            source: EventSource::Button,
+            who: None,
+            detail: None,
+        };
+        let acknowledged = Event {
+            activity: Activity::TakePills,
+            kind: EventKind::Acknowledged,
+            occurred_at: at("2020-01-01T07:12:00"),
+            pending_since: Some(at("2020-01-01T06:00:00")),
+            source: EventSource::Button,
+            who: Some("Sam".to_owned()),
+            detail: None,
+        };
+        let notified = Event {
+            activity: Activity::TakePills,
+            kind: EventKind::Notified,
+            occurred_at: at("2020-01-02T11:00:00"),
+            pending_since: Some(at("2020-01-02T06:00:00")),
+            source: EventSource::System,
+            who: None,
+            detail: Some("someone@else".to_owned()),
+        };
+        for event in [&triggered, &watered, &acknowledged, &notified] {
+            appdb.insert_event(event).unwrap();
+        }
+
+        assert_eq!(
+            appdb
+                .load_activity_events(
+                    Activity::WaterPlants,
+                    at("2020-01-01T00:00:00"),
+                    at("2020-01-02T00:00:00")
+                )
+                .unwrap(),
+            vec![watered]
+        );
+        assert_eq!(
+            appdb
+                .load_activity_events(
+                    Activity::TakePills,
+                    at("2020-01-01T06:00:00"),
+                    at("2020-01-03T00:00:00")
+                )
+                .unwrap(),
+            vec![triggered, acknowledged, notified]
+        );
+        // The end of the range is exclusive
+        assert_eq!(
+            appdb
+                .load_activity_events(
+                    Activity::TakePills,
+                    at("2020-01-01T00:00:00"),
+                    at("2020-01-01T06:00:00")
+                )
+                .unwrap(),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn replay_events_since_snapshot() {
         let appdb = AppDb::new_tmp();
         appdb.run_migrations().unwrap();
 
-        let take_pills_pending = Some(NaiveDateTime::from_str("2020-01-01T08:00:00").unwrap());
-        let water_plants_pending = Some(NaiveDateTime::from_str("2020-01-02T08:00:01").unwrap());
-        let i_pending = Some(NaiveDateTime::from_str("2020-01-02T08:00:02").unwrap());
-        let clean_litter_tray_pending =
-            Some(NaiveDateTime::from_str("2020-01-02T08:00:03").unwrap());
-        let state = ApplicationState {
-            take_pills_pending,
-            water_plants_pending,
-            i_pending,
-            clean_litter_tray_pending,
+        let at = |s: &str| NaiveDateTime::from_str(s).unwrap();
+        let event = |kind, occurred_at, pending_since| Event {
+            activity: Activity::TakePills,
+            kind,
+            occurred_at: at(occurred_at),
+            pending_since: Some(at(pending_since)),
+            source: EventSource::System,
+            who: None,
+            detail: None,
+        };
+        appdb
+            .insert_event(&event(
+                EventKind::Triggered,
+                "2020-01-01T06:00:00",
+                "2020-01-01T06:00:00",
+            ))
+            .unwrap();
+        let snapshot = ActivityState {
+            pending: Some(at("2020-01-01T06:00:00")),
+            ..ActivityState::default()
+        };
+        appdb
+            .update_activity_state(Activity::TakePills, &snapshot)
+            .unwrap();
+        assert_eq!(
+            appdb.load_activity_state(Activity::TakePills).unwrap(),
+            snapshot
+        );
+
+        // Recorded, but not saved yet
+        appdb
+            .insert_event(&event(
+                EventKind::Triggered,
+                "2020-01-02T06:00:00",
+                "2020-01-02T06:00:00",
+            ))
+            .unwrap();
+        appdb
+            .insert_event(&event(
+                EventKind::Acknowledged,
+                "2020-01-02T07:00:00",
+                "2020-01-01T06:00:00",
+            ))
+            .unwrap();
+        let replayed = ActivityState {
+            pending: Some(at("2020-01-02T06:00:00")),
+            ..ActivityState::default()
         };
-        appdb.update_application_state(&state).unwrap();
+        assert_eq!(
+            appdb.load_activity_state(Activity::TakePills).unwrap(),
+            replayed
+        );
+        assert_eq!(
+            appdb
+                .load_application_state()
+                .unwrap()
+                .activity(Activity::TakePills),
+            replayed
+        );
 
-        assert_eq!(appdb.load_application_state().unwrap().unwrap(), state);
+        // Rebuilding starts from scratch, ignoring a bad snapshot
+        appdb
+            .update_activity_state(Activity::TakePills, &ActivityState::default())
+            .unwrap();
+        assert_eq!(
+            appdb.rebuild_activity_state(Activity::TakePills).unwrap(),
+            replayed
+        );
+        assert_eq!(
+            appdb.load_activity_state(Activity::TakePills).unwrap(),
+            replayed
+        );
     }
 
     #[test]
-    fn save_and_load_empty_app_state() {
+    fn follow_ups() {
         let appdb = AppDb::new_tmp();
         appdb.run_migrations().unwrap();
 
-        let state = ApplicationState::blank();
-        appdb.update_application_state(&state).unwrap();
+        let at = |s: &str| NaiveDateTime::from_str(s).unwrap();
+        for (activity, due_at) in [
+            (Activity::Eat, "2020-01-01T09:30:00"),
+            (Activity::CheckDrainageTrays, "2020-01-03T09:00:00"),
+            (Activity::Eat, "2020-01-02T09:30:00"),
+            (Activity::Eat, "2020-01-03T09:30:00"),
+        ] {
+            appdb.insert_follow_up(activity, at(due_at)).unwrap();
+        }
+        appdb
+            .delete_follow_ups(Activity::Eat, at("2020-01-02T09:30:00"))
+            .unwrap();
+        appdb
+            .delete_follow_up(Activity::Eat, at("2020-01-03T09:30:00"))
+            .unwrap();
 
-        assert_eq!(appdb.load_application_state().unwrap().unwrap(), state);
+        assert_eq!(
+            appdb.load_follow_ups().unwrap(),
+            vec![(Activity::CheckDrainageTrays, at("2020-01-03T09:00:00"))]
+        );
     }
 
     #[test]
-    fn load_app_state_after_no_saves() {
+    fn stock() {
         let appdb = AppDb::new_tmp();
         appdb.run_migrations().unwrap();
 
-        assert_eq!(appdb.load_application_state().unwrap(), None);
+        // Not counted until it's first restocked
+        assert_eq!(appdb.consume_stock(Activity::TakePills, 1).unwrap(), None);
+        appdb.restock(Activity::TakePills, 28).unwrap();
+        appdb.consume_stock(Activity::TakePills, 1).unwrap();
+        appdb
+            .set_refill_notified(Activity::TakePills, true)
+            .unwrap();
+        assert_eq!(
+            appdb.consume_stock(Activity::TakePills, 2).unwrap(),
+            Some(Stock {
+                remaining: 25,
+                refill_notified: true,
+            })
+        );
+        assert_eq!(
+            appdb.restock(Activity::TakePills, 28).unwrap(),
+            Stock {
+                remaining: 53,
+                refill_notified: false,
+            }
+        );
+        assert_eq!(appdb.load_stock(Activity::CleanLitterTray).unwrap(), None);
+
+        // A typo mustn't drain it
+        assert!(appdb.restock(Activity::TakePills, 0).is_err());
+        assert!(appdb.restock(Activity::TakePills, -28).is_err());
+        assert_eq!(
+            appdb
+                .load_stock(Activity::TakePills)
+                .unwrap()
+                .unwrap()
+                .remaining,
+            53
+        );
+    }
+
+    #[test]
+    fn schedule_adjustments() {
+        let appdb = AppDb::new_tmp();
+        appdb.run_migrations().unwrap();
+
+        let adjustment = |shift| ScheduleAdjustment {
+            shift: Duration::minutes(shift),
+            applied_at: NaiveDateTime::from_str("2020-01-01T09:00:00").unwrap(),
+        };
+        appdb
+            .update_schedule_adjustment(Activity::TakePills, &adjustment(90))
+            .unwrap();
+        appdb
+            .update_schedule_adjustment(Activity::TakePills, &adjustment(105))
+            .unwrap();
+        appdb
+            .update_schedule_adjustment(Activity::WaterPlants, &adjustment(-30))
+            .unwrap();
+
+        assert_eq!(
+            appdb.load_schedule_adjustments().unwrap(),
+            HashMap::from([
+                (Activity::TakePills, adjustment(105)),
+                (Activity::WaterPlants, adjustment(-30)),
+            ])
+        );
+    }
+
+    #[test]
+    fn rotation_and_away() {
+        let appdb = AppDb::new_tmp();
+        appdb.run_migrations().unwrap();
+
+        assert_eq!(appdb.load_on_duty(Activity::WaterPlants).unwrap(), None);
+        appdb.update_on_duty(Activity::WaterPlants, "Alex").unwrap();
+        appdb.update_on_duty(Activity::WaterPlants, "Sam").unwrap();
+        assert_eq!(
+            appdb.load_on_duty(Activity::WaterPlants).unwrap(),
+            Some("Sam".to_owned())
+        );
+
+        appdb.set_away("Alex", true).unwrap();
+        appdb.set_away("Alex", true).unwrap();
+        appdb.set_away("Jo", true).unwrap();
+        appdb.set_away("Jo", false).unwrap();
+        assert_eq!(
+            appdb.load_away().unwrap(),
+            HashSet::from(["Alex".to_owned()])
+        );
     }
 }
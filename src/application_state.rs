@@ -1,21 +1,809 @@
-use chrono::NaiveDateTime;
+use std::collections::HashMap;
 
-#[allow(clippy::struct_field_names)]
-#[derive(Debug, PartialEq, Eq, PartialOrd)]
+use chrono::{Duration, NaiveDateTime};
+use log::warn;
+
+use crate::{
+    activity::{Acknowledge, Activity, Escalation, EscalationAction},
+    db::parse_naivedatetime_from_sqlite,
+    event::{Event, EventKind},
+    ledstrategy::LedState,
+};
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Default, Clone)]
+pub(crate) struct ActivityState {
+    /// When the oldest occurrence which hasn't been done was triggered.
+    pub(crate) pending: Option<NaiveDateTime>,
+    /// Later occurrences waiting behind `pending`, oldest first.
+    pub(crate) queued: Vec<NaiveDateTime>,
+    pub(crate) snoozed_until: Option<NaiveDateTime>,
+    /// When the last missed occurrence became pending, until someone presses
+    /// the button to say they've seen it.
+    pub(crate) missed: Option<NaiveDateTime>,
+    /// The next trigger, if it's already been done early.
+    pub(crate) completed_early: Option<NaiveDateTime>,
+    /// How many of `pending`'s escalations have been applied.  Not
+    /// persisted, see `ControlActor::startup`.
+    pub(crate) escalation_level: usize,
+    /// When the button's timer ends, if it's been started and not cleared.
+    pub(crate) timer: Option<NaiveDateTime>,
+    /// Whether `timer` has ended.  Not persisted, `ControlActor::startup`
+    /// works it out again.
+    pub(crate) timer_finished: bool,
+}
+
+/// Where an activity's at, derived from its `ActivityState`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum Status {
+    /// Nothing to do.
+    Idle,
+    /// Triggered and waiting to be done.
+    Pending,
+    /// Pending, but put off for a while.
+    Snoozed,
+    /// Pending for long enough to have been escalated to blinking fast.
+    Overdue,
+    /// The last one wasn't done before its deadline, and nobody's said
+    /// they've seen that yet.
+    Missed,
+    /// Nothing to do, and the next one's already been done.
+    CompletedEarly,
+    /// Nothing to do, and the button's timer is counting down.
+    TimerRunning,
+    /// Nothing to do, and the button's timer has ended.
+    TimerFinished,
+}
+
+/// What a short press did.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Press {
+    /// When each occurrence it did became pending, or the missed one it
+    /// acknowledged.  Empty if nothing was pending.
+    pub(crate) acknowledged: Vec<NaiveDateTime>,
+    /// The upcoming trigger it did early instead, if nothing was pending.
+    pub(crate) completed_early: Option<NaiveDateTime>,
+}
+
+/// What happened as time moved on.
+#[derive(Debug, PartialEq, Eq, Default)]
+pub(crate) struct Timeout {
+    /// When each occurrence which missed its deadline became pending.
+    pub(crate) missed: Vec<NaiveDateTime>,
+    /// Indexes of the escalations which are now due, and when the
+    /// occurrence they're for became pending.
+    pub(crate) escalations: Vec<(usize, NaiveDateTime)>,
+    /// Whether the state needs saving.
+    pub(crate) changed: bool,
+}
+
+impl ActivityState {
+    /// How many occurrences are waiting to be done.
+    pub(crate) fn outstanding(&self) -> usize {
+        usize::from(self.pending.is_some()) + self.queued.len()
+    }
+
+    pub(crate) fn status(&self, escalations: &[Escalation]) -> Status {
+        if self.pending.is_some() {
+            let applied = &escalations[..self.escalation_level.min(escalations.len())];
+            if self.snoozed_until.is_some() {
+                Status::Snoozed
+            } else if applied
+                .iter()
+                .any(|escalation| matches!(escalation.action, EscalationAction::BlinkFast))
+            {
+                Status::Overdue
+            } else {
+                Status::Pending
+            }
+        } else if self.missed.is_some() {
+            Status::Missed
+        } else if self.timer_finished {
+            Status::TimerFinished
+        } else if self.timer.is_some() {
+            Status::TimerRunning
+        } else if self.completed_early.is_some() {
+            Status::CompletedEarly
+        } else {
+            Status::Idle
+        }
+    }
+
+    /// What the LED should be showing.  Blinks out how many are outstanding
+    /// if it's more than one.
+    pub(crate) fn led_state(&self, escalations: &[Escalation]) -> LedState {
+        match self.status(escalations) {
+            Status::Idle | Status::Snoozed | Status::CompletedEarly | Status::TimerRunning => {
+                LedState::Off
+            }
+            Status::Pending | Status::Overdue if self.outstanding() > 1 => {
+                LedState::BlinkCount(u8::try_from(self.outstanding().min(9)).unwrap_or(9))
+            }
+            Status::Pending => LedState::On,
+            Status::Overdue | Status::TimerFinished => LedState::BlinkFast,
+            Status::Missed => LedState::BlinkSlow,
+        }
+    }
+
+    /// Triggered at `now`.  Any previous miss has already been recorded, the
+    /// new occurrence takes over the LED.  If the last one still hasn't been
+    /// done this one waits behind it.
+    pub(crate) fn trigger(&mut self, now: NaiveDateTime) {
+        self.snoozed_until = None;
+        self.missed = None;
+        self.completed_early = None;
+        if self.pending.is_some() {
+            self.queued.push(now);
+        } else {
+            self.pending = Some(now);
+            self.escalation_level = 0;
+        }
+    }
+
+    /// Long pressed, put off whatever's pending until `until`.  Returns when
+    /// it became pending, or `None` if there's nothing to snooze.
+    pub(crate) fn snooze(&mut self, until: NaiveDateTime) -> Option<NaiveDateTime> {
+        let pending_since = self.pending?;
+        self.snoozed_until = Some(until);
+        Some(pending_since)
+    }
+
+    /// Start the button's timer, to end at `ends`, if there's nothing else
+    /// going on.  Returns whether it started.
+    pub(crate) fn start_timer(&mut self, ends: NaiveDateTime) -> bool {
+        if self.outstanding() > 0 || self.missed.is_some() || self.timer.is_some() {
+            return false;
+        }
+        self.timer = Some(ends);
+        true
+    }
+
+    /// End the timer, if it's running and due to have ended by `now`.
+    /// Returns whether it did.
+    pub(crate) fn finish_timer(&mut self, now: NaiveDateTime) -> bool {
+        if self.timer_finished || self.timer.is_none_or(|ends| ends > now) {
+            return false;
+        }
+        self.timer_finished = true;
+        true
+    }
+
+    /// Pressed with nothing to do but a timer running or finished, stop or
+    /// dismiss it.  Returns whether there was one.
+    pub(crate) fn clear_timer(&mut self) -> bool {
+        if self.outstanding() > 0 || self.missed.is_some() || self.timer.is_none() {
+            return false;
+        }
+        self.timer = None;
+        self.timer_finished = false;
+        true
+    }
+
+    /// Short pressed at `now`, doing the oldest or all of the outstanding
+    /// occurrences.  With nothing pending that's acknowledging the missed one,
+    /// if any, otherwise doing `early_trigger` (the next trigger, if it's
+    /// soon enough) early.
+    pub(crate) fn press(
+        &mut self,
+        how: Acknowledge,
+        early_trigger: Option<NaiveDateTime>,
+        escalations: &[Escalation],
+        now: NaiveDateTime,
+    ) -> Press {
+        self.snoozed_until = None;
+        let mut acknowledged: Vec<NaiveDateTime> = match how {
+            Acknowledge::Oldest => self.pop_pending().into_iter().collect(),
+            Acknowledge::All => self
+                .pending
+                .take()
+                .into_iter()
+                .chain(self.queued.drain(..))
+                .collect(),
+        };
+        if let Some(missed) = self.missed.take() {
+            if acknowledged.is_empty() {
+                acknowledged.push(missed);
+            }
+        }
+        // Doing the same one early twice doesn't count
+        let completed_early = early_trigger
+            .filter(|trigger| acknowledged.is_empty() && self.completed_early != Some(*trigger));
+        if completed_early.is_some() {
+            self.completed_early = completed_early;
+        }
+        self.catch_up_escalations(escalations, now);
+
+        Press {
+            acknowledged,
+            completed_early,
+        }
+    }
+
+    /// Put back how things were before an accidental press.
+    pub(crate) fn undo(&mut self, previous: ActivityState) {
+        *self = previous;
+    }
+
+    /// Time's moved on to `now`: deadlines pass, snoozes end, triggers done
+    /// early go by and escalations become due.  Snoozed occurrences don't
+    /// escalate until the snooze is over.
+    pub(crate) fn timeout(
+        &mut self,
+        now: NaiveDateTime,
+        deadline: Option<Duration>,
+        escalations: &[Escalation],
+    ) -> Timeout {
+        let mut timeout = Timeout::default();
+        while let Some(pending_since) = self.pending.filter(|pending_since| {
+            deadline.is_some_and(|deadline| *pending_since + deadline <= now)
+        }) {
+            self.miss(pending_since);
+            self.catch_up_escalations(escalations, now);
+            timeout.missed.push(pending_since);
+            timeout.changed = true;
+        }
+        if self.snoozed_until.is_some_and(|until| until <= now) {
+            self.snoozed_until = None;
+            timeout.changed = true;
+        }
+        // The scheduler will have skipped it
+        if self.completed_early.is_some_and(|trigger| trigger <= now) {
+            self.completed_early = None;
+            timeout.changed = true;
+        }
+        if let (Some(pending_since), None) = (self.pending, self.snoozed_until) {
+            while self.escalation_level < escalations.len()
+                && pending_since + escalations[self.escalation_level].after <= now
+            {
+                timeout
+                    .escalations
+                    .push((self.escalation_level, pending_since));
+                self.escalation_level += 1;
+            }
+        }
+        timeout
+    }
+
+    /// When the next snooze ends, escalation is due, deadline passes or
+    /// trigger done early would have fired, whichever is soonest.  Timers
+    /// are one-off jobs in the scheduler instead.
+    pub(crate) fn next_wakeup(
+        &self,
+        deadline: Option<Duration>,
+        escalations: &[Escalation],
+    ) -> Option<NaiveDateTime> {
+        // Snoozed activities don't escalate until the snooze ends
+        let escalation = if self.snoozed_until.is_some() {
+            self.snoozed_until
+        } else {
+            escalations
+                .get(self.escalation_level)
+                .zip(self.pending)
+                .map(|(escalation, pending)| pending + escalation.after)
+        };
+        let deadline = deadline
+            .zip(self.pending)
+            .map(|(deadline, pending)| pending + deadline);
+        [escalation, deadline, self.completed_early]
+            .into_iter()
+            .flatten()
+            .min()
+    }
+
+    /// Done the oldest occurrence, the next one (if any) is now pending.
+    pub(crate) fn pop_pending(&mut self) -> Option<NaiveDateTime> {
+        let popped = self.pending.take();
+        if !self.queued.is_empty() {
+            self.pending = Some(self.queued.remove(0));
+        }
+        popped
+    }
+
+    /// The occurrence pending since `pending_since` is over, one way or
+    /// another.
+    fn remove(&mut self, pending_since: NaiveDateTime) {
+        if self.pending == Some(pending_since) {
+            self.pop_pending();
+        } else {
+            self.queued.retain(|queued| *queued != pending_since);
+        }
+    }
+
+    /// The occurrence pending since `pending_since` missed its deadline.
+    fn miss(&mut self, pending_since: NaiveDateTime) {
+        self.remove(pending_since);
+        self.snoozed_until = None;
+        self.missed = Some(pending_since);
+    }
+
+    /// Whatever's now pending has been waiting behind something else, so
+    /// assume the escalations already due have been applied rather than
+    /// applying them all at once.
+    pub(crate) fn catch_up_escalations(&mut self, escalations: &[Escalation], now: NaiveDateTime) {
+        self.escalation_level = self.pending.map_or(0, |pending_since| {
+            escalations
+                .iter()
+                .take_while(|escalation| pending_since + escalation.after <= now)
+                .count()
+        });
+    }
+}
+
+/// Rebuilds an activity's state from its events, oldest first, on top of
+/// a snapshot (or nothing).  Has to end up where `ControlActor` did when it
+/// recorded them.
+pub(crate) struct Replay {
+    state: ActivityState,
+    // When the latest press was and what it changed, in case it's undone
+    before_press: Option<(NaiveDateTime, ActivityState)>,
+}
+
+impl Replay {
+    pub(crate) fn new(snapshot: ActivityState) -> Self {
+        Self {
+            state: snapshot,
+            before_press: None,
+        }
+    }
+
+    pub(crate) fn apply(&mut self, event: &Event) {
+        let state = &mut self.state;
+        match (event.kind, event.pending_since) {
+            (EventKind::Triggered, _) => {
+                self.before_press = None;
+                state.trigger(event.occurred_at);
+            }
+            (EventKind::Acknowledged | EventKind::CompletedEarly, pending_since) => {
+                // One press records an event per occurrence it did
+                if self
+                    .before_press
+                    .as_ref()
+                    .is_none_or(|(pressed_at, _)| *pressed_at != event.occurred_at)
+                {
+                    self.before_press = Some((event.occurred_at, state.clone()));
+                    state.snoozed_until = None;
+                    state.missed = None;
+                }
+                match (event.kind, pending_since) {
+                    (EventKind::Acknowledged, Some(pending_since)) => state.remove(pending_since),
+                    (EventKind::CompletedEarly, trigger) => state.completed_early = trigger,
+                    _ => {}
+                }
+            }
+            (EventKind::Undone, _) => {
+                // Once for everything the press did
+                if let Some((_, previous)) = self.before_press.take() {
+                    state.undo(previous);
+                }
+            }
+            (EventKind::Snoozed, _) => {
+                let until = detail_time(event);
+                if until.is_none() {
+                    warn!("Ignoring snooze with no end {:?}", event);
+                }
+                state.snoozed_until = until.or(state.snoozed_until);
+            }
+            (EventKind::TimerStarted, _) => {
+                let ends = detail_time(event);
+                if ends.is_none() {
+                    warn!("Ignoring timer with no end {:?}", event);
+                }
+                state.timer = ends.or(state.timer);
+                state.timer_finished = false;
+            }
+            (EventKind::TimerCleared, _) => {
+                state.timer = None;
+                state.timer_finished = false;
+            }
+            (EventKind::Missed, Some(pending_since)) => state.miss(pending_since),
+            (
+                EventKind::Missed
+                | EventKind::Escalated
+                | EventKind::Notified
+                | EventKind::NotifyFailed
+                | EventKind::Restocked
+                | EventKind::TurnSwapped
+                | EventKind::Away
+                | EventKind::Back,
+                _,
+            ) => {}
+        }
+    }
+
+    /// The state after all the events.  Escalations are caught up on at
+    /// startup rather than replayed.
+    pub(crate) fn finish(self) -> ActivityState {
+        self.state
+    }
+}
+
+/// When a snooze or timer ends, as recorded in the event's `detail`.
+fn detail_time(event: &Event) -> Option<NaiveDateTime> {
+    event
+        .detail
+        .as_deref()
+        .and_then(|detail| parse_naivedatetime_from_sqlite(detail).ok())
+}
+
+#[derive(Debug, PartialEq, Eq)]
 pub(crate) struct ApplicationState {
-    pub(crate) take_pills_pending: Option<NaiveDateTime>,
-    pub(crate) water_plants_pending: Option<NaiveDateTime>,
-    pub(crate) i_pending: Option<NaiveDateTime>,
-    pub(crate) clean_litter_tray_pending: Option<NaiveDateTime>,
+    activities: HashMap<Activity, ActivityState>,
 }
 
 impl ApplicationState {
+    #[cfg(test)]
     pub(crate) fn blank() -> Self {
         Self {
-            take_pills_pending: None,
-            water_plants_pending: None,
-            i_pending: None,
-            clean_litter_tray_pending: None,
+            activities: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn new(activities: HashMap<Activity, ActivityState>) -> Self {
+        Self { activities }
+    }
+
+    pub(crate) fn activity(&self, activity: Activity) -> ActivityState {
+        self.activities.get(&activity).cloned().unwrap_or_default()
+    }
+
+    pub(crate) fn activity_mut(&mut self, activity: Activity) -> &mut ActivityState {
+        self.activities.entry(activity).or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use chrono::{Duration, NaiveDateTime};
+
+    use crate::{
+        activity::{Acknowledge, Activity, Escalation, EscalationAction},
+        event::{Event, EventKind, EventSource},
+        ledstrategy::LedState,
+    };
+
+    use super::{ActivityState, Press, Replay, Status, Timeout};
+
+    fn at(s: &str) -> NaiveDateTime {
+        NaiveDateTime::from_str(s).unwrap()
+    }
+
+    fn escalations() -> Vec<Escalation> {
+        vec![
+            Escalation {
+                after: Duration::hours(1),
+                action: EscalationAction::BlinkFast,
+            },
+            Escalation {
+                after: Duration::hours(2),
+                action: EscalationAction::Notify {
+                    title: "Title".to_owned(),
+                    message: "Message".to_owned(),
+                },
+            },
+        ]
+    }
+
+    #[test]
+    fn trigger_and_press() {
+        let escalations = escalations();
+        let mut state = ActivityState::default();
+        assert_eq!(state.status(&escalations), Status::Idle);
+        assert_eq!(state.led_state(&escalations), LedState::Off);
+
+        state.trigger(at("2020-01-01T09:00:00"));
+        assert_eq!(state.status(&escalations), Status::Pending);
+        assert_eq!(state.led_state(&escalations), LedState::On);
+
+        let press = state.press(
+            Acknowledge::Oldest,
+            None,
+            &escalations,
+            at("2020-01-01T09:30:00"),
+        );
+        assert_eq!(
+            press,
+            Press {
+                acknowledged: vec![at("2020-01-01T09:00:00")],
+                completed_early: None,
+            }
+        );
+        assert_eq!(state, ActivityState::default());
+    }
+
+    #[test]
+    fn escalation_and_snooze() {
+        let escalations = escalations();
+        let mut state = ActivityState::default();
+        state.trigger(at("2020-01-01T09:00:00"));
+        assert_eq!(
+            state.next_wakeup(None, &escalations),
+            Some(at("2020-01-01T10:00:00"))
+        );
+
+        let timeout = state.timeout(at("2020-01-01T10:00:00"), None, &escalations);
+        assert_eq!(timeout.escalations, vec![(0, at("2020-01-01T09:00:00"))]);
+        assert_eq!(state.status(&escalations), Status::Overdue);
+        assert_eq!(state.led_state(&escalations), LedState::BlinkFast);
+
+        // Snoozing holds off the next escalation until it's over
+        assert_eq!(
+            state.snooze(at("2020-01-01T12:00:00")),
+            Some(at("2020-01-01T09:00:00"))
+        );
+        assert_eq!(state.status(&escalations), Status::Snoozed);
+        assert_eq!(state.led_state(&escalations), LedState::Off);
+        assert_eq!(
+            state.next_wakeup(None, &escalations),
+            Some(at("2020-01-01T12:00:00"))
+        );
+        assert_eq!(
+            state.timeout(at("2020-01-01T11:00:00"), None, &escalations),
+            Timeout::default()
+        );
+
+        let timeout = state.timeout(at("2020-01-01T12:00:00"), None, &escalations);
+        assert_eq!(timeout.escalations, vec![(1, at("2020-01-01T09:00:00"))]);
+        assert!(timeout.changed);
+        assert_eq!(state.status(&escalations), Status::Overdue);
+        assert_eq!(state.next_wakeup(None, &escalations), None);
+    }
+
+    #[test]
+    fn queue_and_miss() {
+        let deadline = Some(Duration::hours(12));
+        let mut state = ActivityState::default();
+        state.trigger(at("2020-01-01T09:00:00"));
+        state.trigger(at("2020-01-01T18:00:00"));
+        assert_eq!(state.outstanding(), 2);
+        assert_eq!(state.led_state(&[]), LedState::BlinkCount(2));
+
+        let timeout = state.timeout(at("2020-01-01T21:00:00"), deadline, &[]);
+        assert_eq!(timeout.missed, vec![at("2020-01-01T09:00:00")]);
+        assert_eq!(state.pending, Some(at("2020-01-01T18:00:00")));
+        assert!(state.queued.is_empty());
+        // The later one's still waiting to be done
+        assert_eq!(state.status(&[]), Status::Pending);
+
+        let timeout = state.timeout(at("2020-01-02T06:00:00"), deadline, &[]);
+        assert_eq!(timeout.missed, vec![at("2020-01-01T18:00:00")]);
+        assert_eq!(state.status(&[]), Status::Missed);
+        assert_eq!(state.led_state(&[]), LedState::BlinkSlow);
+
+        // Pressing says it's been seen
+        let press = state.press(Acknowledge::Oldest, None, &[], at("2020-01-02T07:00:00"));
+        assert_eq!(press.acknowledged, vec![at("2020-01-01T18:00:00")]);
+        assert_eq!(state.status(&[]), Status::Idle);
+    }
+
+    #[test]
+    fn press_all() {
+        let mut state = ActivityState::default();
+        state.trigger(at("2020-01-01T09:00:00"));
+        state.trigger(at("2020-01-02T09:00:00"));
+        state.trigger(at("2020-01-03T09:00:00"));
+
+        let press = state.press(Acknowledge::All, None, &[], at("2020-01-03T10:00:00"));
+        assert_eq!(
+            press.acknowledged,
+            vec![
+                at("2020-01-01T09:00:00"),
+                at("2020-01-02T09:00:00"),
+                at("2020-01-03T09:00:00"),
+            ]
+        );
+        assert_eq!(state, ActivityState::default());
+    }
+
+    #[test]
+    fn press_oldest_catches_up_escalations() {
+        let escalations = escalations();
+        let mut state = ActivityState::default();
+        state.trigger(at("2020-01-01T09:00:00"));
+        state.trigger(at("2020-01-01T10:00:00"));
+        state.timeout(at("2020-01-01T10:00:00"), None, &escalations);
+
+        // The next one's been waiting long enough to blink fast, without
+        // escalating it again
+        state.press(
+            Acknowledge::Oldest,
+            None,
+            &escalations,
+            at("2020-01-01T11:30:00"),
+        );
+        assert_eq!(state.pending, Some(at("2020-01-01T10:00:00")));
+        assert_eq!(state.escalation_level, 1);
+        assert_eq!(state.status(&escalations), Status::Overdue);
+        assert_eq!(
+            state.timeout(at("2020-01-01T11:30:00"), None, &escalations),
+            Timeout::default()
+        );
+    }
+
+    #[test]
+    fn timer() {
+        let ends = at("2020-01-01T09:04:00");
+        let mut state = ActivityState::default();
+        assert!(!state.clear_timer());
+        assert!(state.start_timer(ends));
+        assert!(!state.start_timer(at("2020-01-01T09:10:00")));
+        assert_eq!(state.status(&[]), Status::TimerRunning);
+        assert!(!state.finish_timer(at("2020-01-01T09:03:59")));
+
+        assert!(state.finish_timer(ends));
+        assert!(!state.finish_timer(ends));
+        assert_eq!(state.status(&[]), Status::TimerFinished);
+        assert_eq!(state.led_state(&[]), LedState::BlinkFast);
+
+        // Something to do comes first
+        state.trigger(at("2020-01-01T09:05:00"));
+        assert_eq!(state.status(&[]), Status::Pending);
+        assert!(!state.clear_timer());
+        state.press(Acknowledge::Oldest, None, &[], at("2020-01-01T09:06:00"));
+        assert!(state.clear_timer());
+        assert_eq!(state, ActivityState::default());
+    }
+
+    #[test]
+    fn complete_early_and_undo() {
+        let trigger = at("2020-01-01T18:00:00");
+        let mut state = ActivityState::default();
+        let previous = state.clone();
+
+        let press = state.press(
+            Acknowledge::All,
+            Some(trigger),
+            &[],
+            at("2020-01-01T09:00:00"),
+        );
+        assert_eq!(press.completed_early, Some(trigger));
+        assert_eq!(state.status(&[]), Status::CompletedEarly);
+        assert_eq!(state.next_wakeup(None, &[]), Some(trigger));
+
+        // Doing it again doesn't count
+        let press = state.press(
+            Acknowledge::All,
+            Some(trigger),
+            &[],
+            at("2020-01-01T09:01:00"),
+        );
+        assert_eq!(press.completed_early, None);
+
+        state.undo(previous);
+        assert_eq!(state.status(&[]), Status::Idle);
+
+        state.press(
+            Acknowledge::All,
+            Some(trigger),
+            &[],
+            at("2020-01-01T09:02:00"),
+        );
+        let timeout = state.timeout(trigger, None, &[]);
+        assert!(timeout.changed);
+        assert_eq!(state, ActivityState::default());
+    }
+
+    fn event(
+        kind: EventKind,
+        occurred_at: &str,
+        pending_since: Option<&str>,
+        detail: Option<&str>,
+    ) -> Event {
+        Event {
+            activity: Activity::TakePills,
+            kind,
+            occurred_at: at(occurred_at),
+            pending_since: pending_since.map(at),
+            source: EventSource::System,
+            who: None,
+            detail: detail.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn replay() {
+        let mut replay = Replay::new(ActivityState::default());
+        for event in [
+            event(
+                EventKind::Triggered,
+                "2020-01-01T09:00:00",
+                Some("2020-01-01T09:00:00"),
+                None,
+            ),
+            event(
+                EventKind::Triggered,
+                "2020-01-02T09:00:00",
+                Some("2020-01-02T09:00:00"),
+                None,
+            ),
+            event(
+                EventKind::Snoozed,
+                "2020-01-02T09:30:00",
+                Some("2020-01-01T09:00:00"),
+                Some("2020-01-02T10:30:00.000000000Z"),
+            ),
+            event(
+                EventKind::Escalated,
+                "2020-01-02T10:00:00",
+                Some("2020-01-01T09:00:00"),
+                None,
+            ),
+        ] {
+            replay.apply(&event);
+        }
+        let snoozed = ActivityState {
+            pending: Some(at("2020-01-01T09:00:00")),
+            queued: vec![at("2020-01-02T09:00:00")],
+            snoozed_until: Some(at("2020-01-02T10:30:00")),
+            ..ActivityState::default()
+        };
+        assert_eq!(replay.state, snoozed);
+
+        // Pressed by mistake, then undone
+        for event in [
+            event(
+                EventKind::Acknowledged,
+                "2020-01-02T10:00:00",
+                Some("2020-01-01T09:00:00"),
+                None,
+            ),
+            event(
+                EventKind::Acknowledged,
+                "2020-01-02T10:00:00",
+                Some("2020-01-02T09:00:00"),
+                None,
+            ),
+        ] {
+            replay.apply(&event);
+        }
+        assert_eq!(replay.state, ActivityState::default());
+        for event in [
+            event(
+                EventKind::Undone,
+                "2020-01-02T10:00:02",
+                Some("2020-01-01T09:00:00"),
+                None,
+            ),
+            event(
+                EventKind::Undone,
+                "2020-01-02T10:00:02",
+                Some("2020-01-02T09:00:00"),
+                None,
+            ),
+        ] {
+            replay.apply(&event);
+        }
+        assert_eq!(replay.state, snoozed);
+
+        // The first one's missed, then the second one's done
+        for event in [
+            event(
+                EventKind::Missed,
+                "2020-01-02T21:00:00",
+                Some("2020-01-01T09:00:00"),
+                None,
+            ),
+            event(
+                EventKind::Acknowledged,
+                "2020-01-02T22:00:00",
+                Some("2020-01-02T09:00:00"),
+                None,
+            ),
+            event(
+                EventKind::CompletedEarly,
+                "2020-01-02T23:00:00",
+                Some("2020-01-03T09:00:00"),
+                None,
+            ),
+        ] {
+            replay.apply(&event);
         }
+        assert_eq!(
+            replay.finish(),
+            ActivityState {
+                completed_early: Some(at("2020-01-03T09:00:00")),
+                ..ActivityState::default()
+            }
+        );
     }
 }
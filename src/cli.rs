@@ -0,0 +1,373 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Context, Result};
+use chrono::{Duration, NaiveDateTime};
+
+use crate::{
+    activity::Activity,
+    appdb::AppDb,
+    clock::Clock,
+    event::{Event, EventKind, EventSource},
+    rotation::{self, Rotation},
+    scheduler::{ScheduleAdjustment, ScheduledJobSpec},
+    stats::{activity_stats, ActivityStats},
+    suggest,
+};
+
+const USAGE: &str = "usage: fourbuttons [stats [--within-hours N]]
+                   [rotation [ACTIVITY PERSON]]
+                   [away PERSON | back PERSON]
+                   [rebuild-state]
+                   [stock | restock ACTIVITY AMOUNT]
+                   [suggest [--apply]]";
+
+// How far back `suggest` looks.
+const SUGGEST_FROM: Duration = Duration::weeks(4);
+
+/// Run a command given on the command line instead of the daemon.
+pub(crate) fn run(
+    args: &[String],
+    db: &AppDb,
+    clock: &dyn Clock,
+    people: &[String],
+    rotations: &HashMap<Activity, Rotation>,
+    job_specs: &[ScheduledJobSpec],
+) -> Result<()> {
+    match args {
+        [command, rest @ ..] if command == "stats" => stats(rest, db, clock),
+        [command] if command == "rotation" => show_rotations(db, rotations),
+        [command, activity, person] if command == "rotation" => {
+            swap_rotation(db, clock, rotations, activity, person)
+        }
+        [command, person] if command == "away" => {
+            set_away(db, clock, people, rotations, person, true)
+        }
+        [command, person] if command == "back" => {
+            set_away(db, clock, people, rotations, person, false)
+        }
+        [command] if command == "rebuild-state" => rebuild_state(db),
+        [command] if command == "stock" => show_stock(db),
+        [command, activity, amount] if command == "restock" => restock(db, clock, activity, amount),
+        [command] if command == "suggest" => suggest(db, clock, job_specs, false),
+        [command, flag] if command == "suggest" && flag == "--apply" => {
+            suggest(db, clock, job_specs, true)
+        }
+        _ => bail!(USAGE),
+    }
+}
+
+fn show_rotations(db: &AppDb, rotations: &HashMap<Activity, Rotation>) -> Result<()> {
+    let away = db.load_away()?;
+    for activity in Activity::ALL {
+        let Some(rotation) = rotations.get(&activity) else {
+            continue;
+        };
+        let on_duty = rotation::on_duty(db, activity, rotation)?;
+        println!(
+            "{}: {}'s turn, taking turns between {}",
+            activity.id(),
+            on_duty.as_deref().unwrap_or("nobody"),
+            rotation.people.join(", ")
+        );
+    }
+    for person in away {
+        println!("{person} is away");
+    }
+    Ok(())
+}
+
+/// Make it someone else's turn.
+fn swap_rotation(
+    db: &AppDb,
+    clock: &dyn Clock,
+    rotations: &HashMap<Activity, Rotation>,
+    activity_id: &str,
+    person: &str,
+) -> Result<()> {
+    let Some(activity) = Activity::from_id(activity_id) else {
+        bail!("Unknown activity {activity_id}");
+    };
+    let Some(rotation) = rotations.get(&activity) else {
+        bail!("{activity_id} doesn't have a rotation");
+    };
+    if !rotation.people.iter().any(|p| p == person) {
+        bail!("{person} isn't in the {activity_id} rotation");
+    }
+    db.update_on_duty(activity, person)?;
+    record_event(db, clock, activity, EventKind::TurnSwapped, person)
+}
+
+/// Skip `person` in the rotations while they're away, or stop skipping
+/// them once they're back.
+fn set_away(
+    db: &AppDb,
+    clock: &dyn Clock,
+    people: &[String],
+    rotations: &HashMap<Activity, Rotation>,
+    person: &str,
+    away: bool,
+) -> Result<()> {
+    if !people.iter().any(|p| p == person) {
+        bail!("{person} isn't in people");
+    }
+    db.set_away(person, away)?;
+    let kind = if away {
+        EventKind::Away
+    } else {
+        EventKind::Back
+    };
+    for activity in Activity::ALL {
+        if rotations
+            .get(&activity)
+            .is_some_and(|rotation| rotation.people.iter().any(|p| p == person))
+        {
+            record_event(db, clock, activity, kind, person)?;
+        }
+    }
+    Ok(())
+}
+
+/// Replay the whole event log, replacing the saved state.  Stop the daemon
+/// first or it'll just save its own over the top.
+fn rebuild_state(db: &AppDb) -> Result<()> {
+    for activity in Activity::ALL {
+        let state = db.rebuild_activity_state(activity)?;
+        println!("{}: {:?}", activity.id(), state);
+    }
+    Ok(())
+}
+
+fn show_stock(db: &AppDb) -> Result<()> {
+    for activity in Activity::ALL {
+        if let Some(stock) = db.load_stock(activity)? {
+            println!("{}: {} left", activity.id(), stock.remaining);
+        }
+    }
+    Ok(())
+}
+
+/// Record that more of what `activity` uses has been bought.  The first
+/// restock starts it being counted.
+fn restock(db: &AppDb, clock: &dyn Clock, activity_id: &str, amount: &str) -> Result<()> {
+    let Some(activity) = Activity::from_id(activity_id) else {
+        bail!("Unknown activity {activity_id}");
+    };
+    let amount = amount.parse().context("Invalid amount")?;
+    if amount <= 0 {
+        bail!("Amount must be positive, not {amount}");
+    }
+    let stock = db.restock(activity, amount)?;
+    record_event(
+        db,
+        clock,
+        activity,
+        EventKind::Restocked,
+        &amount.to_string(),
+    )?;
+    println!("{}: {} left", activity.id(), stock.remaining);
+    Ok(())
+}
+
+/// Keep what was done from the command line in the history too.
+fn record_event(
+    db: &AppDb,
+    clock: &dyn Clock,
+    activity: Activity,
+    kind: EventKind,
+    detail: &str,
+) -> Result<()> {
+    db.insert_event(&Event {
+        activity,
+        kind,
+        occurred_at: clock.now(),
+        pending_since: None,
+        source: EventSource::Cli,
+        who: None,
+        detail: Some(detail.to_owned()),
+    })
+}
+
+/// Suggest moving triggers to when things actually get done, from the
+/// last few weeks' history.  With `apply` they're saved for the daemon to
+/// pick up when it's next restarted.
+fn suggest(
+    db: &AppDb,
+    clock: &dyn Clock,
+    job_specs: &[ScheduledJobSpec],
+    apply: bool,
+) -> Result<()> {
+    let now = clock.now();
+    let adjustments = db.load_schedule_adjustments()?;
+    for spec in job_specs {
+        let Some(activity) = spec.task().activity() else {
+            continue;
+        };
+        let adjustment = adjustments.get(&activity);
+        // Only since the last change, or it'd be suggested all over again
+        let from = adjustment.map_or(now - SUGGEST_FROM, |adjustment| {
+            adjustment.applied_at.max(now - SUGGEST_FROM)
+        });
+        let events = db.load_activity_events(activity, from, now)?;
+        let Some(suggestion) = suggest::suggest(&events) else {
+            println!("{}: not enough history yet", activity.id());
+            continue;
+        };
+        println!(
+            "{}: done a median {} after it's triggered, from {} occurrences",
+            activity.id(),
+            fmt_signed_duration(suggestion.median_latency),
+            suggestion.occurrences
+        );
+        let Some(shift) = suggestion.shift else {
+            println!("  no changes suggested");
+            continue;
+        };
+        println!("  trigger it {}", fmt_shift(shift));
+        // On top of what's already been applied
+        let shift = shift + adjustment.map_or(Duration::zero(), |adjustment| adjustment.shift);
+        if spec.schedule().shifted(shift).is_none() {
+            println!("  can't be applied, it would move to another day");
+        } else if apply {
+            db.update_schedule_adjustment(
+                activity,
+                &ScheduleAdjustment {
+                    shift,
+                    applied_at: now,
+                },
+            )?;
+            println!("  applied, restart the daemon to use it");
+        }
+    }
+    Ok(())
+}
+
+fn fmt_shift(shift: Duration) -> String {
+    if shift < Duration::zero() {
+        format!("{} earlier", fmt_duration(-shift))
+    } else {
+        format!("{} later", fmt_duration(shift))
+    }
+}
+
+fn fmt_signed_duration(duration: Duration) -> String {
+    if duration < Duration::zero() {
+        format!("-{}", fmt_duration(-duration))
+    } else {
+        fmt_duration(duration)
+    }
+}
+
+fn stats(args: &[String], db: &AppDb, clock: &dyn Clock) -> Result<()> {
+    let within = match args {
+        [] => Duration::hours(2),
+        [flag, hours] if flag == "--within-hours" => {
+            Duration::hours(hours.parse().context("Invalid number of hours")?)
+        }
+        _ => bail!(USAGE),
+    };
+
+    for activity in Activity::ALL {
+        let events = db.load_activity_events(activity, NaiveDateTime::default(), clock.now())?;
+        print_stats(activity, &activity_stats(&events, within));
+    }
+
+    Ok(())
+}
+
+fn print_stats(activity: Activity, stats: &ActivityStats) {
+    println!("{}: {} occurrences", activity.id(), stats.occurrences);
+    if stats.occurrences == 0 {
+        return;
+    }
+    println!(
+        "  streak {} (longest {})",
+        stats.current_streak, stats.longest_streak
+    );
+    if let Some(percent) = stats.done_within_percent {
+        println!(
+            "  {:.0}% done within {}",
+            percent,
+            fmt_duration(stats.within)
+        );
+    }
+    if let Some(median) = stats.median_time_to_acknowledge {
+        println!("  median time to acknowledge {}", fmt_duration(median));
+    }
+    for (week_start, missed) in &stats.missed_per_week {
+        println!("  missed {missed} in week starting {week_start}");
+    }
+    for ((year, month), missed) in &stats.missed_per_month {
+        println!("  missed {missed} in {year}-{month:02}");
+    }
+    for (who, done) in &stats.done_by {
+        println!("  {who} did {done}");
+    }
+}
+
+fn fmt_duration(duration: Duration) -> String {
+    format!(
+        "{}h{:02}m",
+        duration.num_hours(),
+        duration.num_minutes() % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use chrono::{Duration, NaiveDateTime};
+
+    use crate::{
+        activity::Activity,
+        appdb::AppDb,
+        clock::testhelper::ManualClock,
+        config,
+        event::{EventKind, EventSource},
+    };
+
+    use super::set_away;
+
+    #[test]
+    fn away_and_back() {
+        let db = AppDb::new_tmp();
+        db.run_migrations().unwrap();
+        let now = NaiveDateTime::from_str("2020-01-01T09:00:00").unwrap();
+        let clock = ManualClock::new(now);
+        let people = vec!["Alex".to_owned(), "Sam".to_owned()];
+        let rotations = config::rotations(&people);
+
+        set_away(&db, &clock, &people, &rotations, "Sam", true).unwrap();
+        assert!(db.load_away().unwrap().contains("Sam"));
+        clock.advance(Duration::days(1));
+        set_away(&db, &clock, &people, &rotations, "Sam", false).unwrap();
+        assert!(db.load_away().unwrap().is_empty());
+
+        let events = db
+            .load_activity_events(Activity::WaterPlants, now, now + Duration::days(2))
+            .unwrap();
+        assert_eq!(
+            events
+                .iter()
+                .map(|event| (event.kind, event.source, event.detail.as_deref()))
+                .collect::<Vec<_>>(),
+            vec![
+                (EventKind::Away, EventSource::Cli, Some("Sam")),
+                (EventKind::Back, EventSource::Cli, Some("Sam")),
+            ]
+        );
+    }
+
+    #[test]
+    fn away_only_for_people() {
+        let db = AppDb::new_tmp();
+        db.run_migrations().unwrap();
+        let clock = ManualClock::new(NaiveDateTime::from_str("2020-01-01T09:00:00").unwrap());
+        let people = vec!["Alex".to_owned(), "Sam".to_owned()];
+        let rotations = config::rotations(&people);
+
+        // A typo would otherwise be away forever, skipping nobody
+        assert!(set_away(&db, &clock, &people, &rotations, "sam", true).is_err());
+        assert!(db.load_away().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,124 @@
+use std::{sync::Arc, time::Instant};
+
+use chrono::{Local, NaiveDateTime};
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+use log::warn;
+
+/// Source of both wall clock and monotonic time, so that tests can control
+/// time rather than sleeping.
+pub(crate) trait Clock {
+    /// Local wall clock time, used for anything to do with schedules.
+    fn now(&self) -> NaiveDateTime;
+    /// Monotonic time, used for measuring short intervals like LED blinks.
+    fn instant(&self) -> Instant;
+    /// Whether the wall clock has been synchronised (e.g. by NTP).  The Pi has
+    /// no RTC, so until this is true `now` could be hours or days out.
+    fn is_synchronised(&self) -> bool;
+}
+
+pub(crate) type SharedClock = Arc<dyn Clock + Send + Sync>;
+
+pub(crate) struct SystemClock {}
+
+impl Clock for SystemClock {
+    fn now(&self) -> NaiveDateTime {
+        Local::now().naive_local()
+    }
+
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+
+    #[cfg(all(target_os = "linux", target_env = "gnu"))]
+    fn is_synchronised(&self) -> bool {
+        // SAFETY: timex is plain old data, and with `modes` zeroed adjtimex
+        // only reads the kernel clock state into it.
+        let mut timex: libc::timex = unsafe { std::mem::zeroed() };
+        let state = unsafe { libc::adjtimex(&raw mut timex) };
+        if state == -1 {
+            // Better to trust the clock than to never trigger anything
+            warn!(
+                "adjtimex failed, assuming clock is synchronised: {}",
+                std::io::Error::last_os_error()
+            );
+            true
+        } else {
+            state != libc::TIME_ERROR
+        }
+    }
+
+    #[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+    fn is_synchronised(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod testhelper {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Instant,
+    };
+
+    use chrono::{Duration, NaiveDateTime};
+
+    use super::{Clock, SharedClock};
+
+    struct ManualTime {
+        now: NaiveDateTime,
+        instant: Instant,
+        is_synchronised: bool,
+    }
+
+    /// A clock which only moves when told to.  Clones share the same time.
+    #[derive(Clone)]
+    pub(crate) struct ManualClock {
+        time: Arc<Mutex<ManualTime>>,
+    }
+
+    impl ManualClock {
+        pub(crate) fn new(now: NaiveDateTime) -> Self {
+            Self {
+                time: Arc::new(Mutex::new(ManualTime {
+                    now,
+                    instant: Instant::now(),
+                    is_synchronised: true,
+                })),
+            }
+        }
+
+        pub(crate) fn shared(&self) -> SharedClock {
+            Arc::new(self.clone())
+        }
+
+        /// Moves both the wall clock and the monotonic clock forwards.
+        pub(crate) fn advance(&self, duration: Duration) {
+            let mut time = self.time.lock().unwrap();
+            time.now += duration;
+            time.instant += duration.to_std().unwrap();
+        }
+
+        /// Moves only the wall clock, like NTP stepping the system time.
+        pub(crate) fn set_now(&self, now: NaiveDateTime) {
+            self.time.lock().unwrap().now = now;
+        }
+
+        pub(crate) fn set_synchronised(&self, is_synchronised: bool) {
+            self.time.lock().unwrap().is_synchronised = is_synchronised;
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> NaiveDateTime {
+            self.time.lock().unwrap().now
+        }
+
+        fn instant(&self) -> Instant {
+            self.time.lock().unwrap().instant
+        }
+
+        fn is_synchronised(&self) -> bool {
+            self.time.lock().unwrap().is_synchronised
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use std::{
+    os::unix::process::CommandExt,
+    process::{Command, ExitStatus, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+
+// How often to check whether the command's finished yet.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Run `command` with `sh -c`, with `envs` added to its environment.  If
+/// it's still going after `timeout` it's killed, along with anything it
+/// started, and that's an error.
+pub(crate) fn run_with_timeout(
+    command: &str,
+    envs: &[(&str, String)],
+    timeout: Duration,
+) -> Result<ExitStatus> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .envs(envs.iter().map(|(key, value)| (key, value)))
+        .stdin(Stdio::null())
+        // Its own process group, so that on timeout whatever it started can
+        // be killed too rather than being left running
+        .process_group(0)
+        .spawn()
+        .with_context(|| format!("Failed to run {command}"))?;
+
+    let started = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait().context("Failed to wait for command")? {
+            return Ok(status);
+        }
+        if started.elapsed() >= timeout {
+            // The group's id is the child's pid.  It might have finished just
+            // now, in which case this fails.
+            let pgid = libc::pid_t::try_from(child.id()).context("Invalid pid")?;
+            // SAFETY: killpg only sends a signal, and the group can't have
+            // been reused while the child's still unwaited for.
+            unsafe { libc::killpg(pgid, libc::SIGKILL) };
+            let _ = child.wait();
+            bail!("{command} timed out after {timeout:?}");
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs, thread,
+        time::{Duration, Instant},
+    };
+
+    use crate::db::testhelper::TmpFile;
+
+    use super::run_with_timeout;
+
+    // Neither gone nor a zombie waiting to be reaped by whoever it was
+    // reparented to.  The state follows the parenthesised command name.
+    fn is_running(pid: &str) -> bool {
+        fs::read_to_string(format!("/proc/{pid}/stat")).is_ok_and(|stat| {
+            let (_, rest) = stat.rsplit_once(") ").unwrap();
+            !rest.starts_with('Z')
+        })
+    }
+
+    #[test]
+    fn exit_status_and_timeout() {
+        let timeout = Duration::from_secs(5);
+        assert!(run_with_timeout("true", &[], timeout).unwrap().success());
+        assert_eq!(
+            run_with_timeout("exit 3", &[], timeout).unwrap().code(),
+            Some(3)
+        );
+        assert!(
+            run_with_timeout("test \"$WHO\" = Sam", &[("WHO", "Sam".to_owned())], timeout)
+                .unwrap()
+                .success()
+        );
+
+        assert!(run_with_timeout("sleep 5", &[], Duration::from_millis(100)).is_err());
+    }
+
+    #[test]
+    fn timeout_kills_what_it_started() {
+        let pid_file = TmpFile::new();
+        let command = format!("sleep 30 & echo $! > {}; wait", pid_file.path);
+        assert!(run_with_timeout(&command, &[], Duration::from_millis(200)).is_err());
+
+        let pid = fs::read_to_string(&pid_file.path).unwrap();
+        let pid = pid.trim();
+        let started = Instant::now();
+        while is_running(pid) {
+            assert!(
+                started.elapsed() < Duration::from_secs(5),
+                "sleep {pid} still running"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use std::{fs, time::Duration};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Local, NaiveDateTime};
+
+use crate::command;
+
+// Long enough to ask something on the local network, short enough not to
+// hold up the other checks much.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+
+// A reading older than this is from a sensor that's stopped, so can't be
+// trusted.
+const MAX_READING_AGE: Duration = Duration::from_hours(24);
+
+/// Checked by the `ConditionActor` when a job's due, to see whether it's
+/// actually needed.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Condition {
+    /// Needed if the number in the file at `path` is below `below`, e.g. a
+    /// soil moisture reading written by something else.
+    FileBelow { path: String, below: f64 },
+    /// Needed if `command` succeeds.  It's killed after `COMMAND_TIMEOUT`,
+    /// which is an error so it's needed after all.
+    Command { command: String },
+}
+
+impl Condition {
+    /// `None` if it's needed at `now`, otherwise why not.
+    pub(crate) fn not_needed(&self, now: NaiveDateTime) -> Result<Option<String>> {
+        match self {
+            Condition::FileBelow { path, below } => {
+                let modified = fs::metadata(path)
+                    .and_then(|metadata| metadata.modified())
+                    .with_context(|| format!("Failed to read {path}"))?;
+                let age = (now - DateTime::<Local>::from(modified).naive_local())
+                    .to_std()
+                    // Written after now, as far as the clock's concerned
+                    .unwrap_or_default();
+                if age > MAX_READING_AGE {
+                    bail!("{path} hasn't been updated for {age:?}");
+                }
+                let contents =
+                    fs::read_to_string(path).with_context(|| format!("Failed to read {path}"))?;
+                let value: f64 = contents
+                    .trim()
+                    .parse()
+                    .with_context(|| format!("Invalid number in {path}"))?;
+                Ok((value >= *below).then(|| format!("{path} reads {value}, not below {below}")))
+            }
+            Condition::Command { command } => {
+                let status = command::run_with_timeout(command, &[], COMMAND_TIMEOUT)?;
+                Ok((!status.success()).then(|| format!("{command} exited with {status}")))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use chrono::{Duration, Local};
+
+    use crate::db::testhelper::TmpFile;
+
+    use super::Condition;
+
+    #[test]
+    fn file_below() {
+        let file = TmpFile::new();
+        let condition = Condition::FileBelow {
+            path: file.path.clone(),
+            below: 40.0,
+        };
+        let now = Local::now().naive_local();
+        // Can't tell without a reading
+        assert!(condition.not_needed(now).is_err());
+
+        fs::write(&file.path, "35.5\n").unwrap();
+        assert_eq!(condition.not_needed(now).unwrap(), None);
+        fs::write(&file.path, "52").unwrap();
+        assert_eq!(
+            condition.not_needed(now).unwrap(),
+            Some(format!("{} reads 52, not below 40", file.path))
+        );
+
+        // Nor if the sensor's stopped updating it
+        assert!(condition.not_needed(now + Duration::hours(25)).is_err());
+
+        fs::write(&file.path, "wet").unwrap();
+        assert!(condition.not_needed(now).is_err());
+    }
+
+    #[test]
+    fn command() {
+        let now = Local::now().naive_local();
+        let condition = |command: &str| Condition::Command {
+            command: command.to_owned(),
+        };
+        assert_eq!(condition("true").not_needed(now).unwrap(), None);
+        assert_eq!(
+            condition("false").not_needed(now).unwrap(),
+            Some("false exited with exit status: 1".to_owned())
+        );
+    }
+}
@@ -0,0 +1,346 @@
+use std::{collections::HashMap, path::Path, str::FromStr};
+
+use anyhow::{Context, Result};
+use chrono::{Duration, NaiveDate, NaiveTime, Weekday};
+
+use crate::{
+    activity::{
+        Acknowledge, Activity, ActivityConfig, Consumable, Escalation, EscalationAction, FollowUp,
+        Hook, Notification, Timer,
+    },
+    actor::{
+        control_actor::{led_for, ControlConfig},
+        digest_actor::DigestConfig,
+    },
+    condition::Condition,
+    event::EventKind,
+    rotation::{Rotation, RotationAdvance},
+    schedule::{every_day, DailySchedule, Schedule, WeeklySchedule},
+    scheduler::{ScheduledJobSpec, Task},
+};
+
+// Everything about what the box does and when.  Secrets (API keys, email
+// addresses) live in separate files next to the binary, see main.rs.
+
+pub(crate) fn job_specs() -> Result<Vec<ScheduledJobSpec>> {
+    let clean_litter_tray = ScheduledJobSpec::new(
+        Schedule::Daily(DailySchedule::new(
+            NaiveTime::from_hms_milli_opt(6, 0, 0, 0).context("Invalid schedule")?,
+            vec![Weekday::Sat, Weekday::Wed],
+        )),
+        Activity::CleanLitterTray,
+        Duration::hours(1),
+    );
+    // Optional, e.g. asking a litter box that counts visits whether it's
+    // been used since last time.
+    let clean_litter_tray = match check_command(Activity::CleanLitterTray) {
+        Some(condition) => clean_litter_tray.with_condition(condition),
+        None => clean_litter_tray,
+    };
+    Ok(vec![
+        ScheduledJobSpec::new(
+            Schedule::Daily(DailySchedule::new(
+                NaiveTime::from_hms_milli_opt(6, 0, 0, 0).context("Invalid schedule")?,
+                every_day(),
+            )),
+            Activity::TakePills,
+            Duration::hours(1),
+        ),
+        ScheduledJobSpec::new(
+            Schedule::Daily(DailySchedule::new(
+                NaiveTime::from_hms_milli_opt(6, 0, 0, 0).context("Invalid schedule")?,
+                vec![Weekday::Sat, Weekday::Wed],
+            )),
+            Activity::WaterPlants,
+            Duration::hours(1),
+        )
+        // Optional, the moisture sensor writes its percentage here.  Without
+        // a recent reading they're watered on schedule.
+        .with_condition(Condition::FileBelow {
+            path: "./soil-moisture".to_owned(),
+            below: 40.0,
+        }),
+        ScheduledJobSpec::new(
+            Schedule::Weekly(WeeklySchedule::new(
+                NaiveDate::from_str("2024-03-13").context("Invalid schedule start")?,
+                NaiveTime::from_hms_milli_opt(6, 0, 0, 0).context("Invalid schedule")?,
+                2,
+            )),
+            Activity::I,
+            Duration::hours(12),
+        ),
+        clean_litter_tray,
+    ])
+}
+
+// An executable in `conditions` named after the activity, which exits
+// non-zero when it isn't needed.  Like hooks, picked up when the daemon
+// starts.
+fn check_command(activity: Activity) -> Option<Condition> {
+    let command = format!("./conditions/{}", activity.id());
+    Path::new(&command)
+        .exists()
+        .then_some(Condition::Command { command })
+}
+
+pub(crate) fn control_config(
+    secondary_address: Option<String>,
+    people: Vec<String>,
+    addresses: HashMap<String, String>,
+    job_specs: &[ScheduledJobSpec],
+) -> ControlConfig {
+    ControlConfig {
+        snooze_duration: Duration::hours(1),
+        activity_configs: activity_configs(secondary_address, &people),
+        people,
+        addresses,
+        schedules: job_specs
+            .iter()
+            .filter_map(|spec| Some((spec.task().activity()?, spec.schedule().clone())))
+            .collect(),
+    }
+}
+
+/// The household takes turns at the shared chores, if there's more than one
+/// of us.
+pub(crate) fn rotations(people: &[String]) -> HashMap<Activity, Rotation> {
+    if people.len() < 2 {
+        return HashMap::new();
+    }
+    [
+        // Your turn until you've done it
+        (Activity::WaterPlants, RotationAdvance::OnCompletion),
+        // A day each, whoever ends up doing it
+        (Activity::CleanLitterTray, RotationAdvance::OnTrigger),
+    ]
+    .into_iter()
+    .map(|(activity, advance)| {
+        (
+            activity,
+            Rotation {
+                people: people.to_vec(),
+                advance,
+            },
+        )
+    })
+    .collect()
+}
+
+fn activity_configs(
+    secondary_address: Option<String>,
+    people: &[String],
+) -> HashMap<Activity, ActivityConfig> {
+    let mut rotations = rotations(people);
+
+    [
+        (
+            Activity::TakePills,
+            ActivityConfig {
+                escalations: take_pills_escalations(secondary_address),
+                // Too late to take today's, just wait for tomorrow's
+                deadline: Some(Duration::hours(12)),
+                rotation: None,
+                acknowledge: Acknowledge::Oldest,
+                // Not before they're due!
+                complete_early: None,
+                // They need taking with food
+                follow_ups: vec![FollowUp {
+                    activity: Activity::Eat,
+                    after: Duration::minutes(30),
+                }],
+                notification: None,
+                timer: None,
+                consumable: Some(Consumable {
+                    name: "pills".to_owned(),
+                    per_acknowledgement: 1,
+                    refill_within: Duration::days(7),
+                }),
+                hooks: hooks(),
+            },
+        ),
+        (
+            Activity::WaterPlants,
+            ActivityConfig {
+                escalations: vec![
+                    Escalation {
+                        after: Duration::hours(12),
+                        action: EscalationAction::BlinkFast,
+                    },
+                    // To whoever's turn it is
+                    Escalation {
+                        after: Duration::days(1),
+                        action: EscalationAction::Notify {
+                            title: "The plants need watering".to_owned(),
+                            message: "The plants still haven't been watered".to_owned(),
+                        },
+                    },
+                ],
+                deadline: Some(Duration::days(3)),
+                rotation: rotations.remove(&Activity::WaterPlants),
+                // Once is enough however many times it's come round
+                acknowledge: Acknowledge::All,
+                // Watered the evening before is fine
+                complete_early: Some(Duration::hours(12)),
+                follow_ups: vec![FollowUp {
+                    activity: Activity::CheckDrainageTrays,
+                    after: Duration::days(2),
+                }],
+                notification: None,
+                timer: None,
+                consumable: None,
+                hooks: hooks(),
+            },
+        ),
+        (
+            Activity::CleanLitterTray,
+            ActivityConfig {
+                escalations: vec![
+                    Escalation {
+                        after: Duration::hours(12),
+                        action: EscalationAction::BlinkFast,
+                    },
+                    // To whoever's turn it is
+                    Escalation {
+                        after: Duration::days(1),
+                        action: EscalationAction::Notify {
+                            title: "The litter tray needs cleaning".to_owned(),
+                            message: "The litter tray still hasn't been cleaned".to_owned(),
+                        },
+                    },
+                ],
+                deadline: Some(Duration::days(3)),
+                rotation: rotations.remove(&Activity::CleanLitterTray),
+                // One clean catches up on however many were skipped
+                acknowledge: Acknowledge::All,
+                complete_early: Some(Duration::hours(12)),
+                follow_ups: Vec::new(),
+                notification: None,
+                timer: None,
+                // Litres, from the bag
+                consumable: Some(Consumable {
+                    name: "litres of cat litter".to_owned(),
+                    per_acknowledgement: 5,
+                    refill_within: Duration::days(14),
+                }),
+                hooks: hooks(),
+            },
+        ),
+        (
+            Activity::I,
+            ActivityConfig {
+                timer: Some(Timer {
+                    name: "laundry".to_owned(),
+                    duration: Duration::minutes(45),
+                }),
+                hooks: hooks(),
+                ..ActivityConfig::default()
+            },
+        ),
+    ]
+    .into_iter()
+    .chain(reminder_configs())
+    .collect()
+}
+
+/// Gentle at first, then more and more insistent.
+fn take_pills_escalations(secondary_address: Option<String>) -> Vec<Escalation> {
+    let mut escalations = vec![
+        Escalation {
+            after: Duration::hours(2),
+            action: EscalationAction::BlinkFast,
+        },
+        Escalation {
+            after: Duration::hours(5),
+            action: EscalationAction::Notify {
+                title: "Did you forget to take your pills".to_owned(),
+                message: "Did you forget to take your pills you fool".to_owned(),
+            },
+        },
+    ];
+    if let Some(to) = secondary_address {
+        escalations.push(Escalation {
+            after: Duration::hours(8),
+            action: EscalationAction::NotifyRecipient {
+                to,
+                title: "Pills still not taken".to_owned(),
+                message: "The pills still haven't been taken today, maybe check in?".to_owned(),
+            },
+        });
+    }
+    escalations
+}
+
+/// Optional, executables in `hooks` named after a kind of event, e.g.
+/// `hooks/missed`, run whenever that happens to one of the buttons'
+/// activities.
+fn hooks() -> Vec<Hook> {
+    [
+        EventKind::Triggered,
+        EventKind::Acknowledged,
+        EventKind::Escalated,
+        EventKind::Missed,
+        EventKind::Undone,
+    ]
+    .into_iter()
+    .map(|kind| (kind, format!("./hooks/{}", kind.id())))
+    .filter(|(_, path)| Path::new(path).exists())
+    .map(|(on, command)| Hook {
+        on,
+        command,
+        timeout: std::time::Duration::from_secs(30),
+    })
+    .collect()
+}
+
+/// The follow-ups, which don't have buttons so are just emails.
+fn reminder_configs() -> [(Activity, ActivityConfig); 2] {
+    let reminder = |title: &str, message: &str| ActivityConfig {
+        notification: Some(Notification {
+            title: title.to_owned(),
+            message: message.to_owned(),
+        }),
+        ..ActivityConfig::default()
+    };
+    [
+        (
+            Activity::Eat,
+            reminder("Time to eat", "Have something to eat with those pills"),
+        ),
+        (
+            Activity::CheckDrainageTrays,
+            reminder(
+                "Check the drainage trays",
+                "Empty any water left in the plants' drainage trays",
+            ),
+        ),
+    ]
+}
+
+/// Every Sunday evening, a summary of the week.  If it's more than an hour
+/// late (e.g. we were down) it's not worth sending, there'll be another next
+/// week.
+pub(crate) fn digest_job_spec() -> Result<ScheduledJobSpec> {
+    Ok(ScheduledJobSpec::for_task(
+        Schedule::Daily(DailySchedule::new(
+            NaiveTime::from_hms_milli_opt(18, 0, 0, 0).context("Invalid schedule")?,
+            vec![Weekday::Sun],
+        )),
+        Task::SendDigest,
+        Duration::hours(1),
+    ))
+}
+
+/// What goes in the digest.  No recipients means the usual address.
+pub(crate) fn digest_config(recipients: Vec<String>, people: &[String]) -> DigestConfig {
+    DigestConfig {
+        recipients,
+        // Only those with a button, not the follow-ups which are just
+        // reminders
+        activities: Activity::ALL
+            .into_iter()
+            .filter(|activity| led_for(*activity).is_some())
+            .collect(),
+        on_time: Duration::hours(2),
+        rotations: rotations(people),
+    }
+}
@@ -119,7 +119,7 @@ impl Db {
         for migration in migrations {
             info!("Running migration {}", migration.id);
             let conn = self.new_conn()?;
-            conn.execute(migration.sql, ())?;
+            conn.execute_batch(migration.sql)?;
             conn.execute(
                 "
                     INSERT INTO migrations (migration_id)
@@ -155,7 +155,7 @@ pub(crate) mod testhelper {
     // A lazy persons tmp file which drops as soon as it goes
     // out of scope
     pub(crate) struct TmpFile {
-        path: String,
+        pub(crate) path: String,
     }
 
     impl TmpFile {
@@ -3,9 +3,12 @@ use curl::easy::{Auth, Easy, Form};
 use log::info;
 
 pub(crate) trait Emailer {
+    /// Send to the default recipient.
     fn send(&self, title: &str, message: &str) -> Result<()>;
+    fn send_to(&self, to: &str, title: &str, message: &str) -> Result<()>;
 }
 
+#[derive(Clone)]
 pub(crate) struct Email {
     apikey: String,
     to: String,
@@ -19,6 +22,10 @@ impl Email {
 
 impl Emailer for Email {
     fn send(&self, title: &str, message: &str) -> Result<()> {
+        self.send_to(&self.to, title, message)
+    }
+
+    fn send_to(&self, to: &str, title: &str, message: &str) -> Result<()> {
         let mut easy = Easy::new();
         let mut form = Form::new();
         form.part("from")
@@ -26,7 +33,7 @@ impl Emailer for Email {
             .add()
             .context("Failed to add from part")?;
         form.part("to")
-            .contents(self.to.as_bytes())
+            .contents(to.as_bytes())
             .add()
             .context("Failed to add to part")?;
         form.part("subject")
@@ -49,12 +56,12 @@ impl Emailer for Email {
         let response_code = easy.response_code();
 
         if response_code == Ok(200) {
-            info!("Sent email {} to {}", message, self.to);
+            info!("Sent email {} to {}", message, to);
         } else {
             anyhow::bail!(
                 "Failed to send email {} to {}, return code was {:?}",
                 message,
-                self.to,
+                to,
                 response_code
             );
         }
@@ -63,13 +70,54 @@ impl Emailer for Email {
     }
 }
 
+#[cfg(test)]
+pub(crate) mod testhelper {
+    use std::cell::{Cell, RefCell};
+
+    use anyhow::bail;
+
+    use super::Emailer;
+
+    /// Records every email as (to, title, message), the default recipient
+    /// being "default".  While `failing` is set they fail instead.
+    pub(crate) struct FakeEmail {
+        pub(crate) sent: RefCell<Vec<(String, String, String)>>,
+        pub(crate) failing: Cell<bool>,
+    }
+
+    impl FakeEmail {
+        pub(crate) fn new() -> Self {
+            Self {
+                sent: RefCell::new(Vec::new()),
+                failing: Cell::new(false),
+            }
+        }
+    }
+
+    impl Emailer for FakeEmail {
+        fn send(&self, title: &str, message: &str) -> anyhow::Result<()> {
+            self.send_to("default", title, message)
+        }
+
+        fn send_to(&self, to: &str, title: &str, message: &str) -> anyhow::Result<()> {
+            if self.failing.get() {
+                bail!("Failed to send to {to}");
+            }
+            self.sent
+                .borrow_mut()
+                .push((to.to_owned(), title.to_owned(), message.to_owned()));
+            Ok(())
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::fs;
 
     use super::{Email, Emailer};
 
-    #[ignore]
+    #[ignore = "sends a real email using the mailgun-apikey and to-address files"]
     #[test]
     fn send_an_email() {
         let mailgun_api_key = fs::read_to_string("./mailgun-apikey").unwrap();
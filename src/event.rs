@@ -0,0 +1,119 @@
+use chrono::NaiveDateTime;
+
+use crate::activity::Activity;
+
+/// Something that happened to an activity, kept in the append-only history.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub(crate) struct Event {
+    pub(crate) activity: Activity,
+    pub(crate) kind: EventKind,
+    pub(crate) occurred_at: NaiveDateTime,
+    /// When the occurrence this event relates to was triggered, if any.
+    pub(crate) pending_since: Option<NaiveDateTime>,
+    pub(crate) source: EventSource,
+    /// Who did it, if we know.
+    pub(crate) who: Option<String>,
+    /// Kind specific extra information, e.g. who was notified.
+    pub(crate) detail: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+pub(crate) enum EventKind {
+    Triggered,
+    Acknowledged,
+    /// An accidental acknowledgement was taken back.
+    Undone,
+    Snoozed,
+    Missed,
+    Escalated,
+    Notified,
+    /// An escalation's email couldn't be sent, `detail` is why.
+    NotifyFailed,
+    /// Done before it was due, `pending_since` is the trigger it replaces.
+    CompletedEarly,
+    /// The button's timer was started, `detail` is when it ends.
+    TimerStarted,
+    /// The button's timer was stopped or, once it had ended, dismissed.
+    TimerCleared,
+    /// More of what it uses was bought, `detail` is how much.
+    Restocked,
+    /// Made someone else's turn by hand, `detail` is whose it is now.
+    TurnSwapped,
+    /// Someone taking turns at it went away, `detail` is who.
+    Away,
+    /// Someone taking turns at it came back, `detail` is who.
+    Back,
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+pub(crate) enum EventSource {
+    /// The scheduler, or the control actor acting on a timeout.
+    System,
+    Button,
+    /// A `fourbuttons` command, e.g. restock.
+    Cli,
+}
+
+impl EventKind {
+    const ALL: [EventKind; 15] = [
+        EventKind::Triggered,
+        EventKind::Acknowledged,
+        EventKind::Undone,
+        EventKind::Snoozed,
+        EventKind::Missed,
+        EventKind::Escalated,
+        EventKind::Notified,
+        EventKind::NotifyFailed,
+        EventKind::CompletedEarly,
+        EventKind::TimerStarted,
+        EventKind::TimerCleared,
+        EventKind::Restocked,
+        EventKind::TurnSwapped,
+        EventKind::Away,
+        EventKind::Back,
+    ];
+
+    /// Identifies the kind in the database, so must never change.
+    pub(crate) fn id(self) -> &'static str {
+        match self {
+            EventKind::Triggered => "triggered",
+            EventKind::Acknowledged => "acknowledged",
+            EventKind::Undone => "undone",
+            EventKind::Snoozed => "snoozed",
+            EventKind::Missed => "missed",
+            EventKind::Escalated => "escalated",
+            EventKind::Notified => "notified",
+            EventKind::NotifyFailed => "notify_failed",
+            EventKind::CompletedEarly => "completed_early",
+            EventKind::TimerStarted => "timer_started",
+            EventKind::TimerCleared => "timer_cleared",
+            EventKind::Restocked => "restocked",
+            EventKind::TurnSwapped => "turn_swapped",
+            EventKind::Away => "away",
+            EventKind::Back => "back",
+        }
+    }
+
+    pub(crate) fn from_id(id: &str) -> Option<EventKind> {
+        EventKind::ALL.into_iter().find(|kind| kind.id() == id)
+    }
+}
+
+impl EventSource {
+    const ALL: [EventSource; 3] = [EventSource::System, EventSource::Button, EventSource::Cli];
+
+    /// Identifies the source in the database, so must never change.
+    pub(crate) fn id(self) -> &'static str {
+        match self {
+            EventSource::System => "system",
+            EventSource::Button => "button",
+            EventSource::Cli => "cli",
+        }
+    }
+
+    pub(crate) fn from_id(id: &str) -> Option<EventSource> {
+        EventSource::ALL
+            .into_iter()
+            .find(|source| source.id() == id)
+    }
+}
@@ -0,0 +1,82 @@
+use chrono::{Duration, NaiveDateTime};
+
+use crate::schedule::{Schedule, NEVER};
+
+/// How much is left of something an activity uses up.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) struct Stock {
+    /// Can go below zero if it's been used without being restocked.
+    pub(crate) remaining: i64,
+    /// A refill reminder's been sent since it was last restocked.
+    pub(crate) refill_notified: bool,
+}
+
+/// Whether `remaining` won't cover every trigger of `schedule` from `now`
+/// until `within` from now, using `per_acknowledgement` each time.
+pub(crate) fn runs_out_within(
+    remaining: i64,
+    per_acknowledgement: u32,
+    schedule: &Schedule,
+    now: NaiveDateTime,
+    within: Duration,
+) -> bool {
+    let until = now + within;
+    let mut needed = 0;
+    let mut trigger = schedule.calculate_next_trigger(now);
+    while trigger <= until && trigger != NEVER {
+        needed += i64::from(per_acknowledgement);
+        if needed > remaining {
+            return true;
+        }
+        trigger = schedule.calculate_next_trigger(trigger);
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use chrono::{Duration, NaiveDateTime, NaiveTime, Weekday};
+
+    use crate::schedule::{every_day, DailySchedule, Schedule};
+
+    use super::runs_out_within;
+
+    #[test]
+    fn projects_from_schedule() {
+        let now = NaiveDateTime::from_str("2020-01-01T09:00:00").unwrap();
+        let daily = Schedule::Daily(DailySchedule::new(
+            NaiveTime::from_str("06:00:00").unwrap(),
+            every_day(),
+        ));
+        // Seven more doses in the next week
+        assert!(!runs_out_within(7, 1, &daily, now, Duration::days(7)));
+        assert!(runs_out_within(6, 1, &daily, now, Duration::days(7)));
+        assert!(runs_out_within(13, 2, &daily, now, Duration::days(7)));
+        assert!(runs_out_within(-1, 1, &daily, now, Duration::days(7)));
+
+        let twice_weekly = Schedule::Daily(DailySchedule::new(
+            NaiveTime::from_str("06:00:00").unwrap(),
+            vec![Weekday::Sat, Weekday::Wed],
+        ));
+        assert!(!runs_out_within(
+            20,
+            5,
+            &twice_weekly,
+            now,
+            Duration::days(14)
+        ));
+        assert!(runs_out_within(
+            19,
+            5,
+            &twice_weekly,
+            now,
+            Duration::days(14)
+        ));
+
+        let once = Schedule::Once(NaiveDateTime::from_str("2020-01-02T09:00:00").unwrap());
+        assert!(!runs_out_within(1, 1, &once, now, Duration::days(7)));
+        assert!(runs_out_within(0, 1, &once, now, Duration::days(7)));
+    }
+}
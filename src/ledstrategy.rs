@@ -1,5 +1,6 @@
 use crate::rpi::Led;
 use crate::rpi::RpiOutput;
+use std::collections::HashMap;
 use std::time::Duration;
 use std::time::Instant;
 
@@ -9,10 +10,21 @@ pub(crate) enum LedState {
     On,
     Off,
     BlinkTemporary,
+    BlinkFast,
+    BlinkSlow,
+    /// Blinks this many times, pauses, and repeats.
+    BlinkCount(u8),
+    /// Blinks this many times, slowly enough to count, then goes off.
+    /// Nought is one long blink.
+    Readout(u8),
 }
 
 pub(crate) trait LedStrategy {
     fn tick(&mut self, instant: Instant, rpi: &mut dyn RpiOutput);
+    /// Whether it's a pattern which ends by itself and hasn't yet.
+    fn is_temporary(&self) -> bool {
+        false
+    }
 }
 
 pub(crate) struct LedStrategies {
@@ -20,6 +32,10 @@ pub(crate) struct LedStrategies {
     pub(crate) l2: Box<dyn LedStrategy + Send>,
     pub(crate) l3: Box<dyn LedStrategy + Send>,
     pub(crate) l4: Box<dyn LedStrategy + Send>,
+    // What each LED was last set to show
+    states: HashMap<Led, LedState>,
+    // What each LED goes on to show once the temporary pattern on it ends
+    after_temporary: HashMap<Led, LedState>,
 }
 
 impl LedStrategies {
@@ -33,28 +49,79 @@ impl LedStrategies {
             l2: Box::new(LedStrategyOff {}),
             l3: Box::new(LedStrategyOff {}),
             l4: Box::new(LedStrategyOff {}),
+            states: [Led::L1, Led::L2, Led::L3, Led::L4]
+                .into_iter()
+                .map(|led| (led, LedState::Off))
+                .collect(),
+            after_temporary: HashMap::new(),
         }
     }
 
     pub(crate) fn tick(&mut self, instant: Instant, rpi: &mut dyn RpiOutput) {
-        self.l1.tick(instant, rpi);
-        self.l2.tick(instant, rpi);
-        self.l3.tick(instant, rpi);
-        self.l4.tick(instant, rpi);
+        for led in [Led::L1, Led::L2, Led::L3, Led::L4] {
+            self.strategy(led).tick(instant, rpi);
+            if !self.strategy(led).is_temporary() {
+                if let Some(led_state) = self.after_temporary.remove(&led) {
+                    self.update(rpi, led, led_state, instant);
+                }
+            }
+        }
+    }
+
+    fn strategy(&mut self, led: Led) -> &mut Box<dyn LedStrategy + Send> {
+        match led {
+            Led::L1 => &mut self.l1,
+            Led::L2 => &mut self.l2,
+            Led::L3 => &mut self.l3,
+            Led::L4 => &mut self.l4,
+        }
     }
 
-    pub(crate) fn update(&mut self, rpi: &mut dyn RpiOutput, led: Led, led_state: LedState) {
+    pub(crate) fn update(
+        &mut self,
+        rpi: &mut dyn RpiOutput,
+        led: Led,
+        led_state: LedState,
+        instant: Instant,
+    ) {
+        let temporary = matches!(led_state, LedState::BlinkTemporary | LedState::Readout(_));
+        // A press blink or readout isn't cut short, except by another
+        if !temporary && self.strategy(led).is_temporary() {
+            self.after_temporary.insert(led, led_state);
+            return;
+        }
+        self.after_temporary.remove(&led);
+        // Starting it again would only break up a blink pattern
+        if !temporary && self.states.get(&led) == Some(&led_state) {
+            return;
+        }
+        self.states.insert(led, led_state);
         let new_state: Box<dyn LedStrategy + Send> = match led_state {
             LedState::On => Box::new(LedStrategyOn::new(led, &mut *rpi)),
             LedState::Off => Box::new(LedStrategyOff::new(led, &mut *rpi)),
-            LedState::BlinkTemporary => Box::new(LedStrategyBlinkTemporary::new(led, &mut *rpi)),
+            LedState::BlinkTemporary => {
+                Box::new(LedStrategyBlinkTemporary::new(led, &mut *rpi, instant))
+            }
+            LedState::BlinkFast => Box::new(LedStrategyBlink::new(
+                led,
+                &mut *rpi,
+                instant,
+                Duration::from_millis(250),
+            )),
+            LedState::BlinkSlow => Box::new(LedStrategyBlink::new(
+                led,
+                &mut *rpi,
+                instant,
+                Duration::from_secs(1),
+            )),
+            LedState::BlinkCount(count) => {
+                Box::new(LedStrategyBlinkCount::new(led, &mut *rpi, instant, count))
+            }
+            LedState::Readout(count) => {
+                Box::new(LedStrategyReadout::new(led, &mut *rpi, instant, count))
+            }
         };
-        match led {
-            Led::L1 => self.l1 = new_state,
-            Led::L2 => self.l2 = new_state,
-            Led::L3 => self.l3 = new_state,
-            Led::L4 => self.l4 = new_state,
-        }
+        *self.strategy(led) = new_state;
     }
 }
 
@@ -84,6 +151,40 @@ impl LedStrategy for LedStrategyOff {
     fn tick(&mut self, _instant: Instant, _rpi: &mut dyn RpiOutput) {}
 }
 
+pub(crate) struct LedStrategyBlink {
+    pub(crate) is_on: bool,
+    pub(crate) last_change: Instant,
+    pub(crate) interval: Duration,
+    pub(crate) led: Led,
+}
+
+impl LedStrategyBlink {
+    pub(crate) fn new(
+        led: Led,
+        rpi: &mut dyn RpiOutput,
+        now: Instant,
+        interval: Duration,
+    ) -> LedStrategyBlink {
+        rpi.switch_led(led, true);
+        LedStrategyBlink {
+            is_on: true,
+            last_change: now,
+            interval,
+            led,
+        }
+    }
+}
+
+impl LedStrategy for LedStrategyBlink {
+    fn tick(&mut self, instant: Instant, rpi: &mut dyn RpiOutput) {
+        if instant - self.last_change >= self.interval {
+            self.last_change = instant;
+            self.is_on = !self.is_on;
+            rpi.switch_led(self.led, self.is_on);
+        }
+    }
+}
+
 pub(crate) struct LedStrategyBlinkTemporary {
     pub(crate) is_on: bool,
     pub(crate) stopped: bool,
@@ -93,9 +194,12 @@ pub(crate) struct LedStrategyBlinkTemporary {
 }
 
 impl LedStrategyBlinkTemporary {
-    pub(crate) fn new(led: Led, rpi: &mut dyn RpiOutput) -> LedStrategyBlinkTemporary {
+    pub(crate) fn new(
+        led: Led,
+        rpi: &mut dyn RpiOutput,
+        now: Instant,
+    ) -> LedStrategyBlinkTemporary {
         rpi.switch_led(led, true);
-        let now = Instant::now();
         LedStrategyBlinkTemporary {
             is_on: true,
             stopped: false,
@@ -112,7 +216,7 @@ impl LedStrategy for LedStrategyBlinkTemporary {
             return;
         }
 
-        if instant - self.created_at >= Duration::from_millis(1000) {
+        if instant - self.created_at >= Duration::from_secs(1) {
             self.stopped = true;
             rpi.switch_led(self.led, false);
         } else if instant - self.last_change >= Duration::from_millis(100) {
@@ -121,4 +225,292 @@ impl LedStrategy for LedStrategyBlinkTemporary {
             rpi.switch_led(self.led, self.is_on);
         }
     }
+
+    fn is_temporary(&self) -> bool {
+        !self.stopped
+    }
+}
+
+pub(crate) struct LedStrategyBlinkCount {
+    pub(crate) is_on: bool,
+    pub(crate) created_at: Instant,
+    pub(crate) count: u8,
+    pub(crate) led: Led,
+}
+
+impl LedStrategyBlinkCount {
+    const BLINK: Duration = Duration::from_millis(250);
+    const PAUSE: Duration = Duration::from_millis(1500);
+
+    pub(crate) fn new(
+        led: Led,
+        rpi: &mut dyn RpiOutput,
+        now: Instant,
+        count: u8,
+    ) -> LedStrategyBlinkCount {
+        rpi.switch_led(led, true);
+        LedStrategyBlinkCount {
+            is_on: true,
+            created_at: now,
+            count,
+            led,
+        }
+    }
+}
+
+impl LedStrategy for LedStrategyBlinkCount {
+    fn tick(&mut self, instant: Instant, rpi: &mut dyn RpiOutput) {
+        // `count` blinks of on then off, followed by a pause
+        let blinks = Self::BLINK * 2 * u32::from(self.count);
+        let cycle = blinks + Self::PAUSE;
+        let elapsed = (instant - self.created_at).as_millis() % cycle.as_millis();
+        let is_on = elapsed < blinks.as_millis()
+            && elapsed % (Self::BLINK * 2).as_millis() < Self::BLINK.as_millis();
+        if is_on != self.is_on {
+            self.is_on = is_on;
+            rpi.switch_led(self.led, is_on);
+        }
+    }
+}
+
+pub(crate) struct LedStrategyReadout {
+    pub(crate) is_on: bool,
+    pub(crate) ended: bool,
+    pub(crate) created_at: Instant,
+    pub(crate) count: u8,
+    pub(crate) led: Led,
+}
+
+impl LedStrategyReadout {
+    const BLINK: Duration = Duration::from_millis(400);
+    const LONG_BLINK: Duration = Duration::from_millis(1500);
+
+    pub(crate) fn new(
+        led: Led,
+        rpi: &mut dyn RpiOutput,
+        now: Instant,
+        count: u8,
+    ) -> LedStrategyReadout {
+        rpi.switch_led(led, true);
+        LedStrategyReadout {
+            is_on: true,
+            ended: false,
+            created_at: now,
+            count,
+            led,
+        }
+    }
+}
+
+impl LedStrategy for LedStrategyReadout {
+    fn tick(&mut self, instant: Instant, rpi: &mut dyn RpiOutput) {
+        let elapsed = (instant - self.created_at).as_millis();
+        let length = if self.count == 0 {
+            Self::LONG_BLINK
+        } else {
+            Self::BLINK * 2 * u32::from(self.count)
+        };
+        let is_on = elapsed < length.as_millis()
+            && (self.count == 0
+                || elapsed % (Self::BLINK * 2).as_millis() < Self::BLINK.as_millis());
+        if is_on != self.is_on {
+            self.is_on = is_on;
+            rpi.switch_led(self.led, is_on);
+        }
+        self.ended = elapsed >= length.as_millis();
+    }
+
+    fn is_temporary(&self) -> bool {
+        !self.ended
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod testhelper {
+    use crate::rpi::{Led, RpiOutput};
+
+    /// Records every LED switch so tests can check the exact output.
+    pub(crate) struct RecordingRpiOutput {
+        pub(crate) switches: Vec<(Led, bool)>,
+    }
+
+    impl RecordingRpiOutput {
+        pub(crate) fn new() -> Self {
+            Self {
+                switches: Vec::new(),
+            }
+        }
+    }
+
+    impl RpiOutput for RecordingRpiOutput {
+        fn switch_led(&mut self, led: Led, is_on: bool) {
+            self.switches.push((led, is_on));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, NaiveDateTime};
+
+    use crate::{
+        clock::{testhelper::ManualClock, Clock},
+        rpi::Led,
+    };
+
+    use super::{testhelper::RecordingRpiOutput, LedState, LedStrategies};
+
+    #[test]
+    fn blink_temporary() {
+        let clock = ManualClock::new(NaiveDateTime::default());
+        let mut rpi = RecordingRpiOutput::new();
+        let mut strategies = LedStrategies::all_off(&mut rpi);
+        rpi.switches.clear();
+
+        strategies.update(&mut rpi, Led::L2, LedState::BlinkTemporary, clock.instant());
+        assert_eq!(rpi.switches, vec![(Led::L2, true)]);
+
+        // Toggles every 100ms
+        for _ in 0..9 {
+            clock.advance(Duration::milliseconds(100));
+            strategies.tick(clock.instant(), &mut rpi);
+        }
+        assert_eq!(
+            rpi.switches,
+            vec![
+                (Led::L2, true),
+                (Led::L2, false),
+                (Led::L2, true),
+                (Led::L2, false),
+                (Led::L2, true),
+                (Led::L2, false),
+                (Led::L2, true),
+                (Led::L2, false),
+                (Led::L2, true),
+                (Led::L2, false),
+            ]
+        );
+
+        // Then switches off after a second and stays off
+        rpi.switches.clear();
+        clock.advance(Duration::milliseconds(100));
+        strategies.tick(clock.instant(), &mut rpi);
+        clock.advance(Duration::seconds(5));
+        strategies.tick(clock.instant(), &mut rpi);
+        assert_eq!(rpi.switches, vec![(Led::L2, false)]);
+    }
+
+    #[test]
+    fn blink_temporary_then() {
+        let clock = ManualClock::new(NaiveDateTime::default());
+        let mut rpi = RecordingRpiOutput::new();
+        let mut strategies = LedStrategies::all_off(&mut rpi);
+        rpi.switches.clear();
+
+        // What it shows next waits for the press blink to finish
+        strategies.update(&mut rpi, Led::L1, LedState::BlinkTemporary, clock.instant());
+        strategies.update(&mut rpi, Led::L1, LedState::On, clock.instant());
+        for _ in 0..9 {
+            clock.advance(Duration::milliseconds(100));
+            strategies.tick(clock.instant(), &mut rpi);
+        }
+        assert_eq!(rpi.switches.last(), Some(&(Led::L1, false)));
+        rpi.switches.clear();
+        clock.advance(Duration::milliseconds(100));
+        strategies.tick(clock.instant(), &mut rpi);
+        assert_eq!(rpi.switches, vec![(Led::L1, false), (Led::L1, true)]);
+
+        // But another press blink starts straight away
+        rpi.switches.clear();
+        strategies.update(&mut rpi, Led::L1, LedState::BlinkTemporary, clock.instant());
+        strategies.update(&mut rpi, Led::L1, LedState::BlinkTemporary, clock.instant());
+        assert_eq!(rpi.switches, vec![(Led::L1, true), (Led::L1, true)]);
+    }
+
+    #[test]
+    fn blink_fast() {
+        let clock = ManualClock::new(NaiveDateTime::default());
+        let mut rpi = RecordingRpiOutput::new();
+        let mut strategies = LedStrategies::all_off(&mut rpi);
+        rpi.switches.clear();
+
+        strategies.update(&mut rpi, Led::L3, LedState::BlinkFast, clock.instant());
+
+        // Keeps on toggling every 250ms
+        for _ in 0..20 {
+            clock.advance(Duration::milliseconds(125));
+            strategies.tick(clock.instant(), &mut rpi);
+        }
+        let expected: Vec<(Led, bool)> = (0..11).map(|idx| (Led::L3, idx % 2 == 0)).collect();
+        assert_eq!(rpi.switches, expected);
+    }
+
+    #[test]
+    fn blink_count() {
+        let clock = ManualClock::new(NaiveDateTime::default());
+        let mut rpi = RecordingRpiOutput::new();
+        let mut strategies = LedStrategies::all_off(&mut rpi);
+        rpi.switches.clear();
+
+        strategies.update(&mut rpi, Led::L1, LedState::BlinkCount(2), clock.instant());
+
+        // Two blinks, a 1.5s pause, then the first blink again
+        for _ in 0..11 {
+            clock.advance(Duration::milliseconds(250));
+            strategies.tick(clock.instant(), &mut rpi);
+        }
+        assert_eq!(
+            rpi.switches,
+            vec![
+                (Led::L1, true),
+                (Led::L1, false),
+                (Led::L1, true),
+                (Led::L1, false),
+                (Led::L1, true),
+                (Led::L1, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn same_state_again() {
+        let clock = ManualClock::new(NaiveDateTime::default());
+        let mut rpi = RecordingRpiOutput::new();
+        let mut strategies = LedStrategies::all_off(&mut rpi);
+        rpi.switches.clear();
+
+        // Carries on where it was rather than starting over
+        strategies.update(&mut rpi, Led::L1, LedState::BlinkCount(2), clock.instant());
+        clock.advance(Duration::milliseconds(250));
+        strategies.tick(clock.instant(), &mut rpi);
+        strategies.update(&mut rpi, Led::L1, LedState::BlinkCount(2), clock.instant());
+        strategies.update(&mut rpi, Led::L2, LedState::Off, clock.instant());
+        assert_eq!(rpi.switches, vec![(Led::L1, true), (Led::L1, false)]);
+    }
+
+    #[test]
+    fn readout() {
+        let clock = ManualClock::new(NaiveDateTime::default());
+        let mut rpi = RecordingRpiOutput::new();
+        let mut strategies = LedStrategies::all_off(&mut rpi);
+        rpi.switches.clear();
+
+        // Three blinks, then off for good
+        strategies.update(&mut rpi, Led::L2, LedState::Readout(3), clock.instant());
+        for _ in 0..25 {
+            clock.advance(Duration::milliseconds(200));
+            strategies.tick(clock.instant(), &mut rpi);
+        }
+        let expected: Vec<(Led, bool)> = (0..6).map(|idx| (Led::L2, idx % 2 == 0)).collect();
+        assert_eq!(rpi.switches, expected);
+
+        // Nought is one long blink
+        rpi.switches.clear();
+        strategies.update(&mut rpi, Led::L2, LedState::Readout(0), clock.instant());
+        for _ in 0..25 {
+            clock.advance(Duration::milliseconds(200));
+            strategies.tick(clock.instant(), &mut rpi);
+        }
+        assert_eq!(rpi.switches, vec![(Led::L2, true), (Led::L2, false)]);
+    }
 }
@@ -6,49 +6,94 @@ mod activity;
 mod actor;
 mod appdb;
 mod application_state;
+mod cli;
+mod clock;
+mod command;
+mod condition;
+mod config;
 mod db;
 mod email;
+mod event;
+mod inventory;
 mod ledstrategy;
+mod rotation;
 mod rpi;
 mod schedule;
 mod scheduler;
+mod stats;
+mod suggest;
 mod supervisor;
 
 use anyhow::{Context, Result};
 use appdb::AppDb;
-use chrono::{Duration, Local, NaiveDate, NaiveTime, Weekday};
 use log::info;
 use rpi::initialise_rpi;
 use scheduler::Scheduler;
-use std::{fs, str::FromStr, time::Instant};
+use std::{
+    collections::HashMap,
+    fs,
+    sync::{mpsc, Arc},
+    time::Instant,
+};
 use supervisor::supervisor::Supervisor;
 
 use crate::{
     activity::Activity,
     actor::{
-        control_actor::ControlActor,
+        clock_watch_actor::ClockWatchActor,
+        condition_actor::ConditionActor,
+        control_actor::{ControlActor, ControlActorMessage, ControlConfig},
+        digest_actor::{DigestActor, DigestConfig},
+        hook_actor::HookActor,
         led_actor::{LedActor, LedActorMessage},
         rpi_input_actor::RpiInputActor,
         scheduler_actor::{SchedulerActor, SchedulerActorMessage},
         tick_actor::TickActor,
     },
     application_state::ApplicationState,
+    clock::{Clock, SharedClock, SystemClock},
     email::Email,
-    schedule::{every_day, DailySchedule, Schedule, WeeklySchedule},
-    scheduler::ScheduledJobSpec,
 };
 
+// Everything lives next to the binary
+const DB_PATH: &str = "./db";
+
 fn main() {
     env_logger::init();
     info!("Initialising");
-    let (db, email, application_state, rpi, scheduler) =
-        initialise().expect("Initialisation error");
+    let clock: SharedClock = Arc::new(SystemClock {});
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        let db = AppDb::new(DB_PATH.to_string());
+        db.run_migrations().expect("Failed to run migrations");
+        let (people, _) = read_people();
+        let rotations = config::rotations(&people);
+        let job_specs = config::job_specs().expect("Invalid job specs");
+        if let Err(err) = cli::run(&args, &db, &*clock, &people, &rotations, &job_specs) {
+            eprintln!("{err:#}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let initialised = initialise(&*clock).expect("Initialisation error");
     info!("Running actors");
-    run_actors(rpi, application_state, db, email, scheduler).expect("Abnormal shutdown");
+    run_actors(initialised, clock).expect("Abnormal shutdown");
+}
+
+struct Initialised {
+    db: AppDb,
+    email: Email,
+    application_state: ApplicationState,
+    rpi: rpi::Rpi,
+    scheduler: Scheduler,
+    control_config: ControlConfig,
+    digest_config: DigestConfig,
 }
 
-fn initialise() -> Result<(AppDb, Email, ApplicationState, rpi::Rpi, Scheduler)> {
-    let db = AppDb::new("./db".to_string());
+fn initialise(clock: &dyn Clock) -> Result<Initialised> {
+    let db = AppDb::new(DB_PATH.to_string());
     let mailgun_api_key =
         fs::read_to_string("./mailgun-apikey").context("Missing mailgun-apikey")?;
     let to_address = fs::read_to_string("./to-address").context("Missing to-address")?;
@@ -61,74 +106,110 @@ fn initialise() -> Result<(AppDb, Email, ApplicationState, rpi::Rpi, Scheduler)>
 
     let application_state = db
         .load_application_state()
-        .context("Failed to load application state")?
-        .unwrap_or(ApplicationState::blank());
+        .context("Failed to load application state")?;
     info!("Loaded state {:?}", application_state);
 
     let rpi = initialise_rpi().context("Failed to initialise rpi")?;
 
-    let now = Local::now().naive_local();
-    let scheduler = Scheduler::new(
-        now,
-        &[
-            ScheduledJobSpec::new(
-                Schedule::Daily(DailySchedule::new(
-                    NaiveTime::from_hms_milli_opt(6, 0, 0, 0).context("Invalid schedule")?,
-                    every_day(),
-                )),
-                Activity::TakePills,
-                Duration::hours(1),
-            ),
-            ScheduledJobSpec::new(
-                Schedule::Daily(DailySchedule::new(
-                    NaiveTime::from_hms_milli_opt(11, 0, 0, 0).expect("Invalid schedule"),
-                    every_day(),
-                )),
-                Activity::TakePillsReminder,
-                Duration::hours(1),
-            ),
-            ScheduledJobSpec::new(
-                Schedule::Daily(DailySchedule::new(
-                    NaiveTime::from_hms_milli_opt(6, 0, 0, 0).expect("Invalid schedule"),
-                    vec![Weekday::Sat, Weekday::Wed],
-                )),
-                Activity::WaterPlants,
-                Duration::hours(1),
-            ),
-            ScheduledJobSpec::new(
-                Schedule::Weekly(WeeklySchedule::new(
-                    NaiveDate::from_str("2024-03-13").expect("Invalid schedule start"),
-                    NaiveTime::from_hms_milli_opt(6, 0, 0, 0).expect("Invalid schedule"),
-                    2,
-                )),
-                Activity::I,
-                Duration::hours(12),
-            ),
-            ScheduledJobSpec::new(
-                Schedule::Daily(DailySchedule::new(
-                    NaiveTime::from_hms_milli_opt(6, 0, 0, 0).expect("Invalid schedule"),
-                    vec![Weekday::Sat, Weekday::Wed],
-                )),
-                Activity::CleanLitterTray,
-                Duration::hours(1),
-            ),
-        ],
-    );
+    // Any changes made by `fourbuttons suggest --apply`
+    let adjustments = db
+        .load_schedule_adjustments()
+        .context("Failed to load schedule adjustments")?;
+    let job_specs: Vec<_> = config::job_specs()?
+        .into_iter()
+        .map(|spec| {
+            match spec
+                .task()
+                .activity()
+                .and_then(|activity| adjustments.get(&activity))
+            {
+                Some(adjustment) => spec.adjusted(adjustment),
+                None => spec,
+            }
+        })
+        .collect();
+    let mut scheduler = Scheduler::new(clock.now(), &job_specs);
+    scheduler.add_job(clock.now(), &config::digest_job_spec()?);
+
+    // Optional, someone else to pester if things are left for too long
+    let secondary_address = fs::read_to_string("./secondary-address")
+        .ok()
+        .map(|address| address.trim().to_owned());
+    let (people, addresses) = read_people();
 
-    Ok((db, email, application_state, rpi, scheduler))
+    // Optional, one address per line, otherwise the digest goes to
+    // to-address
+    let digest_addresses = fs::read_to_string("./digest-addresses")
+        .map(|addresses| {
+            addresses
+                .lines()
+                .map(str::trim)
+                // Nowhere to send it
+                .filter(|address| !address.is_empty())
+                .map(str::to_owned)
+                .collect()
+        })
+        .unwrap_or_default();
+    let digest_config = config::digest_config(digest_addresses, &people);
+    let control_config = config::control_config(secondary_address, people, addresses, &job_specs);
+
+    Ok(Initialised {
+        db,
+        email,
+        application_state,
+        rpi,
+        scheduler,
+        control_config,
+        digest_config,
+    })
 }
 
-fn run_actors(
-    rpi: rpi::Rpi,
-    application_state: ApplicationState,
-    db: AppDb,
-    email: Email,
-    scheduler: Scheduler,
-) -> Result<()> {
+/// Optional, the household, one per line as `Name` or `Name <address>`.  Who
+/// a single, double, triple etc. press means did something, who takes turns
+/// at the shared chores, and where to email them when it's their turn.
+fn read_people() -> (Vec<String>, HashMap<String, String>) {
+    parse_people(&fs::read_to_string("./people").unwrap_or_default())
+}
+
+fn parse_people(contents: &str) -> (Vec<String>, HashMap<String, String>) {
+    let mut people = Vec::new();
+    let mut addresses = HashMap::new();
+    for line in contents.lines() {
+        let line = line.trim();
+        let (name, address) = match line.strip_suffix('>').and_then(|line| line.split_once('<')) {
+            Some((name, address)) => (name.trim(), Some(address.trim())),
+            None => (line, None),
+        };
+        // A blank line isn't somebody, and would throw out who each press
+        // means
+        if name.is_empty() {
+            continue;
+        }
+        people.push(name.to_owned());
+        if let Some(address) = address {
+            addresses.insert(name.to_owned(), address.to_owned());
+        }
+    }
+    (people, addresses)
+}
+
+fn run_actors(initialised: Initialised, clock: SharedClock) -> Result<()> {
+    let Initialised {
+        db,
+        email,
+        application_state,
+        rpi,
+        scheduler,
+        control_config,
+        digest_config,
+    } = initialised;
     let mut supervisor = Supervisor::new();
 
     let tx_led = supervisor
-        .start(LedActor::new(rpi.output), "LEDActor".to_owned())
+        .start(
+            LedActor::new(rpi.output, clock.clone()),
+            "LEDActor".to_owned(),
+        )
         .context("Failed to start LED Actor")?;
 
     supervisor
@@ -142,13 +223,40 @@ fn run_actors(
         )
         .context("Failed to start LED Tick Actor")?;
 
+    let tx_hook = supervisor
+        .start(HookActor::new(), "HookActor".to_owned())
+        .context("Failed to start Hook Actor")?;
+
+    // The control actor tells the scheduler about things done early, but
+    // the scheduler needs the control actor to already be running
+    let (tx_scheduler, rx_scheduler) = mpsc::channel();
     let tx_control = supervisor
         .start(
-            ControlActor::new(tx_led, application_state, db, email),
+            ControlActor::new(
+                tx_led,
+                tx_scheduler.clone(),
+                application_state,
+                db,
+                email.clone(),
+                clock.clone(),
+                control_config,
+            )
+            .with_hooks(tx_hook),
             "ControlActor".to_owned(),
         )
         .context("Failed to start Control Actor")?;
 
+    supervisor
+        .start_message_source(
+            TickActor::new(
+                std::time::Duration::from_mins(1),
+                tx_control.clone(),
+                |_| ControlActorMessage::ReconcileLeds,
+            ),
+            "LED Reconcile Tick Actor".to_owned(),
+        )
+        .context("Failed to start LED Reconcile Tick Actor")?;
+
     supervisor
         .start_message_source(
             RpiInputActor::new(rpi.input, tx_control.clone()),
@@ -156,22 +264,55 @@ fn run_actors(
         )
         .context("Failed to start RPI Input Actor")?;
 
-    let tx_scheduler = supervisor
+    let tx_condition = supervisor
+        .start(
+            ConditionActor::new(tx_control.clone()),
+            "ConditionActor".to_owned(),
+        )
+        .context("Failed to start Condition Actor")?;
+
+    let tx_digest = supervisor
         .start(
-            SchedulerActor::new(scheduler, tx_control),
+            DigestActor::new(digest_config, AppDb::new(DB_PATH.to_string()), email),
+            "DigestActor".to_owned(),
+        )
+        .context("Failed to start Digest Actor")?;
+
+    supervisor
+        .start_with_receiver(
+            SchedulerActor::new(scheduler, tx_control, tx_condition, clock).with_digest(tx_digest),
             "SchedulerActor".to_owned(),
+            rx_scheduler,
         )
         .context("Failed to start Scheduler Actor")?;
+
     supervisor
         .start_message_source(
-            TickActor::new(std::time::Duration::from_millis(1000), tx_scheduler, |_| {
-                SchedulerActorMessage::Tick
-            }),
-            "Scheduler Tick Actor".to_owned(),
+            ClockWatchActor::new(std::time::Duration::from_secs(30))
+                .notify(tx_scheduler, || SchedulerActorMessage::ClockChanged),
+            "Clock Watch Actor".to_owned(),
         )
-        .context("Failed to start Scheduler Tick Actor")?;
+        .context("Failed to start Clock Watch Actor")?;
 
     supervisor.supervise();
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::parse_people;
+
+    #[test]
+    fn parse_people_skips_blank_lines() {
+        let (people, addresses) =
+            parse_people("Alex\n\n  \nSam <sam@example.com>\n <nobody@example.com>\n\n");
+        assert_eq!(people, vec!["Alex".to_owned(), "Sam".to_owned()]);
+        assert_eq!(
+            addresses,
+            HashMap::from([("Sam".to_owned(), "sam@example.com".to_owned())])
+        );
+    }
+}
@@ -0,0 +1,98 @@
+use std::collections::HashSet;
+
+use anyhow::Result;
+
+use crate::{activity::Activity, appdb::AppDb};
+
+/// Whose turn it is to do a shared chore.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Rotation {
+    pub(crate) people: Vec<String>,
+    pub(crate) advance: RotationAdvance,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum RotationAdvance {
+    /// The next person is on duty once it's been done.
+    OnCompletion,
+    /// Each trigger is the next person's.
+    OnTrigger,
+}
+
+impl Rotation {
+    /// Who's on duty, given who was last stored as on duty.  People who are
+    /// away are skipped over, `None` if everyone is away.
+    pub(crate) fn on_duty(&self, stored: Option<&str>, away: &HashSet<String>) -> Option<String> {
+        let start = stored
+            .and_then(|stored| self.people.iter().position(|person| person == stored))
+            .unwrap_or_default();
+        self.first_available(start, away)
+    }
+
+    /// Who's on duty after `current`, skipping people who are away.
+    pub(crate) fn next(&self, current: Option<&str>, away: &HashSet<String>) -> Option<String> {
+        let start = current
+            .and_then(|current| self.people.iter().position(|person| person == current))
+            .map_or(0, |idx| idx + 1);
+        self.first_available(start, away)
+    }
+
+    fn first_available(&self, start: usize, away: &HashSet<String>) -> Option<String> {
+        (0..self.people.len())
+            .map(|offset| &self.people[(start + offset) % self.people.len()])
+            .find(|person| !away.contains(*person))
+            .cloned()
+    }
+}
+
+/// Who's on duty for `activity` according to the database.
+pub(crate) fn on_duty(
+    db: &AppDb,
+    activity: Activity,
+    rotation: &Rotation,
+) -> Result<Option<String>> {
+    let stored = db.load_on_duty(activity)?;
+    Ok(rotation.on_duty(stored.as_deref(), &db.load_away()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::{Rotation, RotationAdvance};
+
+    fn rotation() -> Rotation {
+        Rotation {
+            people: vec!["Alex".to_owned(), "Sam".to_owned(), "Jo".to_owned()],
+            advance: RotationAdvance::OnCompletion,
+        }
+    }
+
+    #[test]
+    fn takes_turns() {
+        let rotation = rotation();
+        let away = HashSet::new();
+
+        assert_eq!(rotation.on_duty(None, &away), Some("Alex".to_owned()));
+        assert_eq!(rotation.on_duty(Some("Sam"), &away), Some("Sam".to_owned()));
+        assert_eq!(rotation.next(Some("Sam"), &away), Some("Jo".to_owned()));
+        assert_eq!(rotation.next(Some("Jo"), &away), Some("Alex".to_owned()));
+        // Someone who's since left the rotation
+        assert_eq!(
+            rotation.on_duty(Some("Robin"), &away),
+            Some("Alex".to_owned())
+        );
+    }
+
+    #[test]
+    fn skips_people_who_are_away() {
+        let rotation = rotation();
+        let away = HashSet::from(["Sam".to_owned()]);
+
+        assert_eq!(rotation.on_duty(Some("Sam"), &away), Some("Jo".to_owned()));
+        assert_eq!(rotation.next(Some("Alex"), &away), Some("Jo".to_owned()));
+
+        let away = HashSet::from(["Alex".to_owned(), "Sam".to_owned(), "Jo".to_owned()]);
+        assert_eq!(rotation.on_duty(None, &away), None);
+    }
+}
@@ -4,9 +4,12 @@ use rppal::gpio::{Gpio, InputPin, OutputPin};
 use std::{
     env,
     io::{self, Read, Stdin},
+    thread,
     time::{Duration, Instant},
 };
 
+use crate::clock::{Clock, SystemClock};
+
 const PIN_BUTTON_1: u8 = 2;
 const PIN_BUTTON_2: u8 = 3;
 const PIN_BUTTON_3: u8 = 20;
@@ -16,10 +19,25 @@ const PIN_LED_2: u8 = 24;
 const PIN_LED_3: u8 = 22;
 const PIN_LED_4: u8 = 27;
 
-// This does look ridiculously high, but I've seen bounces into the hundreds
-// of ms on these switches quite regularly, and I don't need to worry about
-// quick succession button presses for this machine.
-const DEBOUNCE_DELAY: Duration = Duration::from_millis(500);
+// I've seen bounces into the hundreds of ms on these switches quite
+// regularly.  Those while it's held are swallowed by waiting for the
+// release, and later ones by `BOUNCE_WINDOW`, so this only has to cover the
+// worst of the release bouncing, and is short enough to let a double press
+// through.
+const DEBOUNCE_DELAY: Duration = Duration::from_millis(100);
+// Holding a button down for at least this long counts as a long press.
+const LONG_PRESS: Duration = Duration::from_millis(1500);
+// And for at least this long counts as holding it.
+const HOLD: Duration = Duration::from_secs(4);
+// How often to check whether a held button has been released yet.
+const RELEASE_POLL_INTERVAL: Duration = Duration::from_millis(20);
+// It's only released once it's read high this many times in a row.
+const RELEASE_READS: u32 = 3;
+// The release can go on bouncing for a few hundred ms, so a press starting
+// this soon after the last release only counts if it reads low this many
+// times in a row.  Bounces don't stay low that long, a real press does.
+const BOUNCE_WINDOW: Duration = Duration::from_millis(500);
+const MIN_PRESS_READS: u32 = 3;
 
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub(crate) enum Button {
@@ -31,7 +49,15 @@ pub(crate) enum Button {
     Stop,
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Copy, Clone)]
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub(crate) enum PressKind {
+    Short,
+    Long,
+    /// An even longer press, otherwise the same as a long one.
+    Hold,
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Hash, Copy, Clone)]
 pub(crate) enum Led {
     L1,
     L2,
@@ -40,7 +66,9 @@ pub(crate) enum Led {
 }
 
 pub(crate) trait RpiInput {
-    fn wait_for_button_press(&mut self) -> Result<Button>;
+    /// Blocks until a button has been pressed and released, and says when
+    /// it was pressed.
+    fn wait_for_button_press(&mut self) -> Result<(Button, PressKind, Instant)>;
 }
 
 pub(crate) trait RpiOutput {
@@ -82,10 +110,10 @@ pub(crate) fn initialise_rpi() -> Result<Rpi> {
                 pin2: btnpin2,
                 pin3: btnpin3,
                 pin4: btnpin4,
-                last_trigger_1: Instant::now(),
-                last_trigger_2: Instant::now(),
-                last_trigger_3: Instant::now(),
-                last_trigger_4: Instant::now(),
+                last_release_1: Instant::now(),
+                last_release_2: Instant::now(),
+                last_release_3: Instant::now(),
+                last_release_4: Instant::now(),
             }),
             output: Box::new(RealRpiOutput {
                 ledpin1,
@@ -109,26 +137,84 @@ struct RealRpiInput {
     pin2: InputPin,
     pin3: InputPin,
     pin4: InputPin,
-    last_trigger_1: Instant,
-    last_trigger_2: Instant,
-    last_trigger_3: Instant,
-    last_trigger_4: Instant,
-}
-
-fn debounce(last_trigger: &mut Instant) -> bool {
-    let now = Instant::now();
-    let gap = now - *last_trigger;
-    debug!("Debouncer at {:?} (gap {:?})", now, gap);
-    if gap >= DEBOUNCE_DELAY {
-        *last_trigger = now;
-        true
+    last_release_1: Instant,
+    last_release_2: Instant,
+    last_release_3: Instant,
+    last_release_4: Instant,
+}
+
+// Whether a button's pin reads high, i.e. it isn't pressed.
+trait PinLevel {
+    fn is_high(&self) -> bool;
+}
+
+impl PinLevel for InputPin {
+    fn is_high(&self) -> bool {
+        InputPin::is_high(self)
+    }
+}
+
+fn debounce(pressed_at: Instant, last_release: Instant) -> bool {
+    let gap = pressed_at.saturating_duration_since(last_release);
+    debug!("Debouncer at {:?} (gap {:?})", pressed_at, gap);
+    gap >= DEBOUNCE_DELAY
+}
+
+// Only falling edges (presses) are interrupts, because the releases bounce
+// just as badly.  Instead, once pressed poll the pin until it has read high
+// a few times in a row.  Debouncing starts from the release so that any
+// bounces which were cached while the button was held get ignored.  `None`
+// if it never read low, or soon after the last release not for long enough,
+// as that was a bounce rather than a press.  Waits with `sleep`, which has
+// to move `clock` on, so tests needn't sleep.
+fn wait_for_release(
+    pin: &impl PinLevel,
+    clock: &dyn Clock,
+    sleep: impl Fn(Duration),
+    pressed_at: Instant,
+    last_release: &mut Instant,
+) -> Option<PressKind> {
+    let min_low_reads = if pressed_at - *last_release < BOUNCE_WINDOW {
+        MIN_PRESS_READS
     } else {
-        false
+        1
+    };
+    let mut longest_low = 0;
+    let mut low_reads = 0;
+    let mut high_reads = 0;
+    while high_reads < RELEASE_READS {
+        sleep(RELEASE_POLL_INTERVAL);
+        if pin.is_high() {
+            high_reads += 1;
+            low_reads = 0;
+        } else {
+            low_reads += 1;
+            longest_low = longest_low.max(low_reads);
+            high_reads = 0;
+        }
     }
+
+    *last_release = clock.instant();
+    if longest_low < min_low_reads {
+        debug!(
+            "Ignoring bounce, the button only read as pressed {} times in a row",
+            longest_low
+        );
+        return None;
+    }
+    let held_for = *last_release - pressed_at;
+    debug!("Button held for {:?}", held_for);
+    Some(if held_for >= HOLD {
+        PressKind::Hold
+    } else if held_for >= LONG_PRESS {
+        PressKind::Long
+    } else {
+        PressKind::Short
+    })
 }
 
 impl RpiInput for RealRpiInput {
-    fn wait_for_button_press(&mut self) -> Result<Button> {
+    fn wait_for_button_press(&mut self) -> Result<(Button, PressKind, Instant)> {
         loop {
             match self
                 .gpio
@@ -141,17 +227,27 @@ impl RpiInput for RealRpiInput {
                 .context("Failed to poll rpi gpio interrupts")?
             {
                 Some((pin, _)) => {
+                    // Blocked waiting for it, so this is when it was pressed
+                    let pressed_at = Instant::now();
                     debug!("RPi input {:?}", pin);
-                    let (button, survives_debounce) = match pin.pin() {
-                        PIN_BUTTON_1 => (Button::B1, debounce(&mut self.last_trigger_1)),
-                        PIN_BUTTON_2 => (Button::B2, debounce(&mut self.last_trigger_2)),
-                        PIN_BUTTON_3 => (Button::B3, debounce(&mut self.last_trigger_3)),
-                        PIN_BUTTON_4 => (Button::B4, debounce(&mut self.last_trigger_4)),
+                    let (button, pin, last_release) = match pin.pin() {
+                        PIN_BUTTON_1 => (Button::B1, &self.pin1, &mut self.last_release_1),
+                        PIN_BUTTON_2 => (Button::B2, &self.pin2, &mut self.last_release_2),
+                        PIN_BUTTON_3 => (Button::B3, &self.pin3, &mut self.last_release_3),
+                        PIN_BUTTON_4 => (Button::B4, &self.pin4, &mut self.last_release_4),
                         unknown => panic!("Unexpected PIN value: {unknown}"),
                     };
 
-                    if survives_debounce {
-                        return Ok(button);
+                    if debounce(pressed_at, *last_release) {
+                        if let Some(press_kind) = wait_for_release(
+                            pin,
+                            &SystemClock {},
+                            thread::sleep,
+                            pressed_at,
+                            last_release,
+                        ) {
+                            return Ok((button, press_kind, pressed_at));
+                        }
                     }
                 }
                 None => {
@@ -202,7 +298,7 @@ struct FakeRpiInput {
 }
 
 impl RpiInput for FakeRpiInput {
-    fn wait_for_button_press(&mut self) -> Result<Button> {
+    fn wait_for_button_press(&mut self) -> Result<(Button, PressKind, Instant)> {
         let mut next: [u8; 1] = [0; 1];
 
         loop {
@@ -215,19 +311,139 @@ impl RpiInput for FakeRpiInput {
             assert!(bytes_read != 0, "Blocking read should never return 0?");
 
             debug!("Read byte from stdin: {}", next[0]);
-            return match next[0] {
-                49 => Ok(Button::B1),
-                50 => Ok(Button::B2),
-                51 => Ok(Button::B3),
-                52 => Ok(Button::B4),
+            let (button, press_kind) = match next[0] {
+                49 => (Button::B1, PressKind::Short),
+                50 => (Button::B2, PressKind::Short),
+                51 => (Button::B3, PressKind::Short),
+                52 => (Button::B4, PressKind::Short),
+                // a, b, c & d are long presses of 1, 2, 3 & 4
+                97 => (Button::B1, PressKind::Long),
+                98 => (Button::B2, PressKind::Long),
+                99 => (Button::B3, PressKind::Long),
+                100 => (Button::B4, PressKind::Long),
+                // e, f, g & h are holds of 1, 2, 3 & 4
+                101 => (Button::B1, PressKind::Hold),
+                102 => (Button::B2, PressKind::Hold),
+                103 => (Button::B3, PressKind::Hold),
+                104 => (Button::B4, PressKind::Hold),
                 // Ignore enter key
                 10 => continue,
-                113 => Ok(Button::Stop),
+                113 => (Button::Stop, PressKind::Short),
                 unknown => {
                     info!("Unknown input {}", unknown);
                     continue;
                 }
             };
+            return Ok((button, press_kind, Instant::now()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use chrono::NaiveDateTime;
+
+    use crate::{
+        actor::control_actor::MULTI_PRESS_GAP,
+        clock::{testhelper::ManualClock, Clock},
+    };
+
+    use super::{debounce, wait_for_release, PinLevel, PressKind};
+
+    // Low, i.e. pressed, during each of `low` since `start`.
+    struct FakePin {
+        clock: ManualClock,
+        start: Instant,
+        low: Vec<(Duration, Duration)>,
+    }
+
+    impl PinLevel for FakePin {
+        fn is_high(&self) -> bool {
+            let elapsed = self.clock.instant() - self.start;
+            !self
+                .low
+                .iter()
+                .any(|(from, to)| (*from..*to).contains(&elapsed))
+        }
+    }
+
+    #[test]
+    fn double_press() {
+        let ms = Duration::from_millis;
+        let clock = ManualClock::new(NaiveDateTime::default());
+        let sleep = |duration| clock.advance(chrono::Duration::from_std(duration).unwrap());
+        let start = clock.instant();
+        // Two quick presses, each bouncing as it's released, and then a
+        // bounce too short to read as pressed
+        let pin = FakePin {
+            clock: clock.clone(),
+            start,
+            low: vec![
+                (ms(0), ms(90)),
+                (ms(95), ms(100)),
+                (ms(350), ms(440)),
+                (ms(445), ms(450)),
+                (ms(650), ms(652)),
+            ],
+        };
+        let mut last_release = start.checked_sub(Duration::from_secs(1)).unwrap();
+        let mut presses = Vec::new();
+        for (from, _) in &pin.low {
+            // Like an interrupt, which is cached if it's still busy with the
+            // last one
+            sleep((start + *from).saturating_duration_since(clock.instant()));
+            let pressed_at = clock.instant();
+            if debounce(pressed_at, last_release) {
+                if let Some(press_kind) =
+                    wait_for_release(&pin, &clock, sleep, pressed_at, &mut last_release)
+                {
+                    presses.push((press_kind, pressed_at));
+                }
+            }
+        }
+
+        assert_eq!(
+            presses.iter().map(|(kind, _)| *kind).collect::<Vec<_>>(),
+            vec![PressKind::Short, PressKind::Short]
+        );
+        assert!(presses[1].1 - presses[0].1 < MULTI_PRESS_GAP);
+    }
+
+    #[test]
+    fn long_release_bounce() {
+        let ms = Duration::from_millis;
+        let clock = ManualClock::new(NaiveDateTime::default());
+        let sleep = |duration| clock.advance(chrono::Duration::from_std(duration).unwrap());
+        let start = clock.instant();
+        // One press, and then its release bouncing on and off for a few
+        // hundred ms
+        let pin = FakePin {
+            clock: clock.clone(),
+            start,
+            low: vec![
+                (ms(0), ms(120)),
+                (ms(250), ms(275)),
+                (ms(300), ms(330)),
+                (ms(400), ms(425)),
+                (ms(520), ms(540)),
+            ],
+        };
+        let mut last_release = start.checked_sub(Duration::from_secs(1)).unwrap();
+        let mut presses = Vec::new();
+        for (from, _) in &pin.low {
+            sleep((start + *from).saturating_duration_since(clock.instant()));
+            let pressed_at = clock.instant();
+            if debounce(pressed_at, last_release) {
+                if let Some(press_kind) =
+                    wait_for_release(&pin, &clock, sleep, pressed_at, &mut last_release)
+                {
+                    presses.push(press_kind);
+                }
+            }
         }
+
+        assert_eq!(presses, vec![PressKind::Short]);
     }
 }
@@ -1,4 +1,4 @@
-use chrono::{Datelike, Days, NaiveDate, NaiveDateTime, NaiveTime, Weekday};
+use chrono::{Datelike, Days, Duration, NaiveDate, NaiveDateTime, NaiveTime, Weekday};
 use std::ops::Add;
 
 #[derive(Clone)]
@@ -18,8 +18,13 @@ pub(crate) struct WeeklySchedule {
 pub(crate) enum Schedule {
     Daily(DailySchedule),
     Weekly(WeeklySchedule),
+    /// Just the once, after that the next trigger is `NEVER`.
+    Once(NaiveDateTime),
 }
 
+/// The next trigger of a schedule which won't trigger again.
+pub(crate) const NEVER: NaiveDateTime = NaiveDateTime::MAX;
+
 impl DailySchedule {
     pub(crate) fn new(schedule_time: NaiveTime, mut schedule_days: Vec<Weekday>) -> Self {
         // calculate_next_trigger needs schedule_days to be in order
@@ -50,13 +55,16 @@ impl DailySchedule {
         // All of this unwrapping looks awful, but I don't see a cleaner way to do it?
         let days_to_advance = days_from_monday(*next_weekday)
             - TryInto::<i32>::try_into(num_days_from_monday).unwrap();
-        let next_trigger_date = if days_to_advance >= 0 {
-            now.date()
-                .add(Days::new(days_to_advance.try_into().unwrap()))
-        } else {
-            now.date()
-                .add(Days::new((days_to_advance + 7).try_into().unwrap()))
-        };
+        // Only a same day trigger later on today counts, otherwise a
+        // schedule for just today's weekday is a week away.
+        let next_trigger_date =
+            if days_to_advance > 0 || (days_to_advance == 0 && now.time() < self.time) {
+                now.date()
+                    .add(Days::new(days_to_advance.try_into().unwrap()))
+            } else {
+                now.date()
+                    .add(Days::new((days_to_advance + 7).try_into().unwrap()))
+            };
 
         NaiveDateTime::new(next_trigger_date, self.time)
     }
@@ -84,7 +92,7 @@ impl WeeklySchedule {
         let schedule_period_in_days = 7 * self.repeat_every_n_weeks;
         let remainder: u64 = days_since_start % schedule_period_in_days;
 
-        let days_to_advance = if remainder == 0 && now.time() <= self.time {
+        let days_to_advance = if remainder == 0 && now.time() < self.time {
             0
         } else {
             schedule_period_in_days - remainder
@@ -103,6 +111,28 @@ impl Schedule {
         match self {
             Schedule::Daily(schedule) => schedule.calculate_next_trigger(now),
             Schedule::Weekly(schedule) => schedule.calculate_next_trigger(now),
+            Schedule::Once(at) if now < *at => *at,
+            Schedule::Once(_) => NEVER,
+        }
+    }
+
+    /// The same schedule, `by` later.  `None` if that would move a repeating
+    /// trigger onto a different day.
+    pub(crate) fn shifted(&self, by: Duration) -> Option<Schedule> {
+        let shift_time = |time: NaiveTime| {
+            let (shifted, wrapped) = time.overflowing_add_signed(by);
+            (wrapped == 0).then_some(shifted)
+        };
+        match self {
+            Schedule::Daily(schedule) => Some(Schedule::Daily(DailySchedule {
+                time: shift_time(schedule.time)?,
+                days: schedule.days.clone(),
+            })),
+            Schedule::Weekly(schedule) => Some(Schedule::Weekly(WeeklySchedule {
+                time: shift_time(schedule.time)?,
+                ..schedule.clone()
+            })),
+            Schedule::Once(at) => Some(Schedule::Once(*at + by)),
         }
     }
 }
@@ -231,6 +261,29 @@ mod tests {
         );
     }
 
+    #[test]
+    fn daily_only_today_after_schedule_time() {
+        let now = NaiveDateTime::from_str("2020-01-07T10:00:00").unwrap();
+        assert_eq!(now.weekday(), Weekday::Tue);
+
+        let schedule = Schedule::Daily(DailySchedule::new(
+            NaiveTime::from_str("10:00:00").unwrap(),
+            vec![Weekday::Tue],
+        ));
+        // Used to give the trigger that's just happened, which made it fire
+        // again on every tick
+        assert_eq!(
+            schedule.calculate_next_trigger(now),
+            NaiveDateTime::from_str("2020-01-14T10:00:00").unwrap()
+        );
+        // Earlier in the day it's still today's
+        let now = NaiveDateTime::from_str("2020-01-07T09:59:59").unwrap();
+        assert_eq!(
+            schedule.calculate_next_trigger(now),
+            NaiveDateTime::from_str("2020-01-07T10:00:00").unwrap()
+        );
+    }
+
     #[test]
     fn test_daily_schedule_days_out_of_order() {
         let now = NaiveDateTime::from_str("2024-06-22T08:26:15").unwrap();
@@ -337,4 +390,51 @@ mod tests {
             NaiveDateTime::from_str("2020-02-12T08:00:00").unwrap()
         );
     }
+
+    #[test]
+    fn weekly_exactly_at_schedule_time() {
+        let now = NaiveDateTime::from_str("2020-01-15T08:00:00").unwrap();
+        assert_eq!(now.weekday(), Weekday::Wed);
+
+        // Like the daily schedule, a trigger exactly at `now` has already
+        // happened, so the next one is a full period away
+        let schedule = Schedule::Weekly(WeeklySchedule::new(
+            NaiveDate::from_str("2020-01-01").unwrap(),
+            NaiveTime::from_str("08:00:00").unwrap(),
+            2,
+        ));
+        assert_eq!(
+            schedule.calculate_next_trigger(now),
+            NaiveDateTime::from_str("2020-01-29T08:00:00").unwrap()
+        );
+    }
+
+    #[test]
+    fn once() {
+        let at = NaiveDateTime::from_str("2020-01-01T08:30:00").unwrap();
+        let schedule = Schedule::Once(at);
+        assert_eq!(
+            schedule
+                .calculate_next_trigger(NaiveDateTime::from_str("2020-01-01T08:00:00").unwrap()),
+            at
+        );
+        assert_eq!(schedule.calculate_next_trigger(at), super::NEVER);
+    }
+
+    #[test]
+    fn shifted() {
+        let now = NaiveDateTime::from_str("2020-01-01T08:00:00").unwrap();
+        let schedule = Schedule::Daily(DailySchedule::new(
+            NaiveTime::from_str("06:00:00").unwrap(),
+            every_day(),
+        ));
+        assert_eq!(
+            schedule
+                .shifted(chrono::Duration::minutes(105))
+                .unwrap()
+                .calculate_next_trigger(now),
+            NaiveDateTime::from_str("2020-01-02T07:45:00").unwrap()
+        );
+        assert!(schedule.shifted(chrono::Duration::hours(-7)).is_none());
+    }
 }
@@ -1,6 +1,10 @@
-use crate::{activity::Activity, schedule::Schedule};
+use crate::{
+    activity::Activity,
+    condition::Condition,
+    schedule::{Schedule, NEVER},
+};
 use chrono::{Duration, NaiveDateTime};
-use log::info;
+use log::{info, warn};
 
 pub(crate) struct Scheduler {
     jobs: Vec<Job>,
@@ -8,73 +12,261 @@ pub(crate) struct Scheduler {
 
 pub(crate) struct ScheduledJobSpec {
     schedule: Schedule,
-    activity: Activity,
+    task: Task,
     grace_period: Duration,
+    condition: Option<Condition>,
+}
+
+/// What a job does when it fires.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum Task {
+    Trigger(Activity),
+    EndTimer(Activity),
+    SendDigest,
+}
+
+/// A job that's fired.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Trigger {
+    pub(crate) task: Task,
+    /// Still to be checked, it's only done if this says it's needed.
+    pub(crate) condition: Option<Condition>,
+}
+
+/// A change to a job's schedule from `fourbuttons suggest --apply`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) struct ScheduleAdjustment {
+    /// Trigger this much later (or, negative, earlier).
+    pub(crate) shift: Duration,
+    pub(crate) applied_at: NaiveDateTime,
 }
 
 struct Job {
     next_trigger: NaiveDateTime,
+    // A trigger which has already been done early, so shouldn't fire
+    skip: Option<NaiveDateTime>,
+    // The latest trigger handled, so that if the clock goes backwards it
+    // isn't fired again
+    last_fired: Option<NaiveDateTime>,
     schedule: Schedule,
-    activity: Activity,
+    task: Task,
     grace_period: Duration,
+    // Checked once it's fired, it's skipped if it's not needed
+    condition: Option<Condition>,
 }
 
 impl Scheduler {
     pub(crate) fn new(now: NaiveDateTime, job_specs: &[ScheduledJobSpec]) -> Self {
-        let jobs = job_specs
-            .iter()
-            .map(|spec| {
-                let next_trigger = spec.schedule.calculate_next_trigger(now);
-                info!(
-                    "Next trigger for {:?} will be at {}",
-                    spec.activity, next_trigger
-                );
-
-                Job {
-                    schedule: spec.schedule.clone(),
-                    activity: spec.activity,
-                    grace_period: spec.grace_period,
-                    next_trigger,
-                }
-            })
-            .collect();
+        let jobs = job_specs.iter().map(|spec| Job::new(now, spec)).collect();
         Self { jobs }
     }
 
-    pub(crate) fn tick(&mut self, now: NaiveDateTime) -> Vec<Activity> {
-        self.jobs
+    pub(crate) fn add_job(&mut self, now: NaiveDateTime, job_spec: &ScheduledJobSpec) {
+        self.jobs.push(Job::new(now, job_spec));
+    }
+
+    pub(crate) fn tick(&mut self, now: NaiveDateTime) -> Vec<Trigger> {
+        let triggers = self
+            .jobs
             .iter_mut()
             .filter_map(|job| job.tick(now))
-            .collect()
+            .collect();
+        // One-off jobs which have been and gone
+        self.jobs.retain(|job| job.next_trigger != NEVER);
+        triggers
+    }
+
+    /// Remove the one-off job triggering `activity` at `at`, it's no longer
+    /// wanted.
+    pub(crate) fn cancel(&mut self, activity: Activity, at: NaiveDateTime) {
+        self.jobs.retain(|job| {
+            !(job.task.activity() == Some(activity)
+                && matches!(job.schedule, Schedule::Once(once) if once == at))
+        });
+    }
+
+    /// Call when the wall clock has been stepped.
+    ///
+    /// If the clock went backwards, a job's next trigger may now be much
+    /// further away than its schedule says, so bring it back to the next
+    /// trigger after `now`.  If it went forwards nothing changes here: the
+    /// next `tick` fires the latest trigger if it's still within its grace
+    /// period and skips the rest, just like after a long pause.
+    pub(crate) fn clock_changed(&mut self, now: NaiveDateTime) {
+        for job in &mut self.jobs {
+            let next_trigger = job.schedule.calculate_next_trigger(now);
+            if next_trigger < job.next_trigger {
+                warn!(
+                    "Clock went backwards, rescheduling {:?} from {} to {}",
+                    job.task, job.next_trigger, next_trigger
+                );
+                job.next_trigger = next_trigger;
+            }
+        }
+    }
+
+    /// Don't fire `activity`'s trigger at `trigger`, it's already been done.
+    pub(crate) fn skip(&mut self, activity: Activity, trigger: NaiveDateTime) {
+        for job in self
+            .jobs
+            .iter_mut()
+            .filter(|job| job.task == Task::Trigger(activity))
+        {
+            info!("Will skip {:?} trigger at {}", activity, trigger);
+            job.skip = Some(trigger);
+        }
+    }
+
+    /// Undo `skip`.
+    pub(crate) fn unskip(&mut self, activity: Activity) {
+        for job in self
+            .jobs
+            .iter_mut()
+            .filter(|job| job.task == Task::Trigger(activity))
+        {
+            job.skip = None;
+        }
+    }
+
+    /// The earliest time at which `tick` could return a trigger, or `None`
+    /// if there are no jobs at all.
+    pub(crate) fn next_trigger(&self) -> Option<NaiveDateTime> {
+        self.jobs.iter().map(|job| job.next_trigger).min()
+    }
+}
+
+impl Task {
+    /// The activity it's for, if any.
+    pub(crate) fn activity(self) -> Option<Activity> {
+        match self {
+            Task::Trigger(activity) | Task::EndTimer(activity) => Some(activity),
+            Task::SendDigest => None,
+        }
     }
 }
 
 impl ScheduledJobSpec {
     pub(crate) fn new(schedule: Schedule, activity: Activity, grace_period: Duration) -> Self {
+        Self::for_task(schedule, Task::Trigger(activity), grace_period)
+    }
+
+    pub(crate) fn for_task(schedule: Schedule, task: Task, grace_period: Duration) -> Self {
         Self {
             schedule,
-            activity,
+            task,
             grace_period,
+            condition: None,
         }
     }
+
+    /// Only trigger when `condition` says it's needed.
+    pub(crate) fn with_condition(self, condition: Condition) -> Self {
+        Self {
+            condition: Some(condition),
+            ..self
+        }
+    }
+
+    pub(crate) fn task(&self) -> Task {
+        self.task
+    }
+
+    pub(crate) fn schedule(&self) -> &Schedule {
+        &self.schedule
+    }
+
+    /// The job with `adjustment` applied.
+    pub(crate) fn adjusted(self, adjustment: &ScheduleAdjustment) -> Self {
+        let schedule = self.schedule.shifted(adjustment.shift).unwrap_or_else(|| {
+            warn!(
+                "Not shifting {:?} by {}, it would move to another day",
+                self.task, adjustment.shift
+            );
+            self.schedule.clone()
+        });
+        Self { schedule, ..self }
+    }
 }
 
 impl Job {
-    fn tick(&mut self, now: NaiveDateTime) -> Option<Activity> {
+    fn new(now: NaiveDateTime, spec: &ScheduledJobSpec) -> Self {
+        let next_trigger = spec.schedule.calculate_next_trigger(now);
+        info!(
+            "Next trigger for {:?} will be at {}",
+            spec.task, next_trigger
+        );
+
+        Job {
+            schedule: spec.schedule.clone(),
+            task: spec.task,
+            grace_period: spec.grace_period,
+            condition: spec.condition.clone(),
+            next_trigger,
+            skip: None,
+            last_fired: None,
+        }
+    }
+
+    fn tick(&mut self, now: NaiveDateTime) -> Option<Trigger> {
         if now - self.next_trigger > self.grace_period {
             // It's been so long since the last tick that we don't want to
             // trigger.  Just reset and wait for the next one.
-            self.next_trigger = self.schedule.calculate_next_trigger(now);
+            warn!(
+                "Skipping {:?} trigger at {} as it's now {}, outside the grace period",
+                self.task, self.next_trigger, now
+            );
+            // But if the clock jumped forwards past several triggers, the
+            // latest of them might still be within its grace period.
+            let latest_trigger = self
+                .schedule
+                .calculate_next_trigger(now - self.grace_period);
+            if latest_trigger <= now {
+                info!(
+                    "Trigger for {:?} at {} is still within the grace period",
+                    self.task, latest_trigger
+                );
+                self.next_trigger = self.schedule.calculate_next_trigger(now);
 
-            None
+                self.fire(latest_trigger)
+            } else {
+                self.next_trigger = latest_trigger;
+
+                None
+            }
         } else if now >= self.next_trigger {
+            let trigger = self.next_trigger;
             self.next_trigger = self.schedule.calculate_next_trigger(now);
 
-            Some(self.activity)
+            self.fire(trigger)
         } else {
             None
         }
     }
+
+    fn fire(&mut self, trigger: NaiveDateTime) -> Option<Trigger> {
+        if self
+            .last_fired
+            .is_some_and(|last_fired| trigger <= last_fired)
+        {
+            info!(
+                "Not firing {:?} trigger at {} again, the clock must have gone backwards",
+                self.task, trigger
+            );
+            return None;
+        }
+        self.last_fired = Some(trigger);
+        if self.skip.take_if(|skip| *skip == trigger).is_some() {
+            info!(
+                "Skipping {:?} trigger at {} as it was done early",
+                self.task, trigger
+            );
+            return None;
+        }
+        Some(Trigger {
+            task: self.task,
+            condition: self.condition.clone(),
+        })
+    }
 }
 
 #[cfg(test)]
@@ -85,10 +277,18 @@ mod tests {
 
     use crate::{
         activity::Activity,
+        condition::Condition,
         schedule::{every_day, DailySchedule, Schedule},
     };
 
-    use super::{ScheduledJobSpec, Scheduler};
+    use super::{ScheduledJobSpec, Scheduler, Task, Trigger};
+
+    fn activities(triggers: Vec<Trigger>) -> Vec<Activity> {
+        triggers
+            .into_iter()
+            .filter_map(|trigger| trigger.task.activity())
+            .collect()
+    }
 
     #[test]
     fn regular_ticks() {
@@ -103,18 +303,18 @@ mod tests {
         );
         let mut sched = Scheduler::new(now, &[job_spec]);
 
-        assert_eq!(sched.tick(now), vec![]);
+        assert_eq!(activities(sched.tick(now)), vec![]);
         // Advance to scheduled time, see activity
         let now = NaiveDateTime::from_str("2020-01-01T08:00:00").unwrap();
-        assert_eq!(sched.tick(now), vec![Activity::I]);
+        assert_eq!(activities(sched.tick(now)), vec![Activity::I]);
 
         // Run again at scheduled time, don't see activity
         let now = NaiveDateTime::from_str("2020-01-01T08:00:00").unwrap();
-        assert_eq!(sched.tick(now), vec![]);
+        assert_eq!(activities(sched.tick(now)), vec![]);
 
         // Advance past scheduled time
         let now = NaiveDateTime::from_str("2020-01-01T08:00:01").unwrap();
-        assert_eq!(sched.tick(now), vec![]);
+        assert_eq!(activities(sched.tick(now)), vec![]);
     }
 
     #[test]
@@ -132,7 +332,7 @@ mod tests {
 
         // Just before end of grace period
         let now = NaiveDateTime::from_str("2020-01-01T09:00:00").unwrap();
-        assert_eq!(sched.tick(now), vec![Activity::I]);
+        assert_eq!(activities(sched.tick(now)), vec![Activity::I]);
     }
 
     #[test]
@@ -150,6 +350,245 @@ mod tests {
 
         // Just outside of grace period
         let now = NaiveDateTime::from_str("2020-01-01T09:00:01").unwrap();
-        assert_eq!(sched.tick(now), vec![]);
+        assert_eq!(activities(sched.tick(now)), vec![]);
+    }
+
+    #[test]
+    fn next_trigger_is_earliest_job() {
+        let now = NaiveDateTime::from_str("2020-01-01T07:59:00").unwrap();
+        let mut sched = Scheduler::new(now, &[]);
+        assert_eq!(sched.next_trigger(), None);
+
+        sched.add_job(
+            now,
+            &ScheduledJobSpec::new(
+                Schedule::Daily(DailySchedule::new(
+                    NaiveTime::from_str("10:00:00").unwrap(),
+                    every_day(),
+                )),
+                Activity::I,
+                Duration::hours(1),
+            ),
+        );
+        sched.add_job(
+            now,
+            &ScheduledJobSpec::new(
+                Schedule::Daily(DailySchedule::new(
+                    NaiveTime::from_str("08:00:00").unwrap(),
+                    every_day(),
+                )),
+                Activity::WaterPlants,
+                Duration::hours(1),
+            ),
+        );
+        assert_eq!(
+            sched.next_trigger(),
+            Some(NaiveDateTime::from_str("2020-01-01T08:00:00").unwrap())
+        );
+
+        // Once the earliest job fires the next trigger moves on to the other
+        let now = NaiveDateTime::from_str("2020-01-01T08:00:00").unwrap();
+        assert_eq!(activities(sched.tick(now)), vec![Activity::WaterPlants]);
+        assert_eq!(
+            sched.next_trigger(),
+            Some(NaiveDateTime::from_str("2020-01-01T10:00:00").unwrap())
+        );
+    }
+
+    #[test]
+    fn clock_changed_backwards() {
+        let now = NaiveDateTime::from_str("2020-01-03T07:59:00").unwrap();
+        let job_spec = ScheduledJobSpec::new(
+            Schedule::Daily(DailySchedule::new(
+                NaiveTime::from_str("08:00:00").unwrap(),
+                every_day(),
+            )),
+            Activity::I,
+            Duration::hours(1),
+        );
+        let mut sched = Scheduler::new(now, &[job_spec]);
+
+        // Clock was two days fast, so without rescheduling we'd wait two
+        // days for the next trigger
+        let now = NaiveDateTime::from_str("2020-01-01T07:59:00").unwrap();
+        sched.clock_changed(now);
+        assert_eq!(
+            sched.next_trigger(),
+            Some(NaiveDateTime::from_str("2020-01-01T08:00:00").unwrap())
+        );
+    }
+
+    #[test]
+    fn clock_changed_backwards_after_firing() {
+        let now = NaiveDateTime::from_str("2020-01-01T07:59:00").unwrap();
+        let job_spec = ScheduledJobSpec::new(
+            Schedule::Daily(DailySchedule::new(
+                NaiveTime::from_str("08:00:00").unwrap(),
+                every_day(),
+            )),
+            Activity::I,
+            Duration::hours(1),
+        );
+        let mut sched = Scheduler::new(now, &[job_spec]);
+        let now = NaiveDateTime::from_str("2020-01-01T08:05:00").unwrap();
+        assert_eq!(activities(sched.tick(now)), vec![Activity::I]);
+
+        // Clock was ten minutes fast, the 08:00 trigger comes round again
+        // but has already fired
+        let now = NaiveDateTime::from_str("2020-01-01T07:55:00").unwrap();
+        sched.clock_changed(now);
+        let now = NaiveDateTime::from_str("2020-01-01T08:00:00").unwrap();
+        assert_eq!(activities(sched.tick(now)), vec![]);
+        assert_eq!(
+            sched.next_trigger(),
+            Some(NaiveDateTime::from_str("2020-01-02T08:00:00").unwrap())
+        );
+        let now = NaiveDateTime::from_str("2020-01-02T08:00:00").unwrap();
+        assert_eq!(activities(sched.tick(now)), vec![Activity::I]);
+    }
+
+    #[test]
+    fn clock_changed_forwards() {
+        let now = NaiveDateTime::from_str("2020-01-01T07:59:00").unwrap();
+        let job_spec = ScheduledJobSpec::new(
+            Schedule::Daily(DailySchedule::new(
+                NaiveTime::from_str("08:00:00").unwrap(),
+                every_day(),
+            )),
+            Activity::I,
+            Duration::hours(1),
+        );
+        let mut sched = Scheduler::new(now, &[job_spec]);
+
+        // Forward jumps don't reschedule, tick decides based on the grace
+        // period
+        let now = NaiveDateTime::from_str("2020-01-01T08:30:00").unwrap();
+        sched.clock_changed(now);
+        assert_eq!(
+            sched.next_trigger(),
+            Some(NaiveDateTime::from_str("2020-01-01T08:00:00").unwrap())
+        );
+        assert_eq!(activities(sched.tick(now)), vec![Activity::I]);
+    }
+
+    #[test]
+    fn clock_changed_forwards_past_several_triggers() {
+        let now = NaiveDateTime::from_str("2020-01-01T07:59:00").unwrap();
+        let job_spec = ScheduledJobSpec::new(
+            Schedule::Daily(DailySchedule::new(
+                NaiveTime::from_str("08:00:00").unwrap(),
+                every_day(),
+            )),
+            Activity::I,
+            Duration::hours(1),
+        );
+        let mut sched = Scheduler::new(now, &[job_spec]);
+
+        // Several days pass, only the latest trigger is inside the grace
+        // period so it fires just once
+        let now = NaiveDateTime::from_str("2020-01-04T08:30:00").unwrap();
+        sched.clock_changed(now);
+        assert_eq!(activities(sched.tick(now)), vec![Activity::I]);
+        assert_eq!(
+            sched.next_trigger(),
+            Some(NaiveDateTime::from_str("2020-01-05T08:00:00").unwrap())
+        );
+    }
+
+    #[test]
+    fn skips_trigger_done_early() {
+        let now = NaiveDateTime::from_str("2020-01-01T07:00:00").unwrap();
+        let job_spec = ScheduledJobSpec::new(
+            Schedule::Daily(DailySchedule::new(
+                NaiveTime::from_str("08:00:00").unwrap(),
+                every_day(),
+            )),
+            Activity::I,
+            Duration::hours(1),
+        );
+        let mut sched = Scheduler::new(now, &[job_spec]);
+        sched.skip(
+            Activity::I,
+            NaiveDateTime::from_str("2020-01-01T08:00:00").unwrap(),
+        );
+
+        let now = NaiveDateTime::from_str("2020-01-01T08:00:00").unwrap();
+        assert_eq!(activities(sched.tick(now)), vec![]);
+        // Only that one
+        let now = NaiveDateTime::from_str("2020-01-02T08:00:00").unwrap();
+        assert_eq!(activities(sched.tick(now)), vec![Activity::I]);
+
+        // Changed our minds
+        sched.skip(
+            Activity::I,
+            NaiveDateTime::from_str("2020-01-03T08:00:00").unwrap(),
+        );
+        sched.unskip(Activity::I);
+        let now = NaiveDateTime::from_str("2020-01-03T08:00:00").unwrap();
+        assert_eq!(activities(sched.tick(now)), vec![Activity::I]);
+    }
+
+    #[test]
+    fn trigger_has_condition() {
+        let now = NaiveDateTime::from_str("2020-01-01T07:00:00").unwrap();
+        let condition = Condition::FileBelow {
+            path: "moisture".to_owned(),
+            below: 40.0,
+        };
+        let job_spec = ScheduledJobSpec::new(
+            Schedule::Daily(DailySchedule::new(
+                NaiveTime::from_str("08:00:00").unwrap(),
+                every_day(),
+            )),
+            Activity::WaterPlants,
+            Duration::hours(1),
+        )
+        .with_condition(condition.clone());
+        let mut sched = Scheduler::new(now, &[job_spec]);
+
+        // It's up to whoever gets the trigger to check it
+        let now = NaiveDateTime::from_str("2020-01-01T08:00:00").unwrap();
+        assert_eq!(
+            sched.tick(now),
+            vec![Trigger {
+                task: Task::Trigger(Activity::WaterPlants),
+                condition: Some(condition),
+            }]
+        );
+    }
+
+    #[test]
+    fn one_off_jobs() {
+        let now = NaiveDateTime::from_str("2020-01-01T07:00:00").unwrap();
+        let at = |s: &str| NaiveDateTime::from_str(s).unwrap();
+        let mut sched = Scheduler::new(now, &[]);
+        for time in ["2020-01-01T08:00:00", "2020-01-01T09:00:00"] {
+            sched.add_job(
+                now,
+                &ScheduledJobSpec::new(Schedule::Once(at(time)), Activity::Eat, Duration::hours(1)),
+            );
+        }
+        sched.cancel(Activity::Eat, at("2020-01-01T09:00:00"));
+
+        assert_eq!(sched.next_trigger(), Some(at("2020-01-01T08:00:00")));
+        assert_eq!(
+            activities(sched.tick(at("2020-01-01T08:00:00"))),
+            vec![Activity::Eat]
+        );
+        // Gone once it's fired
+        assert_eq!(sched.next_trigger(), None);
+        assert_eq!(activities(sched.tick(at("2020-01-01T09:00:00"))), vec![]);
+
+        // Too late, never fires
+        sched.add_job(
+            now,
+            &ScheduledJobSpec::new(
+                Schedule::Once(at("2020-01-01T07:30:00")),
+                Activity::Eat,
+                Duration::hours(1),
+            ),
+        );
+        assert_eq!(activities(sched.tick(at("2020-01-01T10:00:00"))), vec![]);
+        assert_eq!(sched.next_trigger(), None);
     }
 }
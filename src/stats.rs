@@ -0,0 +1,424 @@
+use std::collections::BTreeMap;
+
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime};
+
+use crate::event::{Event, EventKind};
+
+/// One triggering of an activity, pieced together from its events.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Occurrence {
+    pub(crate) triggered_at: NaiveDateTime,
+    pub(crate) acknowledged_at: Option<NaiveDateTime>,
+    pub(crate) acknowledged_by: Option<String>,
+    pub(crate) missed: bool,
+}
+
+impl Occurrence {
+    pub(crate) fn done(&self) -> bool {
+        self.acknowledged_at.is_some() && !self.missed
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct ActivityStats {
+    pub(crate) occurrences: usize,
+    /// How many of the latest occurrences in a row were done.
+    pub(crate) current_streak: usize,
+    pub(crate) longest_streak: usize,
+    /// Percentage of occurrences done within `within`, `None` if there
+    /// aren't any yet.
+    pub(crate) done_within_percent: Option<f64>,
+    pub(crate) within: Duration,
+    pub(crate) median_time_to_acknowledge: Option<Duration>,
+    /// Keyed by the Monday the week starts on.
+    pub(crate) missed_per_week: BTreeMap<NaiveDate, usize>,
+    /// Keyed by year and month.
+    pub(crate) missed_per_month: BTreeMap<(i32, u32), usize>,
+    /// How many occurrences each person did, where we know who.
+    pub(crate) done_by: BTreeMap<String, usize>,
+}
+
+/// How one activity's occurrences over a period turned out.
+#[derive(Debug, PartialEq, Eq, Default)]
+pub(crate) struct Summary {
+    pub(crate) triggered: usize,
+    pub(crate) on_time: usize,
+    pub(crate) late: usize,
+    pub(crate) missed: usize,
+}
+
+/// Group one activity's events, oldest first, into occurrences.
+pub(crate) fn occurrences(events: &[Event]) -> Vec<Occurrence> {
+    let mut occurrences: Vec<Occurrence> = Vec::new();
+    for event in events {
+        let Some(pending_since) = event.pending_since else {
+            // Pressed with nothing pending
+            continue;
+        };
+        match event.kind {
+            EventKind::Triggered => {
+                occurrences.push(Occurrence {
+                    triggered_at: pending_since,
+                    acknowledged_at: None,
+                    acknowledged_by: None,
+                    missed: false,
+                });
+                continue;
+            }
+            // The trigger never happens, so this is all there is
+            EventKind::CompletedEarly => {
+                occurrences.push(Occurrence {
+                    triggered_at: pending_since,
+                    acknowledged_at: Some(event.occurred_at),
+                    acknowledged_by: event.who.clone(),
+                    missed: false,
+                });
+                continue;
+            }
+            _ => {}
+        }
+        let Some(idx) = occurrences
+            .iter()
+            .rposition(|occurrence| occurrence.triggered_at == pending_since)
+        else {
+            continue;
+        };
+        let occurrence = &mut occurrences[idx];
+        match event.kind {
+            EventKind::Acknowledged if occurrence.acknowledged_at.is_none() => {
+                occurrence.acknowledged_at = Some(event.occurred_at);
+                occurrence.acknowledged_by.clone_from(&event.who);
+            }
+            // Nothing's been triggered yet, so there's nothing left
+            EventKind::Undone
+                if occurrence
+                    .acknowledged_at
+                    .is_some_and(|acknowledged_at| acknowledged_at < occurrence.triggered_at) =>
+            {
+                occurrences.remove(idx);
+            }
+            EventKind::Undone => {
+                occurrence.acknowledged_at = None;
+                occurrence.acknowledged_by = None;
+            }
+            EventKind::Missed => occurrence.missed = true,
+            _ => {}
+        }
+    }
+    occurrences
+}
+
+/// Statistics for one activity's events, oldest first.
+pub(crate) fn activity_stats(events: &[Event], within: Duration) -> ActivityStats {
+    let mut occurrences = occurrences(events);
+    // The latest occurrence may still be done in time, so leave it out
+    if occurrences
+        .last()
+        .is_some_and(|occurrence| occurrence.acknowledged_at.is_none() && !occurrence.missed)
+    {
+        occurrences.pop();
+    }
+
+    let mut current_streak = 0;
+    let mut longest_streak = 0;
+    let mut missed_per_week = BTreeMap::new();
+    let mut missed_per_month = BTreeMap::new();
+    let mut done_by = BTreeMap::new();
+    for occurrence in &occurrences {
+        if occurrence.done() {
+            if let Some(who) = &occurrence.acknowledged_by {
+                *done_by.entry(who.clone()).or_default() += 1;
+            }
+            current_streak += 1;
+            longest_streak = longest_streak.max(current_streak);
+        } else {
+            current_streak = 0;
+            let date = occurrence.triggered_at.date();
+            let week_start =
+                date - Duration::days(i64::from(date.weekday().num_days_from_monday()));
+            *missed_per_week.entry(week_start).or_default() += 1;
+            *missed_per_month
+                .entry((date.year(), date.month()))
+                .or_default() += 1;
+        }
+    }
+
+    let mut latencies: Vec<Duration> = occurrences
+        .iter()
+        .filter(|occurrence| occurrence.done())
+        .filter_map(|occurrence| {
+            occurrence
+                .acknowledged_at
+                // Done early counts as straight away
+                .map(|acknowledged_at| {
+                    (acknowledged_at - occurrence.triggered_at).max(Duration::zero())
+                })
+        })
+        .collect();
+    latencies.sort();
+
+    #[allow(clippy::cast_precision_loss)]
+    let done_within_percent = (!occurrences.is_empty()).then(|| {
+        let done_within = latencies
+            .iter()
+            .filter(|latency| **latency <= within)
+            .count();
+        100.0 * done_within as f64 / occurrences.len() as f64
+    });
+
+    ActivityStats {
+        occurrences: occurrences.len(),
+        current_streak,
+        longest_streak,
+        done_within_percent,
+        within,
+        median_time_to_acknowledge: median(&latencies),
+        missed_per_week,
+        missed_per_month,
+        done_by,
+    }
+}
+
+/// When one activity was last done, given its events oldest first.
+pub(crate) fn last_done(events: &[Event]) -> Option<NaiveDateTime> {
+    occurrences(events)
+        .iter()
+        .filter(|occurrence| occurrence.done())
+        .filter_map(|occurrence| occurrence.acknowledged_at)
+        .max()
+}
+
+/// Summarise one activity's events, oldest first, counting anything done
+/// within `on_time` of being triggered as on time.
+pub(crate) fn summarise(events: &[Event], on_time: Duration) -> Summary {
+    let occurrences = occurrences(events);
+    let mut summary = Summary {
+        triggered: occurrences.len(),
+        ..Summary::default()
+    };
+    for (idx, occurrence) in occurrences.iter().enumerate() {
+        match occurrence.acknowledged_at {
+            _ if occurrence.missed => summary.missed += 1,
+            Some(acknowledged_at) if acknowledged_at - occurrence.triggered_at <= on_time => {
+                summary.on_time += 1;
+            }
+            Some(_) => summary.late += 1,
+            // The latest might still be done, so doesn't count yet
+            None if idx == occurrences.len() - 1 => {}
+            None => summary.missed += 1,
+        }
+    }
+    summary
+}
+
+pub(crate) fn median(sorted: &[Duration]) -> Option<Duration> {
+    let mid = sorted.len() / 2;
+    if sorted.is_empty() {
+        None
+    } else if sorted.len().is_multiple_of(2) {
+        Some((sorted[mid - 1] + sorted[mid]) / 2)
+    } else {
+        Some(sorted[mid])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, str::FromStr};
+
+    use chrono::{Duration, NaiveDate, NaiveDateTime};
+
+    use crate::{
+        activity::Activity,
+        event::{Event, EventKind, EventSource},
+    };
+
+    use super::{activity_stats, last_done, summarise, Summary};
+
+    fn event(kind: EventKind, occurred_at: &str, pending_since: &str) -> Event {
+        Event {
+            activity: Activity::TakePills,
+            kind,
+            occurred_at: NaiveDateTime::from_str(occurred_at).unwrap(),
+            pending_since: Some(NaiveDateTime::from_str(pending_since).unwrap()),
+            source: EventSource::System,
+            who: None,
+            detail: None,
+        }
+    }
+
+    fn day(date: &str, acknowledged_at: Option<&str>, missed: bool) -> Vec<Event> {
+        day_by(date, acknowledged_at, missed, None)
+    }
+
+    fn day_by(
+        date: &str,
+        acknowledged_at: Option<&str>,
+        missed: bool,
+        who: Option<&str>,
+    ) -> Vec<Event> {
+        let triggered_at = format!("{date}T06:00:00");
+        let mut events = vec![event(EventKind::Triggered, &triggered_at, &triggered_at)];
+        if missed {
+            events.push(event(
+                EventKind::Missed,
+                &format!("{date}T18:00:00"),
+                &triggered_at,
+            ));
+        }
+        if let Some(time) = acknowledged_at {
+            events.push(Event {
+                who: who.map(str::to_owned),
+                ..event(
+                    EventKind::Acknowledged,
+                    &format!("{date}T{time}"),
+                    &triggered_at,
+                )
+            });
+        }
+        events
+    }
+
+    #[test]
+    fn stats() {
+        let events: Vec<Event> = [
+            day_by("2020-01-01", Some("06:30:00"), false, Some("Alex")),
+            day_by("2020-01-02", Some("07:00:00"), false, Some("Sam")),
+            day_by("2020-01-03", Some("09:00:00"), false, Some("Alex")),
+            // Missed, then acknowledged that it was missed
+            day("2020-01-04", Some("20:00:00"), true),
+            // Never acknowledged and replaced by the next one
+            day("2020-01-05", None, false),
+            day("2020-01-06", Some("06:10:00"), false),
+            day("2020-01-07", Some("06:20:00"), false),
+            // Still pending, so doesn't count yet
+            day("2020-01-08", None, false),
+        ]
+        .concat();
+
+        let stats = activity_stats(&events, Duration::hours(2));
+
+        assert_eq!(stats.occurrences, 7);
+        assert_eq!(stats.current_streak, 2);
+        assert_eq!(stats.longest_streak, 3);
+        assert_eq!(stats.done_within_percent, Some(400.0 / 7.0));
+        assert_eq!(
+            stats.median_time_to_acknowledge,
+            Some(Duration::minutes(30))
+        );
+        assert_eq!(
+            stats.missed_per_week,
+            BTreeMap::from([(NaiveDate::from_ymd_opt(2019, 12, 30).unwrap(), 2)])
+        );
+        assert_eq!(stats.missed_per_month, BTreeMap::from([((2020, 1), 2)]));
+        assert_eq!(
+            stats.done_by,
+            BTreeMap::from([("Alex".to_owned(), 2), ("Sam".to_owned(), 1)])
+        );
+    }
+
+    #[test]
+    fn summary() {
+        let events: Vec<Event> = [
+            day("2020-01-01", Some("06:30:00"), false),
+            day("2020-01-02", Some("09:00:00"), false),
+            day("2020-01-03", Some("20:00:00"), true),
+            day("2020-01-04", None, false),
+            day("2020-01-05", None, false),
+        ]
+        .concat();
+
+        assert_eq!(
+            summarise(&events, Duration::hours(2)),
+            Summary {
+                triggered: 5,
+                on_time: 1,
+                late: 1,
+                missed: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn undone_acknowledgement() {
+        let mut events = day("2020-01-01", Some("06:30:00"), false);
+        events.push(event(
+            EventKind::Undone,
+            "2020-01-01T06:30:02",
+            "2020-01-01T06:00:00",
+        ));
+        events.push(event(
+            EventKind::Acknowledged,
+            "2020-01-01T09:00:00",
+            "2020-01-01T06:00:00",
+        ));
+
+        assert_eq!(
+            summarise(&events, Duration::hours(2)),
+            Summary {
+                triggered: 1,
+                late: 1,
+                ..Summary::default()
+            }
+        );
+    }
+
+    #[test]
+    fn completed_early() {
+        let events = [
+            day("2020-01-01", Some("06:30:00"), false),
+            vec![
+                // Done the night before, so never triggered
+                event(
+                    EventKind::CompletedEarly,
+                    "2020-01-01T20:00:00",
+                    "2020-01-02T06:00:00",
+                ),
+                // Pressed by mistake and undone
+                event(
+                    EventKind::CompletedEarly,
+                    "2020-01-02T20:00:00",
+                    "2020-01-03T06:00:00",
+                ),
+                event(
+                    EventKind::Undone,
+                    "2020-01-02T20:00:02",
+                    "2020-01-03T06:00:00",
+                ),
+            ],
+            day("2020-01-03", Some("07:00:00"), false),
+        ]
+        .concat();
+
+        let stats = activity_stats(&events, Duration::hours(2));
+        assert_eq!(stats.occurrences, 3);
+        assert_eq!(stats.current_streak, 3);
+        assert_eq!(
+            stats.median_time_to_acknowledge,
+            Some(Duration::minutes(30))
+        );
+        assert_eq!(
+            summarise(&events, Duration::hours(2)),
+            Summary {
+                triggered: 3,
+                on_time: 3,
+                ..Summary::default()
+            }
+        );
+        assert_eq!(
+            last_done(&events),
+            Some(NaiveDateTime::from_str("2020-01-03T07:00:00").unwrap())
+        );
+    }
+
+    #[test]
+    fn no_history() {
+        let stats = activity_stats(&[], Duration::hours(2));
+
+        assert_eq!(stats.occurrences, 0);
+        assert_eq!(stats.current_streak, 0);
+        assert_eq!(stats.done_within_percent, None);
+        assert_eq!(stats.median_time_to_acknowledge, None);
+        assert_eq!(last_done(&[]), None);
+    }
+}
@@ -0,0 +1,157 @@
+use chrono::Duration;
+
+use crate::{
+    event::Event,
+    stats::{median, occurrences},
+};
+
+// Too few to tell a habit from a one-off.
+const MIN_OCCURRENCES: usize = 7;
+// Not worth moving the trigger for less than this.
+const MIN_SHIFT: Duration = Duration::minutes(30);
+// Only move the trigger if the middle half of the latencies are this close
+// together, otherwise there's no one better time.
+const MAX_SPREAD: Duration = Duration::hours(1);
+// Suggested triggers are on the quarter hour.
+const SHIFT_STEP_MINUTES: i64 = 15;
+
+/// How a job could change to fit when it actually gets done.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Suggestion {
+    /// How many occurrences it's based on.
+    pub(crate) occurrences: usize,
+    /// From being triggered to being done.
+    pub(crate) median_latency: Duration,
+    /// Trigger this much later (or, negative, earlier).  `None` if it's
+    /// fine where it is, or when it's done varies too much to say.
+    pub(crate) shift: Option<Duration>,
+}
+
+/// Suggest changes to a job from one activity's events, oldest first.
+/// `None` if there aren't enough done occurrences to go on.
+pub(crate) fn suggest(events: &[Event]) -> Option<Suggestion> {
+    let mut latencies: Vec<Duration> = occurrences(events)
+        .iter()
+        .filter(|occurrence| occurrence.done())
+        .filter_map(|occurrence| {
+            occurrence
+                .acknowledged_at
+                .map(|acknowledged_at| acknowledged_at - occurrence.triggered_at)
+        })
+        .collect();
+    if latencies.len() < MIN_OCCURRENCES {
+        return None;
+    }
+    latencies.sort();
+
+    let median_latency = median(&latencies)?;
+    let spread = percentile(&latencies, 75) - percentile(&latencies, 25);
+    let shift = (median_latency.abs() >= MIN_SHIFT && spread <= MAX_SPREAD)
+        .then(|| round_to_step(median_latency))
+        .filter(|shift| !shift.is_zero());
+
+    Some(Suggestion {
+        occurrences: latencies.len(),
+        median_latency,
+        shift,
+    })
+}
+
+fn percentile(sorted: &[Duration], percent: usize) -> Duration {
+    sorted[(sorted.len() - 1) * percent / 100]
+}
+
+fn round_to_step(duration: Duration) -> Duration {
+    let minutes = duration.num_minutes() + SHIFT_STEP_MINUTES / 2;
+    Duration::minutes(minutes.div_euclid(SHIFT_STEP_MINUTES) * SHIFT_STEP_MINUTES)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use chrono::{Duration, NaiveDateTime};
+
+    use crate::{
+        activity::Activity,
+        event::{Event, EventKind, EventSource},
+    };
+
+    use super::{suggest, Suggestion};
+
+    fn event(kind: EventKind, occurred_at: NaiveDateTime, pending_since: NaiveDateTime) -> Event {
+        Event {
+            activity: Activity::TakePills,
+            kind,
+            occurred_at,
+            pending_since: Some(pending_since),
+            source: EventSource::System,
+            who: None,
+            detail: None,
+        }
+    }
+
+    // Triggered at 06:00 each day, and done however many minutes later.
+    fn days(latencies: &[i64]) -> Vec<Event> {
+        let start = NaiveDateTime::from_str("2020-01-01T06:00:00").unwrap();
+        latencies
+            .iter()
+            .enumerate()
+            .flat_map(|(day, latency)| {
+                let triggered_at = start + Duration::days(i64::try_from(day).unwrap());
+                [
+                    event(EventKind::Triggered, triggered_at, triggered_at),
+                    event(
+                        EventKind::Acknowledged,
+                        triggered_at + Duration::minutes(*latency),
+                        triggered_at,
+                    ),
+                ]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn consistently_late() {
+        let events = days(&[95, 100, 110, 90, 105, 100, 300]);
+        assert_eq!(
+            suggest(&events),
+            Some(Suggestion {
+                occurrences: 7,
+                median_latency: Duration::minutes(100),
+                shift: Some(Duration::minutes(105)),
+            })
+        );
+    }
+
+    #[test]
+    fn done_early() {
+        let events = days(&[-40, -45, -50, -40, -35, -45, -40]);
+        assert_eq!(
+            suggest(&events),
+            Some(Suggestion {
+                occurrences: 7,
+                median_latency: Duration::minutes(-40),
+                shift: Some(Duration::minutes(-45)),
+            })
+        );
+    }
+
+    #[test]
+    fn too_spread_out_to_move() {
+        let events = days(&[10, 200, 60, 300, 30, 240, 120]);
+        assert_eq!(
+            suggest(&events),
+            Some(Suggestion {
+                occurrences: 7,
+                median_latency: Duration::minutes(120),
+                shift: None,
+            })
+        );
+    }
+
+    #[test]
+    fn not_enough_history() {
+        assert_eq!(suggest(&days(&[100, 100, 100])), None);
+    }
+}
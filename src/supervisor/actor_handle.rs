@@ -1,6 +1,6 @@
 use anyhow::{Context, Result};
 use std::{
-    sync::mpsc::{self, Sender},
+    sync::mpsc::{self, Receiver, Sender},
     thread::{self, JoinHandle},
 };
 
@@ -22,18 +22,30 @@ where
         U: Actor<T> + Send + 'static,
     {
         let (sender, receiver) = mpsc::channel::<T>();
-        let join_handle = thread::Builder::new()
+        let join_handle = Self::spawn(actor, name, runner, receiver)?;
+
+        Ok(Self {
+            sender,
+            join_handle,
+        })
+    }
+
+    pub(super) fn spawn<U>(
+        actor: U,
+        name: String,
+        runner: Runner,
+        receiver: Receiver<T>,
+    ) -> Result<JoinHandle<Result<()>>>
+    where
+        U: Actor<T> + Send + 'static,
+    {
+        thread::Builder::new()
             .name(name)
             .spawn(move || {
                 runner.run_actor(&receiver, actor)?;
                 // Runner should be dropped here in order to notify supervisor
                 Ok(())
             })
-            .context("Failed to start actor thread")?;
-
-        Ok(Self {
-            sender,
-            join_handle,
-        })
+            .context("Failed to start actor thread")
     }
 }
@@ -1,6 +1,9 @@
 use anyhow::{Context, Result};
 use log::{debug, info};
-use std::sync::{mpsc::Receiver, Arc, Condvar, Mutex};
+use std::sync::{
+    mpsc::{Receiver, RecvTimeoutError},
+    Arc, Condvar, Mutex,
+};
 
 use crate::actor::{actor::Actor, message_source::MessageSource};
 
@@ -26,17 +29,32 @@ impl Runner {
     {
         debug!("Running Actor: {}", self.actor_id);
         actor.startup().context("Error in actor startup")?;
-        while let Ok(msg) = receiver.recv() {
-            let should_terminate = actor
-                .handle_message(msg)
-                .context("Error handling actor message")?;
+        loop {
+            let msg = match actor.timeout() {
+                Some(timeout) => match receiver.recv_timeout(timeout) {
+                    Ok(msg) => Some(msg),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => return Ok(()),
+                },
+                None => match receiver.recv() {
+                    Ok(msg) => Some(msg),
+                    Err(_) => return Ok(()),
+                },
+            };
+
+            let should_terminate = match msg {
+                Some(msg) => actor
+                    .handle_message(msg)
+                    .context("Error handling actor message")?,
+                None => actor
+                    .handle_timeout()
+                    .context("Error handling actor timeout")?,
+            };
 
             if should_terminate {
                 return Ok(());
             }
         }
-
-        Ok(())
     }
 
     pub(crate) fn run_message_source<T>(&self, mut message_source: T) -> Result<()>
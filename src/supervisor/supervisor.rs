@@ -1,6 +1,9 @@
 use std::{
     collections::HashMap,
-    sync::{mpsc::Sender, Arc, Condvar, Mutex},
+    sync::{
+        mpsc::{Receiver, Sender},
+        Arc, Condvar, Mutex,
+    },
     thread::JoinHandle,
 };
 
@@ -43,6 +46,29 @@ impl Supervisor {
         Ok(handle.sender)
     }
 
+    /// Like `start`, but for an actor which actors started before it need
+    /// to send messages to.  They get the sender half of `receiver`'s
+    /// channel.
+    pub(crate) fn start_with_receiver<T, U>(
+        &mut self,
+        actor: T,
+        name: String,
+        receiver: Receiver<U>,
+    ) -> Result<()>
+    where
+        T: Actor<U> + Send + 'static,
+        U: Send + Sync + 'static,
+    {
+        let actor_id = self.get_next_actor_id();
+        let runner = Runner::new(self.completed_actors.clone(), actor_id);
+
+        let join_handle = ActorHandle::spawn(actor, name, runner, receiver)?;
+
+        self.handles.insert(actor_id, join_handle);
+
+        Ok(())
+    }
+
     pub(crate) fn start_message_source<T>(&mut self, source_actor: T, name: String) -> Result<()>
     where
         T: MessageSource + Send + 'static,